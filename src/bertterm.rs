@@ -1,7 +1,14 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::iter;
+use std::mem;
+
 use num_bigint::BigInt;
 
 /// A enum representing a BertTerm
-#[derive(Debug, PartialEq)]
+///
+/// Dropping a term recurses into its elements: drop the terms that
+/// may be deeply nested with `drop_deep`.
 pub enum BertTerm {
     /// The empty list
     Nil,
@@ -24,29 +31,107 @@ pub enum BertTerm {
     /// An array of bytes
     Binary(Vec<u8>),
 
+    /// An array of bytes where only the `bits` most significant
+    /// bits of the last byte are part of the bitstring
+    BitBinary(Vec<u8>, u8),
+
     /// A container for a fixed number of elements
     Tuple(Vec<BertTerm>),
 
     /// A container for an arbitrary number of elements
     List(Vec<BertTerm>),
 
+    /// A list whose tail is not the empty list, e.g. `[a, b | c]`
+    ImproperList(Vec<BertTerm>, Box<BertTerm>),
+
     /// A container for key-to-value pairs
     Map(Vec<BertTerm>, Vec<BertTerm>),
+
+    /// A process identifier
+    Pid {
+        node: String,
+        id: u32,
+        serial: u32,
+        creation: u32,
+    },
+
+    /// A port identifier
+    Port {
+        node: String,
+        id: u64,
+        creation: u32,
+    },
+
+    /// A reference; `ids` are stored in the order they are encoded
+    Reference {
+        node: String,
+        creation: u32,
+        ids: Vec<u32>,
+    },
+
+    /// A local fun
+    Fun(Box<BertFun>),
+
+    /// An external function reference (`fun M:F/A`)
+    Export {
+        module: String,
+        function: String,
+        arity: u8,
+    },
+
+    /// A term encoded with `term_to_binary(T, [local])`, and the
+    /// hash that identifies the node that encoded it
+    Local(u32, Box<BertTerm>),
+
+    /// Where the input ended, in a term parsed leniently (see
+    /// `BertParser::set_lenient`); the rest of the term is missing
+    Truncated,
+}
+
+/// The fields of a local fun.
+///
+/// Funs encoded with the legacy FUN_EXT tag have no arity and
+/// no MD5 `uniq`; their `arity` is `None` and `uniq` is all zeros.
+#[derive(Debug, PartialEq)]
+pub struct BertFun {
+    pub module: String,
+    pub arity: Option<u8>,
+    pub uniq: [u8; 16],
+    pub index: u32,
+    pub old_index: i32,
+    pub old_uniq: i32,
+    pub pid: BertTerm,
+    pub free_vars: Vec<BertTerm>,
 }
 
 impl BertTerm {
     /// Lists, tuples, and maps are not basic terms;
     /// everything else is.
     pub fn is_basic(&self) -> bool {
-        match *self {
+        let mut term = self;
+        while let BertTerm::Local(_, ref inner) = *term {
+            term = inner;
+        }
+        match *term {
             BertTerm::Int(_)
             | BertTerm::BigInt(_)
             | BertTerm::Float(_)
             | BertTerm::Atom(_)
             | BertTerm::String(_)
             | BertTerm::Binary(_)
-            | BertTerm::Nil => true,
-            BertTerm::List(_) | BertTerm::Tuple(_) | BertTerm::Map(_, _) => false,
+            | BertTerm::BitBinary(_, _)
+            | BertTerm::Nil
+            | BertTerm::Pid { .. }
+            | BertTerm::Port { .. }
+            | BertTerm::Reference { .. }
+            | BertTerm::Fun(_)
+            | BertTerm::Export { .. }
+            | BertTerm::Truncated => true,
+            BertTerm::List(_)
+            | BertTerm::ImproperList(_, _)
+            | BertTerm::Tuple(_)
+            | BertTerm::Map(_, _) => false,
+            BertTerm::Local(_, _) => unreachable!(),
         }
     }
 
@@ -54,12 +139,10 @@ impl BertTerm {
     /// [ {atom|string|binary, term}* ]
     pub fn is_proplist(&self) -> bool {
         fn is_proplist_tuple(elems: &[BertTerm]) -> bool {
-            match elems {
-                [BertTerm::Atom(_), _] => true,
-                [BertTerm::String(_), _] => true,
-                [BertTerm::Binary(_), _] => true,
-                _ => false,
-            }
+            matches!(
+                elems,
+                [BertTerm::Atom(_), _] | [BertTerm::String(_), _] | [BertTerm::Binary(_), _]
+            )
         }
 
         fn is_proplist_entry(t: &BertTerm) -> bool {
@@ -70,7 +153,988 @@ impl BertTerm {
         }
 
         match *self {
-            BertTerm::List(ref elems) => elems.iter().all(|e| is_proplist_entry(e)),
+            BertTerm::List(ref elems) => elems.iter().all(is_proplist_entry),
+            _ => false,
+        }
+    }
+}
+
+/// A term whose atoms, strings and binaries borrow from the
+/// buffer it was parsed from.
+///
+/// Data that cannot be borrowed (e.g., Latin-1 atoms that must
+/// be converted to UTF-8, or terms inflated from a compressed
+/// term) is owned.
+pub enum BertTermRef<'a> {
+    Nil,
+    Int(i32),
+    BigInt(BigInt),
+    Float(f64),
+    Atom(Cow<'a, str>),
+    String(Cow<'a, [u8]>),
+    Binary(Cow<'a, [u8]>),
+    BitBinary(Cow<'a, [u8]>, u8),
+    Tuple(Vec<BertTermRef<'a>>),
+    List(Vec<BertTermRef<'a>>),
+    ImproperList(Vec<BertTermRef<'a>>, Box<BertTermRef<'a>>),
+    Map(Vec<BertTermRef<'a>>, Vec<BertTermRef<'a>>),
+    Pid {
+        node: Cow<'a, str>,
+        id: u32,
+        serial: u32,
+        creation: u32,
+    },
+    Port {
+        node: Cow<'a, str>,
+        id: u64,
+        creation: u32,
+    },
+    Reference {
+        node: Cow<'a, str>,
+        creation: u32,
+        ids: Vec<u32>,
+    },
+    Fun(Box<BertFunRef<'a>>),
+    Export {
+        module: Cow<'a, str>,
+        function: Cow<'a, str>,
+        arity: u8,
+    },
+    Local(u32, Box<BertTermRef<'a>>),
+    Truncated,
+}
+
+/// The borrowed counterpart of `BertFun`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BertFunRef<'a> {
+    pub module: Cow<'a, str>,
+    pub arity: Option<u8>,
+    pub uniq: [u8; 16],
+    pub index: u32,
+    pub old_index: i32,
+    pub old_uniq: i32,
+    pub pid: BertTermRef<'a>,
+    pub free_vars: Vec<BertTermRef<'a>>,
+}
+
+// Terms are converted, cloned, compared, formatted and dropped (with
+// `drop_deep`, for a `BertTerm`) with an explicit stack rather than
+// recursively, so that their depth is
+// only limited by memory.
+//
+// `convert` splits every term into a leaf, which is converted
+// directly, or the shape of its container and its elements; once
+// its elements are converted, `build` puts them back into their
+// container.
+enum Shape<F> {
+    Tuple,
+    List,
+    // The tail is the last element.
+    ImproperList,
+    // The keys, then the values.
+    Map,
+    // The pid, then the free variables; `F` is the fun without them.
+    Fun(F),
+    Local(u32),
+}
+
+enum Step<S, T, F> {
+    Leaf(T),
+    Node(Shape<F>, Vec<S>),
+}
+
+enum Work<S, F> {
+    Visit(S),
+    Build(Shape<F>, usize),
+}
+
+fn convert<S, T, F>(
+    term: S,
+    step: impl Fn(S) -> Step<S, T, F>,
+    build: impl Fn(Shape<F>, Vec<T>) -> T,
+) -> T {
+    let mut work = vec![Work::Visit(term)];
+    let mut done: Vec<T> = Vec::new();
+    while let Some(item) = work.pop() {
+        match item {
+            Work::Visit(term) => match step(term) {
+                Step::Leaf(t) => done.push(t),
+                Step::Node(shape, elems) => {
+                    work.push(Work::Build(shape, elems.len()));
+                    work.extend(elems.into_iter().rev().map(Work::Visit));
+                }
+            },
+            Work::Build(shape, n) => {
+                let elems = done.split_off(done.len() - n);
+                done.push(build(shape, elems));
+            }
+        }
+    }
+    return done.pop().unwrap();
+}
+
+fn build_term(shape: Shape<Box<BertFun>>, mut elems: Vec<BertTerm>) -> BertTerm {
+    match shape {
+        Shape::Tuple => BertTerm::Tuple(elems),
+        Shape::List => BertTerm::List(elems),
+        Shape::ImproperList => {
+            let tail = elems.pop().unwrap();
+            BertTerm::ImproperList(elems, Box::new(tail))
+        }
+        Shape::Map => {
+            let vals = elems.split_off(elems.len() / 2);
+            BertTerm::Map(elems, vals)
+        }
+        Shape::Fun(mut fun) => {
+            fun.free_vars = elems.split_off(1);
+            fun.pid = elems.pop().unwrap();
+            BertTerm::Fun(fun)
+        }
+        Shape::Local(hash) => BertTerm::Local(hash, Box::new(elems.pop().unwrap())),
+    }
+}
+
+fn build_term_ref<'a>(
+    shape: Shape<Box<BertFunRef<'a>>>,
+    mut elems: Vec<BertTermRef<'a>>,
+) -> BertTermRef<'a> {
+    match shape {
+        Shape::Tuple => BertTermRef::Tuple(elems),
+        Shape::List => BertTermRef::List(elems),
+        Shape::ImproperList => {
+            let tail = elems.pop().unwrap();
+            BertTermRef::ImproperList(elems, Box::new(tail))
+        }
+        Shape::Map => {
+            let vals = elems.split_off(elems.len() / 2);
+            BertTermRef::Map(elems, vals)
+        }
+        Shape::Fun(mut fun) => {
+            fun.free_vars = elems.split_off(1);
+            fun.pid = elems.pop().unwrap();
+            BertTermRef::Fun(fun)
+        }
+        Shape::Local(hash) => BertTermRef::Local(hash, Box::new(elems.pop().unwrap())),
+    }
+}
+
+impl BertTerm {
+    /// Returns a view of the term that borrows its atoms,
+    /// strings and binaries; the printers work on this view.
+    pub fn as_term_ref(&self) -> BertTermRef<'_> {
+        return convert(self, BertTerm::ref_step, build_term_ref);
+    }
+
+    fn ref_step(&self) -> Step<&BertTerm, BertTermRef<'_>, Box<BertFunRef<'_>>> {
+        let leaf = match *self {
+            BertTerm::Nil => BertTermRef::Nil,
+            BertTerm::Truncated => BertTermRef::Truncated,
+            BertTerm::Int(n) => BertTermRef::Int(n),
+            BertTerm::BigInt(ref n) => BertTermRef::BigInt(n.clone()),
+            BertTerm::Float(x) => BertTermRef::Float(x),
+            BertTerm::Atom(ref s) => BertTermRef::Atom(Cow::Borrowed(s)),
+            BertTerm::String(ref bytes) => BertTermRef::String(Cow::Borrowed(bytes)),
+            BertTerm::Binary(ref bytes) => BertTermRef::Binary(Cow::Borrowed(bytes)),
+            BertTerm::BitBinary(ref bytes, bits) => {
+                BertTermRef::BitBinary(Cow::Borrowed(bytes), bits)
+            }
+            BertTerm::Pid {
+                ref node,
+                id,
+                serial,
+                creation,
+            } => BertTermRef::Pid {
+                node: Cow::Borrowed(node),
+                id,
+                serial,
+                creation,
+            },
+            BertTerm::Port {
+                ref node,
+                id,
+                creation,
+            } => BertTermRef::Port {
+                node: Cow::Borrowed(node),
+                id,
+                creation,
+            },
+            BertTerm::Reference {
+                ref node,
+                creation,
+                ref ids,
+            } => BertTermRef::Reference {
+                node: Cow::Borrowed(node),
+                creation,
+                ids: ids.clone(),
+            },
+            BertTerm::Export {
+                ref module,
+                ref function,
+                arity,
+            } => BertTermRef::Export {
+                module: Cow::Borrowed(module),
+                function: Cow::Borrowed(function),
+                arity,
+            },
+            BertTerm::Tuple(ref terms) => return Step::Node(Shape::Tuple, terms.iter().collect()),
+            BertTerm::List(ref terms) => return Step::Node(Shape::List, terms.iter().collect()),
+            BertTerm::ImproperList(ref terms, ref tail) => {
+                let elems = terms.iter().chain(iter::once(&**tail)).collect();
+                return Step::Node(Shape::ImproperList, elems);
+            }
+            BertTerm::Map(ref keys, ref vals) => {
+                return Step::Node(Shape::Map, keys.iter().chain(vals).collect());
+            }
+            BertTerm::Fun(ref fun) => {
+                let shape = Shape::Fun(Box::new(BertFunRef {
+                    module: Cow::Borrowed(&fun.module),
+                    arity: fun.arity,
+                    uniq: fun.uniq,
+                    index: fun.index,
+                    old_index: fun.old_index,
+                    old_uniq: fun.old_uniq,
+                    pid: BertTermRef::Nil,
+                    free_vars: Vec::new(),
+                }));
+                let elems = iter::once(&fun.pid).chain(&fun.free_vars).collect();
+                return Step::Node(shape, elems);
+            }
+            BertTerm::Local(hash, ref term) => {
+                return Step::Node(Shape::Local(hash), vec![&**term]);
+            }
+        };
+        return Step::Leaf(leaf);
+    }
+
+    /// Drops the term without recursion, however deeply it is
+    /// nested.
+    pub fn drop_deep(mut self) {
+        let mut stack = self.take_elements();
+        while let Some(mut term) = stack.pop() {
+            stack.append(&mut term.take_elements());
+        }
+    }
+
+    // Compares two terms, but not their elements.
+    fn shallow_eq(&self, other: &BertTerm) -> bool {
+        use BertTerm::*;
+        match (self, other) {
+            (Nil, Nil) | (Truncated, Truncated) => true,
+            (Int(a), Int(b)) => a == b,
+            (BigInt(a), BigInt(b)) => a == b,
+            (Float(a), Float(b)) => a == b,
+            (Atom(a), Atom(b)) => a == b,
+            (String(a), String(b)) | (Binary(a), Binary(b)) => a == b,
+            (BitBinary(a, m), BitBinary(b, n)) => a == b && m == n,
+            (Tuple(a), Tuple(b))
+            | (List(a), List(b))
+            | (ImproperList(a, _), ImproperList(b, _)) => a.len() == b.len(),
+            (Map(k1, v1), Map(k2, v2)) => k1.len() == k2.len() && v1.len() == v2.len(),
+            (
+                Pid {
+                    node: n1,
+                    id: i1,
+                    serial: s1,
+                    creation: c1,
+                },
+                Pid {
+                    node: n2,
+                    id: i2,
+                    serial: s2,
+                    creation: c2,
+                },
+            ) => n1 == n2 && i1 == i2 && s1 == s2 && c1 == c2,
+            (
+                Port {
+                    node: n1,
+                    id: i1,
+                    creation: c1,
+                },
+                Port {
+                    node: n2,
+                    id: i2,
+                    creation: c2,
+                },
+            ) => n1 == n2 && i1 == i2 && c1 == c2,
+            (
+                Reference {
+                    node: n1,
+                    creation: c1,
+                    ids: i1,
+                },
+                Reference {
+                    node: n2,
+                    creation: c2,
+                    ids: i2,
+                },
+            ) => n1 == n2 && c1 == c2 && i1 == i2,
+            (Fun(a), Fun(b)) => {
+                a.module == b.module
+                    && a.arity == b.arity
+                    && a.uniq == b.uniq
+                    && a.index == b.index
+                    && a.old_index == b.old_index
+                    && a.old_uniq == b.old_uniq
+                    && a.free_vars.len() == b.free_vars.len()
+            }
+            (
+                Export {
+                    module: m1,
+                    function: f1,
+                    arity: a1,
+                },
+                Export {
+                    module: m2,
+                    function: f2,
+                    arity: a2,
+                },
+            ) => m1 == m2 && f1 == f2 && a1 == a2,
+            (Local(a, _), Local(b, _)) => a == b,
+            _ => false,
+        }
+    }
+
+    // The elements of a container, in the order of `take_elements`.
+    fn elements(&self) -> Vec<&BertTerm> {
+        match *self {
+            BertTerm::Tuple(ref terms) | BertTerm::List(ref terms) => terms.iter().collect(),
+            BertTerm::ImproperList(ref terms, ref tail) => {
+                terms.iter().chain(iter::once(&**tail)).collect()
+            }
+            BertTerm::Map(ref keys, ref vals) => keys.iter().chain(vals).collect(),
+            BertTerm::Fun(ref fun) => iter::once(&fun.pid).chain(&fun.free_vars).collect(),
+            BertTerm::Local(_, ref term) => vec![&**term],
+            _ => Vec::new(),
+        }
+    }
+
+    // Moves the elements out of a container, leaving it empty.
+    fn take_elements(&mut self) -> Vec<BertTerm> {
+        match *self {
+            BertTerm::Tuple(ref mut terms) | BertTerm::List(ref mut terms) => mem::take(terms),
+            BertTerm::ImproperList(ref mut terms, ref mut tail) => {
+                let mut elems = mem::take(terms);
+                elems.push(mem::replace(&mut **tail, BertTerm::Nil));
+                elems
+            }
+            BertTerm::Map(ref mut keys, ref mut vals) => {
+                let mut elems = mem::take(keys);
+                elems.append(vals);
+                elems
+            }
+            BertTerm::Fun(ref mut fun) => {
+                let mut elems = vec![mem::replace(&mut fun.pid, BertTerm::Nil)];
+                elems.append(&mut fun.free_vars);
+                elems
+            }
+            BertTerm::Local(_, ref mut term) => vec![mem::replace(&mut **term, BertTerm::Nil)],
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl PartialEq for BertTerm {
+    fn eq(&self, other: &BertTerm) -> bool {
+        let mut stack = vec![(self, other)];
+        while let Some((a, b)) = stack.pop() {
+            if !a.shallow_eq(b) {
+                return false;
+            }
+            stack.extend(a.elements().into_iter().zip(b.elements()));
+        }
+        return true;
+    }
+}
+
+impl fmt::Debug for BertTerm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        debug(&self.as_term_ref(), "BertFun", f)
+    }
+}
+
+impl<'a> Clone for BertTermRef<'a> {
+    fn clone(&self) -> BertTermRef<'a> {
+        return convert(self, BertTermRef::clone_step, build_term_ref);
+    }
+}
+
+impl<'a> Drop for BertTermRef<'a> {
+    fn drop(&mut self) {
+        let mut stack = self.take_elements();
+        while let Some(mut term) = stack.pop() {
+            stack.append(&mut term.take_elements());
+        }
+    }
+}
+
+impl PartialEq for BertTermRef<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        let mut stack = vec![(self, other)];
+        while let Some((a, b)) = stack.pop() {
+            if !a.shallow_eq(b) {
+                return false;
+            }
+            stack.extend(a.elements().into_iter().zip(b.elements()));
+        }
+        return true;
+    }
+}
+
+impl fmt::Debug for BertTermRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        debug(self, "BertFunRef", f)
+    }
+}
+
+enum DebugItem<'t, 'a> {
+    Term(&'t BertTermRef<'a>),
+    Text(&'static str),
+}
+
+// Writes `term` as `#[derive(Debug)]` would, without recursion;
+// `fun` is the name of the struct of funs.
+fn debug(term: &BertTermRef<'_>, fun: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fn seq<'t, 'a>(items: &mut Vec<DebugItem<'t, 'a>>, terms: &'t [BertTermRef<'a>]) {
+        for (i, term) in terms.iter().enumerate() {
+            if i > 0 {
+                items.push(DebugItem::Text(", "));
+            }
+            items.push(DebugItem::Term(term));
+        }
+    }
+
+    let mut stack = vec![DebugItem::Term(term)];
+    while let Some(item) = stack.pop() {
+        let term = match item {
+            DebugItem::Text(s) => {
+                f.write_str(s)?;
+                continue;
+            }
+            DebugItem::Term(term) => term,
+        };
+        // The items after the opening of a container, in order.
+        let mut items = Vec::new();
+        match *term {
+            BertTermRef::Tuple(ref terms) => {
+                f.write_str("Tuple([")?;
+                seq(&mut items, terms);
+                items.push(DebugItem::Text("])"));
+            }
+            BertTermRef::List(ref terms) => {
+                f.write_str("List([")?;
+                seq(&mut items, terms);
+                items.push(DebugItem::Text("])"));
+            }
+            BertTermRef::ImproperList(ref terms, ref tail) => {
+                f.write_str("ImproperList([")?;
+                seq(&mut items, terms);
+                items.push(DebugItem::Text("], "));
+                items.push(DebugItem::Term(tail));
+                items.push(DebugItem::Text(")"));
+            }
+            BertTermRef::Map(ref keys, ref vals) => {
+                f.write_str("Map([")?;
+                seq(&mut items, keys);
+                items.push(DebugItem::Text("], ["));
+                seq(&mut items, vals);
+                items.push(DebugItem::Text("])"));
+            }
+            BertTermRef::Fun(ref fun_ref) => {
+                write!(
+                    f,
+                    "Fun({} {{ module: {:?}, arity: {:?}, uniq: {:?}, index: {:?}, \
+                     old_index: {:?}, old_uniq: {:?}, pid: ",
+                    fun,
+                    fun_ref.module,
+                    fun_ref.arity,
+                    fun_ref.uniq,
+                    fun_ref.index,
+                    fun_ref.old_index,
+                    fun_ref.old_uniq
+                )?;
+                items.push(DebugItem::Term(&fun_ref.pid));
+                items.push(DebugItem::Text(", free_vars: ["));
+                seq(&mut items, &fun_ref.free_vars);
+                items.push(DebugItem::Text("] })"));
+            }
+            BertTermRef::Local(hash, ref term) => {
+                write!(f, "Local({:?}, ", hash)?;
+                items.push(DebugItem::Term(term));
+                items.push(DebugItem::Text(")"));
+            }
+            ref leaf => leaf.debug_leaf(f)?,
+        }
+        stack.extend(items.into_iter().rev());
+    }
+    return Ok(());
+}
+
+impl<'a> BertTermRef<'a> {
+    // Compares two terms, but not their elements.
+    fn shallow_eq(&self, other: &BertTermRef<'_>) -> bool {
+        use BertTermRef::*;
+        match (self, other) {
+            (Nil, Nil) | (Truncated, Truncated) => true,
+            (Int(a), Int(b)) => a == b,
+            (BigInt(a), BigInt(b)) => a == b,
+            (Float(a), Float(b)) => a == b,
+            (Atom(a), Atom(b)) => a == b,
+            (String(a), String(b)) | (Binary(a), Binary(b)) => a == b,
+            (BitBinary(a, m), BitBinary(b, n)) => a == b && m == n,
+            (Tuple(a), Tuple(b))
+            | (List(a), List(b))
+            | (ImproperList(a, _), ImproperList(b, _)) => a.len() == b.len(),
+            (Map(k1, v1), Map(k2, v2)) => k1.len() == k2.len() && v1.len() == v2.len(),
+            (
+                Pid {
+                    node: n1,
+                    id: i1,
+                    serial: s1,
+                    creation: c1,
+                },
+                Pid {
+                    node: n2,
+                    id: i2,
+                    serial: s2,
+                    creation: c2,
+                },
+            ) => n1 == n2 && i1 == i2 && s1 == s2 && c1 == c2,
+            (
+                Port {
+                    node: n1,
+                    id: i1,
+                    creation: c1,
+                },
+                Port {
+                    node: n2,
+                    id: i2,
+                    creation: c2,
+                },
+            ) => n1 == n2 && i1 == i2 && c1 == c2,
+            (
+                Reference {
+                    node: n1,
+                    creation: c1,
+                    ids: i1,
+                },
+                Reference {
+                    node: n2,
+                    creation: c2,
+                    ids: i2,
+                },
+            ) => n1 == n2 && c1 == c2 && i1 == i2,
+            (Fun(a), Fun(b)) => {
+                a.module == b.module
+                    && a.arity == b.arity
+                    && a.uniq == b.uniq
+                    && a.index == b.index
+                    && a.old_index == b.old_index
+                    && a.old_uniq == b.old_uniq
+                    && a.free_vars.len() == b.free_vars.len()
+            }
+            (
+                Export {
+                    module: m1,
+                    function: f1,
+                    arity: a1,
+                },
+                Export {
+                    module: m2,
+                    function: f2,
+                    arity: a2,
+                },
+            ) => m1 == m2 && f1 == f2 && a1 == a2,
+            (Local(a, _), Local(b, _)) => a == b,
+            _ => false,
+        }
+    }
+
+    // The elements of a container, in the order of `take_elements`.
+    fn elements(&self) -> Vec<&BertTermRef<'a>> {
+        match *self {
+            BertTermRef::Tuple(ref terms) | BertTermRef::List(ref terms) => terms.iter().collect(),
+            BertTermRef::ImproperList(ref terms, ref tail) => {
+                terms.iter().chain(iter::once(&**tail)).collect()
+            }
+            BertTermRef::Map(ref keys, ref vals) => keys.iter().chain(vals).collect(),
+            BertTermRef::Fun(ref fun) => iter::once(&fun.pid).chain(&fun.free_vars).collect(),
+            BertTermRef::Local(_, ref term) => vec![&**term],
+            _ => Vec::new(),
+        }
+    }
+
+    fn debug_leaf(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            BertTermRef::Nil => f.write_str("Nil"),
+            BertTermRef::Truncated => f.write_str("Truncated"),
+            BertTermRef::Int(n) => f.debug_tuple("Int").field(&n).finish(),
+            BertTermRef::BigInt(ref n) => f.debug_tuple("BigInt").field(n).finish(),
+            BertTermRef::Float(x) => f.debug_tuple("Float").field(&x).finish(),
+            BertTermRef::Atom(ref s) => f.debug_tuple("Atom").field(s).finish(),
+            BertTermRef::String(ref bytes) => f.debug_tuple("String").field(bytes).finish(),
+            BertTermRef::Binary(ref bytes) => f.debug_tuple("Binary").field(bytes).finish(),
+            BertTermRef::BitBinary(ref bytes, bits) => f
+                .debug_tuple("BitBinary")
+                .field(bytes)
+                .field(&bits)
+                .finish(),
+            BertTermRef::Pid {
+                ref node,
+                id,
+                serial,
+                creation,
+            } => f
+                .debug_struct("Pid")
+                .field("node", node)
+                .field("id", &id)
+                .field("serial", &serial)
+                .field("creation", &creation)
+                .finish(),
+            BertTermRef::Port {
+                ref node,
+                id,
+                creation,
+            } => f
+                .debug_struct("Port")
+                .field("node", node)
+                .field("id", &id)
+                .field("creation", &creation)
+                .finish(),
+            BertTermRef::Reference {
+                ref node,
+                creation,
+                ref ids,
+            } => f
+                .debug_struct("Reference")
+                .field("node", node)
+                .field("creation", &creation)
+                .field("ids", ids)
+                .finish(),
+            BertTermRef::Export {
+                ref module,
+                ref function,
+                arity,
+            } => f
+                .debug_struct("Export")
+                .field("module", module)
+                .field("function", function)
+                .field("arity", &arity)
+                .finish(),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Copies the borrowed data into a `BertTerm`.
+    pub fn to_owned(&self) -> BertTerm {
+        self.clone().into_owned()
+    }
+
+    /// Converts into a `BertTerm`, copying only the borrowed data.
+    pub fn into_owned(self) -> BertTerm {
+        return convert(self, BertTermRef::owned_step, build_term);
+    }
+
+    /// Copies the borrowed data so that the term no longer
+    /// depends on the buffer it was parsed from.
+    pub fn into_static(self) -> BertTermRef<'static> {
+        return convert(self, BertTermRef::static_step, build_term_ref);
+    }
+
+    fn clone_step<'b>(&'b self) -> Step<&'b BertTermRef<'a>, BertTermRef<'a>, Box<BertFunRef<'a>>> {
+        let leaf = match *self {
+            BertTermRef::Nil => BertTermRef::Nil,
+            BertTermRef::Truncated => BertTermRef::Truncated,
+            BertTermRef::Int(n) => BertTermRef::Int(n),
+            BertTermRef::BigInt(ref n) => BertTermRef::BigInt(n.clone()),
+            BertTermRef::Float(x) => BertTermRef::Float(x),
+            BertTermRef::Atom(ref s) => BertTermRef::Atom(s.clone()),
+            BertTermRef::String(ref bytes) => BertTermRef::String(bytes.clone()),
+            BertTermRef::Binary(ref bytes) => BertTermRef::Binary(bytes.clone()),
+            BertTermRef::BitBinary(ref bytes, bits) => BertTermRef::BitBinary(bytes.clone(), bits),
+            BertTermRef::Pid {
+                ref node,
+                id,
+                serial,
+                creation,
+            } => BertTermRef::Pid {
+                node: node.clone(),
+                id,
+                serial,
+                creation,
+            },
+            BertTermRef::Port {
+                ref node,
+                id,
+                creation,
+            } => BertTermRef::Port {
+                node: node.clone(),
+                id,
+                creation,
+            },
+            BertTermRef::Reference {
+                ref node,
+                creation,
+                ref ids,
+            } => BertTermRef::Reference {
+                node: node.clone(),
+                creation,
+                ids: ids.clone(),
+            },
+            BertTermRef::Export {
+                ref module,
+                ref function,
+                arity,
+            } => BertTermRef::Export {
+                module: module.clone(),
+                function: function.clone(),
+                arity,
+            },
+            BertTermRef::Tuple(ref terms) => {
+                return Step::Node(Shape::Tuple, terms.iter().collect());
+            }
+            BertTermRef::List(ref terms) => return Step::Node(Shape::List, terms.iter().collect()),
+            BertTermRef::ImproperList(ref terms, ref tail) => {
+                let elems = terms.iter().chain(iter::once(&**tail)).collect();
+                return Step::Node(Shape::ImproperList, elems);
+            }
+            BertTermRef::Map(ref keys, ref vals) => {
+                return Step::Node(Shape::Map, keys.iter().chain(vals).collect());
+            }
+            BertTermRef::Fun(ref fun) => {
+                let shape = Shape::Fun(Box::new(BertFunRef {
+                    module: fun.module.clone(),
+                    arity: fun.arity,
+                    uniq: fun.uniq,
+                    index: fun.index,
+                    old_index: fun.old_index,
+                    old_uniq: fun.old_uniq,
+                    pid: BertTermRef::Nil,
+                    free_vars: Vec::new(),
+                }));
+                let elems = iter::once(&fun.pid).chain(&fun.free_vars).collect();
+                return Step::Node(shape, elems);
+            }
+            BertTermRef::Local(hash, ref term) => {
+                return Step::Node(Shape::Local(hash), vec![&**term]);
+            }
+        };
+        return Step::Leaf(leaf);
+    }
+
+    fn owned_step(mut self) -> Step<BertTermRef<'a>, BertTerm, Box<BertFun>> {
+        let elems = self.take_elements();
+        let leaf = match self {
+            BertTermRef::Nil => BertTerm::Nil,
+            BertTermRef::Truncated => BertTerm::Truncated,
+            BertTermRef::Int(n) => BertTerm::Int(n),
+            BertTermRef::BigInt(ref mut n) => BertTerm::BigInt(mem::take(n)),
+            BertTermRef::Float(x) => BertTerm::Float(x),
+            BertTermRef::Atom(ref mut s) => BertTerm::Atom(mem::take(s).into_owned()),
+            BertTermRef::String(ref mut bytes) => BertTerm::String(mem::take(bytes).into_owned()),
+            BertTermRef::Binary(ref mut bytes) => BertTerm::Binary(mem::take(bytes).into_owned()),
+            BertTermRef::BitBinary(ref mut bytes, bits) => {
+                BertTerm::BitBinary(mem::take(bytes).into_owned(), bits)
+            }
+            BertTermRef::Pid {
+                ref mut node,
+                id,
+                serial,
+                creation,
+            } => BertTerm::Pid {
+                node: mem::take(node).into_owned(),
+                id,
+                serial,
+                creation,
+            },
+            BertTermRef::Port {
+                ref mut node,
+                id,
+                creation,
+            } => BertTerm::Port {
+                node: mem::take(node).into_owned(),
+                id,
+                creation,
+            },
+            BertTermRef::Reference {
+                ref mut node,
+                creation,
+                ref mut ids,
+            } => BertTerm::Reference {
+                node: mem::take(node).into_owned(),
+                creation,
+                ids: mem::take(ids),
+            },
+            BertTermRef::Export {
+                ref mut module,
+                ref mut function,
+                arity,
+            } => BertTerm::Export {
+                module: mem::take(module).into_owned(),
+                function: mem::take(function).into_owned(),
+                arity,
+            },
+            BertTermRef::Tuple(_) => return Step::Node(Shape::Tuple, elems),
+            BertTermRef::List(_) => return Step::Node(Shape::List, elems),
+            BertTermRef::ImproperList(..) => return Step::Node(Shape::ImproperList, elems),
+            BertTermRef::Map(..) => return Step::Node(Shape::Map, elems),
+            BertTermRef::Fun(ref mut fun) => {
+                let shape = Shape::Fun(Box::new(BertFun {
+                    module: mem::take(&mut fun.module).into_owned(),
+                    arity: fun.arity,
+                    uniq: fun.uniq,
+                    index: fun.index,
+                    old_index: fun.old_index,
+                    old_uniq: fun.old_uniq,
+                    pid: BertTerm::Nil,
+                    free_vars: Vec::new(),
+                }));
+                return Step::Node(shape, elems);
+            }
+            BertTermRef::Local(hash, _) => return Step::Node(Shape::Local(hash), elems),
+        };
+        return Step::Leaf(leaf);
+    }
+
+    fn static_step(
+        mut self,
+    ) -> Step<BertTermRef<'a>, BertTermRef<'static>, Box<BertFunRef<'static>>> {
+        fn cow<T: ?Sized + ToOwned>(c: &mut Cow<'_, T>) -> Cow<'static, T>
+        where
+            T::Owned: Default,
+        {
+            Cow::Owned(mem::take(c).into_owned())
+        }
+
+        let elems = self.take_elements();
+        let leaf = match self {
+            BertTermRef::Nil => BertTermRef::Nil,
+            BertTermRef::Truncated => BertTermRef::Truncated,
+            BertTermRef::Int(n) => BertTermRef::Int(n),
+            BertTermRef::BigInt(ref mut n) => BertTermRef::BigInt(mem::take(n)),
+            BertTermRef::Float(x) => BertTermRef::Float(x),
+            BertTermRef::Atom(ref mut s) => BertTermRef::Atom(cow(s)),
+            BertTermRef::String(ref mut bytes) => BertTermRef::String(cow(bytes)),
+            BertTermRef::Binary(ref mut bytes) => BertTermRef::Binary(cow(bytes)),
+            BertTermRef::BitBinary(ref mut bytes, bits) => BertTermRef::BitBinary(cow(bytes), bits),
+            BertTermRef::Pid {
+                ref mut node,
+                id,
+                serial,
+                creation,
+            } => BertTermRef::Pid {
+                node: cow(node),
+                id,
+                serial,
+                creation,
+            },
+            BertTermRef::Port {
+                ref mut node,
+                id,
+                creation,
+            } => BertTermRef::Port {
+                node: cow(node),
+                id,
+                creation,
+            },
+            BertTermRef::Reference {
+                ref mut node,
+                creation,
+                ref mut ids,
+            } => BertTermRef::Reference {
+                node: cow(node),
+                creation,
+                ids: mem::take(ids),
+            },
+            BertTermRef::Export {
+                ref mut module,
+                ref mut function,
+                arity,
+            } => BertTermRef::Export {
+                module: cow(module),
+                function: cow(function),
+                arity,
+            },
+            BertTermRef::Tuple(_) => return Step::Node(Shape::Tuple, elems),
+            BertTermRef::List(_) => return Step::Node(Shape::List, elems),
+            BertTermRef::ImproperList(..) => return Step::Node(Shape::ImproperList, elems),
+            BertTermRef::Map(..) => return Step::Node(Shape::Map, elems),
+            BertTermRef::Fun(ref mut fun) => {
+                let shape = Shape::Fun(Box::new(BertFunRef {
+                    module: cow(&mut fun.module),
+                    arity: fun.arity,
+                    uniq: fun.uniq,
+                    index: fun.index,
+                    old_index: fun.old_index,
+                    old_uniq: fun.old_uniq,
+                    pid: BertTermRef::Nil,
+                    free_vars: Vec::new(),
+                }));
+                return Step::Node(shape, elems);
+            }
+            BertTermRef::Local(hash, _) => return Step::Node(Shape::Local(hash), elems),
+        };
+        return Step::Leaf(leaf);
+    }
+
+    // Moves the elements out of a container, leaving it empty.
+    fn take_elements(&mut self) -> Vec<BertTermRef<'a>> {
+        match *self {
+            BertTermRef::Tuple(ref mut terms) | BertTermRef::List(ref mut terms) => {
+                mem::take(terms)
+            }
+            BertTermRef::ImproperList(ref mut terms, ref mut tail) => {
+                let mut elems = mem::take(terms);
+                elems.push(mem::replace(&mut **tail, BertTermRef::Nil));
+                elems
+            }
+            BertTermRef::Map(ref mut keys, ref mut vals) => {
+                let mut elems = mem::take(keys);
+                elems.append(vals);
+                elems
+            }
+            BertTermRef::Fun(ref mut fun) => {
+                let mut elems = vec![mem::replace(&mut fun.pid, BertTermRef::Nil)];
+                elems.append(&mut fun.free_vars);
+                elems
+            }
+            BertTermRef::Local(_, ref mut term) => {
+                vec![mem::replace(&mut **term, BertTermRef::Nil)]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// See `BertTerm::is_basic`.
+    pub fn is_basic(&self) -> bool {
+        let mut term = self;
+        while let BertTermRef::Local(_, ref inner) = *term {
+            term = inner;
+        }
+        !matches!(
+            *term,
+            BertTermRef::List(_)
+                | BertTermRef::ImproperList(_, _)
+                | BertTermRef::Tuple(_)
+                | BertTermRef::Map(_, _)
+        )
+    }
+
+    /// See `BertTerm::is_proplist`.
+    pub fn is_proplist(&self) -> bool {
+        fn is_proplist_entry(t: &BertTermRef) -> bool {
+            match *t {
+                BertTermRef::Tuple(ref elems) => matches!(
+                    elems[..],
+                    [BertTermRef::Atom(_), _]
+                        | [BertTermRef::String(_), _]
+                        | [BertTermRef::Binary(_), _]
+                ),
+                _ => false,
+            }
+        }
+
+        match *self {
+            BertTermRef::List(ref elems) => elems.iter().all(is_proplist_entry),
             _ => false,
         }
     }
@@ -10,14 +10,90 @@ pub const NIL_EXT: u8 = 106;
 pub const STRING_EXT: u8 = 107;
 pub const LIST_EXT: u8 = 108;
 pub const BINARY_EXT: u8 = 109;
+pub const BIT_BINARY_EXT: u8 = 77;
 pub const SMALL_BIG_EXT: u8 = 110;
 pub const LARGE_BIG_EXT: u8 = 111;
 pub const ATOM_UTF8_EXT: u8 = 118;
 pub const SMALL_ATOM_UTF8_EXT: u8 = 119;
 pub const NEW_FLOAT_EXT: u8 = 70;
 pub const MAP_EXT: u8 = 116;
+pub const COMPRESSED: u8 = 80;
+pub const PID_EXT: u8 = 103;
+pub const NEW_PID_EXT: u8 = 88;
+pub const PORT_EXT: u8 = 102;
+pub const NEW_PORT_EXT: u8 = 89;
+pub const V4_PORT_EXT: u8 = 120;
+pub const REFERENCE_EXT: u8 = 101;
+pub const NEW_REFERENCE_EXT: u8 = 114;
+pub const NEWER_REFERENCE_EXT: u8 = 90;
+pub const NEW_FUN_EXT: u8 = 112;
+pub const FUN_EXT: u8 = 117;
+pub const EXPORT_EXT: u8 = 113;
+
+pub const ATOM_CACHE_REF: u8 = 82;
+pub const LOCAL_EXT: u8 = 121;
+
+// Distribution messages
+pub const DIST_HEADER: u8 = 68;
+pub const DIST_FRAG_HEADER: u8 = 69;
+pub const DIST_FRAG_CONT: u8 = 70;
+pub const DIST_PASS_THROUGH: u8 = 112;
+pub const DIST_ATOM_CACHE_SIZE: usize = 2048;
+// The longest atom Erlang creates, in characters.
+pub const MAX_ATOM_CHARS: usize = 255;
 
 pub const DISK_LOG_MAGIC: u32 = 0x01020304;
 pub const DISK_LOG_OPENED: u32 = 0x06070809;
 pub const DISK_LOG_CLOSED: u32 = 0x63584d0b;
 pub const DISK_LOG_TERM_MAGIC: u32 = 0x62574c41;
+// Records of at least this many bytes have an MD5 of their length
+// after the term magic.
+pub const DISK_LOG_MIN_MD5_TERM: usize = 65528;
+// The number of files of a wrap log is less than this.
+pub const WRAP_LOG_MAX_FILES: u32 = 65000;
+
+// The default nesting limit of the parser. Terms are parsed and
+// printed without recursion, so this only bounds the memory that a
+// few bytes per level of hostile input can use.
+pub const DEFAULT_MAX_DEPTH: usize = 100_000;
+
+/// The name of a term tag, as in the External Term Format
+/// documentation.
+pub fn tag_name(tag: u8) -> Option<&'static str> {
+    let name = match tag {
+        SMALL_INTEGER_EXT => "SMALL_INTEGER_EXT",
+        INTEGER_EXT => "INTEGER_EXT",
+        FLOAT_EXT => "FLOAT_EXT",
+        ATOM_EXT => "ATOM_EXT",
+        SMALL_ATOM_EXT => "SMALL_ATOM_EXT",
+        SMALL_TUPLE_EXT => "SMALL_TUPLE_EXT",
+        LARGE_TUPLE_EXT => "LARGE_TUPLE_EXT",
+        NIL_EXT => "NIL_EXT",
+        STRING_EXT => "STRING_EXT",
+        LIST_EXT => "LIST_EXT",
+        BINARY_EXT => "BINARY_EXT",
+        BIT_BINARY_EXT => "BIT_BINARY_EXT",
+        SMALL_BIG_EXT => "SMALL_BIG_EXT",
+        LARGE_BIG_EXT => "LARGE_BIG_EXT",
+        ATOM_UTF8_EXT => "ATOM_UTF8_EXT",
+        SMALL_ATOM_UTF8_EXT => "SMALL_ATOM_UTF8_EXT",
+        NEW_FLOAT_EXT => "NEW_FLOAT_EXT",
+        MAP_EXT => "MAP_EXT",
+        COMPRESSED => "COMPRESSED",
+        PID_EXT => "PID_EXT",
+        NEW_PID_EXT => "NEW_PID_EXT",
+        PORT_EXT => "PORT_EXT",
+        NEW_PORT_EXT => "NEW_PORT_EXT",
+        V4_PORT_EXT => "V4_PORT_EXT",
+        REFERENCE_EXT => "REFERENCE_EXT",
+        NEW_REFERENCE_EXT => "NEW_REFERENCE_EXT",
+        NEWER_REFERENCE_EXT => "NEWER_REFERENCE_EXT",
+        NEW_FUN_EXT => "NEW_FUN_EXT",
+        FUN_EXT => "FUN_EXT",
+        EXPORT_EXT => "EXPORT_EXT",
+        ATOM_CACHE_REF => "ATOM_CACHE_REF",
+        LOCAL_EXT => "LOCAL_EXT",
+        _ => return None,
+    };
+    return Some(name);
+}
@@ -0,0 +1,80 @@
+use crate::framing::Framing;
+use crate::parser::{BertParser, ParserLimits};
+use crate::prelude::*;
+
+#[derive(Debug, PartialEq)]
+pub enum Decoded {
+    /// A term, and the number of bytes it used, framing included.
+    Complete(BertTerm, usize),
+    /// At least this many more bytes are needed to decode the next
+    /// term.
+    NeedMore(usize),
+}
+
+/// A decoder that is fed bytes as they arrive and returns terms once
+/// all their bytes have been fed.
+///
+/// With a framing that skips invalid terms, such as `Packet`, an
+/// invalid term is skipped, so that the next call
+/// decodes the following term. Otherwise, e.g. with `Bert1` or `Bert2`, after
+/// an error every call returns the same error.
+pub struct Decoder<F> {
+    parser: BertParser,
+    framing: F,
+}
+
+impl<F: Framing> Decoder<F> {
+    pub fn new(framing: F) -> Decoder<F> {
+        Decoder::with_limits(framing, ParserLimits::default())
+    }
+
+    pub fn with_limits(framing: F, limits: ParserLimits) -> Decoder<F> {
+        let mut parser = BertParser::with_limits(Vec::new(), limits);
+        parser.set_more_input(true);
+        Decoder { parser, framing }
+    }
+
+    /// Appends `chunk` to the buffered bytes and decodes the next term.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<Decoded> {
+        self.parser.feed(chunk);
+        return self.decode();
+    }
+
+    /// Returns the warnings about the terms decoded since the last
+    /// call, see `BertParser::take_warnings`.
+    pub fn take_warnings(&mut self) -> Vec<BertWarning> {
+        self.parser.take_warnings()
+    }
+
+    /// Decodes the next term from the buffered bytes. A chunk may
+    /// hold several terms: call `decode` until it returns `NeedMore`.
+    pub fn decode(&mut self) -> Result<Decoded> {
+        self.parser.discard_parsed();
+        let start = self.parser.position();
+        let result = self
+            .framing
+            .next_term(&self.parser)
+            .map(|result| result.map(BertTermRef::into_owned));
+        match result {
+            None => return Ok(Decoded::NeedMore(1)),
+            Some(Ok(term)) => {
+                let consumed = self.parser.position() - start;
+                return Ok(Decoded::Complete(term, consumed));
+            }
+            Some(Err(e)) => {
+                if let BertError::NotEnoughData {
+                    needed, available, ..
+                } = *e.root()
+                {
+                    self.parser.rewind(start);
+                    let missing = usize::max(1, needed.saturating_sub(available));
+                    return Ok(Decoded::NeedMore(missing));
+                }
+                if !self.framing.skips_invalid_terms() {
+                    self.parser.rewind(start);
+                }
+                return Err(e);
+            }
+        }
+    }
+}
@@ -19,12 +19,34 @@ pub enum BertError {
     InvalidFloat(usize),
     InvalidUTF8Atom(usize),
     InvalidLatin1Atom(usize),
+    InvalidNode(usize),
+    InvalidFun(usize),
     VarintTooLarge(usize),
+    InvalidCompressedData(usize),
+    // a compressed term inside an inflated one, which Erlang never
+    // encodes
+    NestedCompressedTerm(usize),
+    InvalidCompressedSize {
+        offset: usize,
+        expected: usize,
+        actual: usize,
+    },
     NotEnoughData {
         offset: usize,
         needed: usize,
         available: usize,
     },
+    // an inflated term that needs more bytes than were inflated
+    TruncatedCompressedTerm {
+        offset: usize,
+        needed: usize,
+        available: usize,
+    },
+    InvalidFrameLength {
+        offset: usize,
+        expected: usize,
+        actual: usize,
+    },
     InvalidDiskLogMagic {
         offset: usize,
         actual: u32,
@@ -37,6 +59,176 @@ pub enum BertError {
         offset: usize,
         actual: u32,
     },
+    InvalidAtomCacheRef {
+        offset: usize,
+        index: u8,
+    },
+    // the bits of the last byte of a bit binary must be 1 to 8, or 0
+    // if it has no bytes
+    InvalidBitBinary {
+        offset: usize,
+        bits: u8,
+    },
+    MissingDistFragments {
+        offset: usize,
+        seq: u64,
+    },
+    InvalidWrapLogIndex(usize),
+    InvalidWrapLogSize,
+    InvalidCapture(usize),
+
+    // limit errors
+    DepthLimitExceeded {
+        offset: usize,
+        limit: usize,
+    },
+    ElementLimitExceeded {
+        offset: usize,
+        limit: usize,
+    },
+    BinaryLimitExceeded {
+        offset: usize,
+        size: usize,
+        limit: usize,
+    },
+    AtomLimitExceeded {
+        offset: usize,
+        length: usize,
+        limit: usize,
+    },
+
+    // a suspicious term, when warnings are errors
+    Strict(Box<BertWarning>),
+
+    // an error in a nested term, with the path to that term
+    AtPath {
+        path: Vec<PathStep>,
+        error: Box<BertError>,
+    },
+}
+
+impl BertError {
+    /// Adds `path` in front of the path of the error.
+    pub fn at_path(self, mut path: Vec<PathStep>) -> BertError {
+        if path.is_empty() {
+            return self;
+        }
+        match self {
+            BertError::AtPath { path: rest, error } => {
+                path.extend(rest);
+                return BertError::AtPath { path, error };
+            }
+            error => {
+                return BertError::AtPath {
+                    path,
+                    error: Box::new(error),
+                };
+            }
+        }
+    }
+
+    /// The offset the error is about, if any. After a
+    /// `PathStep::Compressed`, it is an offset in the inflated bytes.
+    pub fn offset(&self) -> Option<usize> {
+        use self::BertError::*;
+        match *self {
+            IoError(_) | InvalidWrapLogSize => None,
+            InvalidTag(offset, _)
+            | InvalidFloat(offset)
+            | InvalidUTF8Atom(offset)
+            | InvalidLatin1Atom(offset)
+            | InvalidNode(offset)
+            | InvalidFun(offset)
+            | VarintTooLarge(offset)
+            | InvalidCompressedData(offset)
+            | NestedCompressedTerm(offset)
+            | InvalidWrapLogIndex(offset)
+            | InvalidCapture(offset) => Some(offset),
+            InvalidMagicNumber { offset, .. }
+            | InvalidCompressedSize { offset, .. }
+            | NotEnoughData { offset, .. }
+            | TruncatedCompressedTerm { offset, .. }
+            | InvalidFrameLength { offset, .. }
+            | InvalidDiskLogMagic { offset, .. }
+            | InvalidDiskLogTermMagic { offset, .. }
+            | InvalidDiskLogOpenedStatus { offset, .. }
+            | InvalidAtomCacheRef { offset, .. }
+            | InvalidBitBinary { offset, .. }
+            | MissingDistFragments { offset, .. }
+            | DepthLimitExceeded { offset, .. }
+            | ElementLimitExceeded { offset, .. }
+            | BinaryLimitExceeded { offset, .. }
+            | AtomLimitExceeded { offset, .. } => Some(offset),
+            Strict(ref warning) => Some(warning.offset()),
+            AtPath { ref error, .. } => error.offset(),
+        }
+    }
+
+    /// Replaces the error without its path by `f` of it.
+    pub fn map_root(self, f: impl FnOnce(BertError) -> BertError) -> BertError {
+        match self {
+            BertError::AtPath { path, error } => BertError::AtPath {
+                path,
+                error: Box::new(f(*error)),
+            },
+            error => f(error),
+        }
+    }
+
+    /// The error, without its path.
+    pub fn root(&self) -> &BertError {
+        match *self {
+            BertError::AtPath { ref error, .. } => error,
+            ref error => error,
+        }
+    }
+
+    /// The path to the term where the error occurred; empty for a
+    /// top-level term, or when the path is unknown.
+    pub fn path(&self) -> &[PathStep] {
+        match *self {
+            BertError::AtPath { ref path, .. } => path,
+            _ => &[],
+        }
+    }
+}
+
+/// A step in the path from the start of the input to a term.
+/// Terms and elements are counted from 0.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PathStep {
+    /// A top-level term of the input.
+    Term(usize),
+    Tuple(usize),
+    List(usize),
+    /// The tail of an improper list, or the `[]` of a proper one.
+    ListTail,
+    MapKey(usize),
+    MapValue(usize),
+    /// A free variable of a fun.
+    FunFreeVar(usize),
+    /// The term wrapped by LOCAL_EXT.
+    Local,
+    /// The inflated term of COMPRESSED; the offsets after this step
+    /// are in the inflated bytes.
+    Compressed,
+}
+
+impl fmt::Display for PathStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::PathStep::*;
+        match *self {
+            Term(n) => write!(f, "term #{}", n),
+            Tuple(n) => write!(f, "tuple[{}]", n),
+            List(n) => write!(f, "list[{}]", n),
+            ListTail => write!(f, "list tail"),
+            MapKey(n) => write!(f, "map key #{}", n),
+            MapValue(n) => write!(f, "map value #{}", n),
+            FunFreeVar(n) => write!(f, "fun free var[{}]", n),
+            Local => write!(f, "local"),
+            Compressed => write!(f, "compressed"),
+        }
+    }
 }
 
 impl fmt::Display for BertError {
@@ -63,11 +255,30 @@ impl fmt::Display for BertError {
                 "Latin-1 atom is not correctly encoded at offset {}",
                 offset
             ),
+            InvalidNode(offset) => write!(f, "node name is not an atom at offset {}", offset),
+            InvalidFun(offset) => write!(f, "invalid fun at offset {}", offset),
             VarintTooLarge(offset) => write!(
                 f,
                 "varint is too large (greater than 2^64-1) at offset {}",
                 offset
             ),
+            InvalidCompressedData(offset) => {
+                write!(f, "invalid zlib stream at offset {}", offset)
+            }
+            NestedCompressedTerm(offset) => write!(
+                f,
+                "compressed term inside a compressed term at offset {}",
+                offset
+            ),
+            InvalidCompressedSize {
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "invalid uncompressed size at offset {}: expected {} bytes, inflated {}",
+                offset, expected, actual
+            ),
             NotEnoughData {
                 needed,
                 available,
@@ -77,6 +288,24 @@ impl fmt::Display for BertError {
                 "no enough data available at offset {}: needed {} bytes, only {} remaining",
                 offset, needed, available
             ),
+            TruncatedCompressedTerm {
+                offset,
+                needed,
+                available,
+            } => write!(
+                f,
+                "inflated term is truncated at offset {}: needed {} bytes, only {} inflated",
+                offset, needed, available
+            ),
+            InvalidFrameLength {
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "invalid frame length at offset {}: frame is {} bytes, term is {}",
+                offset, expected, actual
+            ),
             InvalidDiskLogMagic { offset, actual } => write!(
                 f,
                 "invalid disk_log magic at {}: expected 0x{:08x}, found 0x{:08x}",
@@ -92,12 +321,166 @@ impl fmt::Display for BertError {
                 "invalid disk_log opened status at offset {}: expected 0x{:08x}, found 0x{:08x}",
                 offset, DISK_LOG_OPENED, actual
             ),
+            InvalidAtomCacheRef { offset, index } => write!(
+                f,
+                "atom cache reference {} at offset {} is not in the distribution header",
+                index, offset
+            ),
+            InvalidBitBinary { offset, bits } => write!(
+                f,
+                "invalid number of bits in the last byte of a bit binary ({}) at offset {}",
+                bits, offset
+            ),
+            MissingDistFragments { offset, seq } => write!(
+                f,
+                "missing fragments of distribution message {} at offset {}",
+                seq, offset
+            ),
+            InvalidWrapLogIndex(offset) => {
+                write!(f, "invalid wrap log index file at offset {}", offset)
+            }
+            InvalidWrapLogSize => write!(f, "invalid wrap log size file"),
+            InvalidCapture(offset) => write!(f, "invalid capture file at offset {}", offset),
+            DepthLimitExceeded { offset, limit } => write!(
+                f,
+                "term at offset {} is nested more than {} levels deep",
+                offset, limit
+            ),
+            ElementLimitExceeded { offset, limit } => write!(
+                f,
+                "term has more than {} elements at offset {}",
+                limit, offset
+            ),
+            BinaryLimitExceeded {
+                offset,
+                size,
+                limit,
+            } => write!(
+                f,
+                "binary of {} bytes at offset {} is larger than the limit of {} bytes",
+                size, offset, limit
+            ),
+            AtomLimitExceeded {
+                offset,
+                length,
+                limit,
+            } => write!(
+                f,
+                "atom of {} bytes at offset {} is longer than the limit of {} bytes",
+                length, offset, limit
+            ),
+            Strict(ref warning) => write!(f, "{}", warning),
+            AtPath {
+                ref path,
+                ref error,
+            } => {
+                write!(f, "{}, in ", error)?;
+                for (i, step) in path.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " \u{2192} ")?;
+                    }
+                    write!(f, "{}", step)?;
+                }
+                return Ok(());
+            }
         }
     }
 }
 
 impl Error for BertError {}
 
+/// A problem in the input that the parser recovered from, or a term
+/// that decodes but is suspicious.
+///
+/// The offsets of suspicious terms are those of their tags; in a
+/// compressed term, they are offsets in its inflated bytes.
+#[derive(Debug)]
+pub enum BertWarning {
+    /// The bytes from `start` to `end` were skipped because they
+    /// could not be parsed.
+    SkippedBytes {
+        start: usize,
+        end: usize,
+        cause: BertError,
+    },
+
+    /// A map key that is encoded like an earlier key of the map.
+    DuplicateMapKey { offset: usize },
+
+    /// A SMALL_BIG_EXT or LARGE_BIG_EXT whose value fits in an i32.
+    SmallBigInt { offset: usize },
+
+    /// A float encoded as a string (FLOAT_EXT).
+    OldFloat { offset: usize },
+
+    /// An atom encoded in Latin-1 (ATOM_EXT or SMALL_ATOM_EXT).
+    Latin1Atom { offset: usize },
+
+    /// An atom of more than 255 characters, which Erlang rejects.
+    LongAtom { offset: usize, length: usize },
+
+    /// A bigint of zero with the negative sign.
+    NegativeZero { offset: usize },
+}
+
+impl BertWarning {
+    /// The offset of the first byte the warning is about.
+    pub fn offset(&self) -> usize {
+        use self::BertWarning::*;
+        match *self {
+            SkippedBytes { start, .. } => start,
+            DuplicateMapKey { offset }
+            | SmallBigInt { offset }
+            | OldFloat { offset }
+            | Latin1Atom { offset }
+            | LongAtom { offset, .. }
+            | NegativeZero { offset } => offset,
+        }
+    }
+
+    /// Whether the warning is about a term that decodes but is
+    /// suspicious, rather than about bytes that were skipped.
+    pub fn is_suspicious(&self) -> bool {
+        !matches!(*self, BertWarning::SkippedBytes { .. })
+    }
+}
+
+impl fmt::Display for BertWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::BertWarning::*;
+        match *self {
+            SkippedBytes {
+                start,
+                end,
+                ref cause,
+            } => write!(
+                f,
+                "skipped {} bytes from offset {} to {}: {}",
+                end - start,
+                start,
+                end,
+                cause
+            ),
+            DuplicateMapKey { offset } => write!(f, "duplicate map key at offset {}", offset),
+            SmallBigInt { offset } => write!(
+                f,
+                "big integer at offset {} fits in a 32-bit integer",
+                offset
+            ),
+            OldFloat { offset } => write!(f, "float at offset {} is a FLOAT_EXT string", offset),
+            Latin1Atom { offset } => write!(f, "atom at offset {} is encoded in Latin-1", offset),
+            LongAtom { offset, length } => write!(
+                f,
+                "atom at offset {} has {} characters, more than 255",
+                offset, length
+            ),
+            NegativeZero { offset } => {
+                write!(f, "big integer at offset {} is a negative zero", offset)
+            }
+        }
+    }
+}
+
 pub type Result<T> = result::Result<T, BertError>;
 
 impl From<io::Error> for BertError {
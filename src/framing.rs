@@ -0,0 +1,268 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::parser::BertParser;
+use crate::prelude::*;
+
+/// How the terms of an input are delimited.
+///
+/// `next_term` parses the next term and its framing from the
+/// parser's position, and returns `None` at the end of the input. A
+/// framing that is defined outside this crate is written with the
+/// public parsing methods of `BertParser`.
+pub trait Framing {
+    fn next_term<'a>(&self, parser: &'a BertParser) -> Option<Result<BertTermRef<'a>>>;
+
+    /// Whether `next_term` moves past an invalid term, so that the
+    /// next call parses the following one, as `Packet` does.
+    fn skips_invalid_terms(&self) -> bool {
+        false
+    }
+}
+
+/// Terms that follow each other, each starting with the magic number
+/// (.bert files).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bert1;
+
+/// Terms preceded by their length as a varint (.bert2 files).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bert2;
+
+/// The records of a disk_log; a log without a header is read as a
+/// halt log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DiskLog;
+
+/// The records of a disk_log halt log, which has no header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HaltLog;
+
+/// The terms of a disk_log opened with `{format, external}`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExternalLog;
+
+/// Erlang distribution messages, each preceded by its length on four
+/// bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dist;
+
+/// Terms preceded by their length as a big-endian integer of 1, 2 or
+/// 4 bytes, like Erlang's `{packet, N}` option.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Packet(u8);
+
+impl Packet {
+    /// Returns `None` if `n` is not 1, 2 or 4.
+    pub fn new(n: u8) -> Option<Packet> {
+        match n {
+            1 | 2 | 4 => Some(Packet(n)),
+            _ => None,
+        }
+    }
+}
+
+impl Framing for Bert1 {
+    fn next_term<'a>(&self, parser: &'a BertParser) -> Option<Result<BertTermRef<'a>>> {
+        parser.bert1_next_ref()
+    }
+}
+
+impl Framing for Bert2 {
+    fn next_term<'a>(&self, parser: &'a BertParser) -> Option<Result<BertTermRef<'a>>> {
+        parser.bert2_next_ref()
+    }
+}
+
+impl Framing for DiskLog {
+    fn next_term<'a>(&self, parser: &'a BertParser) -> Option<Result<BertTermRef<'a>>> {
+        parser.disk_log_next_ref()
+    }
+}
+
+impl Framing for HaltLog {
+    fn next_term<'a>(&self, parser: &'a BertParser) -> Option<Result<BertTermRef<'a>>> {
+        parser.halt_log_next_ref()
+    }
+}
+
+impl Framing for ExternalLog {
+    fn next_term<'a>(&self, parser: &'a BertParser) -> Option<Result<BertTermRef<'a>>> {
+        parser.external_log_next_ref()
+    }
+}
+
+impl Framing for Dist {
+    fn next_term<'a>(&self, parser: &'a BertParser) -> Option<Result<BertTermRef<'a>>> {
+        parser.dist_next_ref()
+    }
+}
+
+impl Framing for Packet {
+    fn next_term<'a>(&self, parser: &'a BertParser) -> Option<Result<BertTermRef<'a>>> {
+        if parser.eof() {
+            return None;
+        }
+        return Some(parser.packet_term(self.0));
+    }
+
+    fn skips_invalid_terms(&self) -> bool {
+        true
+    }
+}
+
+impl<F: Framing + ?Sized> Framing for &F {
+    fn next_term<'a>(&self, parser: &'a BertParser) -> Option<Result<BertTermRef<'a>>> {
+        (**self).next_term(parser)
+    }
+
+    fn skips_invalid_terms(&self) -> bool {
+        (**self).skips_invalid_terms()
+    }
+}
+
+impl<F: Framing + ?Sized> Framing for Box<F> {
+    fn next_term<'a>(&self, parser: &'a BertParser) -> Option<Result<BertTermRef<'a>>> {
+        (**self).next_term(parser)
+    }
+
+    fn skips_invalid_terms(&self) -> bool {
+        (**self).skips_invalid_terms()
+    }
+}
+
+impl<F: Framing + ?Sized> Framing for Rc<F> {
+    fn next_term<'a>(&self, parser: &'a BertParser) -> Option<Result<BertTermRef<'a>>> {
+        (**self).next_term(parser)
+    }
+
+    fn skips_invalid_terms(&self) -> bool {
+        (**self).skips_invalid_terms()
+    }
+}
+
+/// The number of bytes that `detect` looks at.
+pub const DETECT_LEN: usize = 9;
+
+/// A format that `detect` recognizes from the first bytes of an
+/// input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Bert1,
+    /// BERT whose first term is compressed.
+    CompressedBert1,
+    Bert2,
+    DiskLog,
+    HaltLog,
+    Packet4,
+}
+
+impl Format {
+    pub fn framing(self) -> &'static dyn Framing {
+        match self {
+            Format::Bert1 | Format::CompressedBert1 => &Bert1,
+            Format::Bert2 => &Bert2,
+            Format::DiskLog => &DiskLog,
+            Format::HaltLog => &HaltLog,
+            Format::Packet4 => &Packet(4),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Format::Bert1 => "BERT",
+            Format::CompressedBert1 => "compressed BERT",
+            Format::Bert2 => "BERT2",
+            Format::DiskLog => "disk_log",
+            Format::HaltLog => "disk_log halt log",
+            Format::Packet4 => "{packet, 4}",
+        };
+        return f.write_str(name);
+    }
+}
+
+/// Guesses the format of an input from its first `DETECT_LEN`
+/// bytes, or fewer if the input is shorter. Returns `None` if they
+/// fit no format.
+pub fn detect(bytes: &[u8]) -> Option<Format> {
+    let be32 = |b: &[u8]| u32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+    if bytes.len() >= 4 && be32(bytes) == DISK_LOG_MAGIC {
+        return Some(Format::DiskLog);
+    }
+    if bytes.len() >= 8 && be32(&bytes[4..]) == DISK_LOG_TERM_MAGIC {
+        return Some(Format::HaltLog);
+    }
+    // A BERT2 term of 131 bytes starts with the magic number too, but
+    // is not followed by a tag.
+    match *bytes {
+        [BERT_MAGIC_NUMBER, COMPRESSED, ..] => return Some(Format::CompressedBert1),
+        [BERT_MAGIC_NUMBER] => return Some(Format::Bert1),
+        [BERT_MAGIC_NUMBER, tag, ..] if tag_name(tag).is_some() => return Some(Format::Bert1),
+        _ => (),
+    }
+    // A varint of at most 8 bytes, which is not zero. It is checked
+    // before a 4-byte length, since it may be 4 bytes long too; a
+    // 4-byte length below 16 MiB is not a varint, as it starts with
+    // a zero.
+    if let Some(len) = bytes.iter().take(8).position(|b| b & 0x80 == 0) {
+        if bytes[..=len].iter().any(|b| b & 0x7f != 0)
+            && bytes.get(len + 1) == Some(&BERT_MAGIC_NUMBER)
+        {
+            return Some(Format::Bert2);
+        }
+    }
+    if bytes.len() >= 5 && be32(bytes) > 0 && bytes[4] == BERT_MAGIC_NUMBER {
+        return Some(Format::Packet4);
+    }
+    return None;
+}
+
+/// An iterator over the terms of a parser, returned by
+/// `BertParser::terms`. Every term comes with the offset of its
+/// first byte, framing included. The iterator ends after the first
+/// error, whose path starts with the number of the term.
+pub struct Terms<'p, F> {
+    parser: &'p mut BertParser,
+    framing: F,
+    failed: bool,
+    // The number of terms returned so far.
+    terms: usize,
+}
+
+impl<'p, F: Framing> Terms<'p, F> {
+    pub fn new(parser: &'p mut BertParser, framing: F) -> Self {
+        Terms {
+            parser,
+            framing,
+            failed: false,
+            terms: 0,
+        }
+    }
+
+    /// Returns the warnings collected since the last call.
+    pub fn take_warnings(&mut self) -> Vec<BertWarning> {
+        self.parser.take_warnings()
+    }
+}
+
+impl<'p, F: Framing> Iterator for Terms<'p, F> {
+    type Item = (usize, Result<BertTerm>);
+
+    fn next(&mut self) -> Option<(usize, Result<BertTerm>)> {
+        if self.failed {
+            return None;
+        }
+        let offset = self.parser.position();
+        let result = match self.framing.next_term(self.parser)? {
+            Ok(term) => Ok(term.into_owned()),
+            Err(e) => {
+                self.failed = true;
+                Err(e.at_path(vec![PathStep::Term(self.terms)]))
+            }
+        };
+        self.terms += 1;
+        return Some((offset, result));
+    }
+}
@@ -0,0 +1,128 @@
+use std::io::{self, Write};
+
+use crate::parser::Annotation;
+
+// The number of bytes shown before and after the offset, and on a
+// line.
+const BEFORE: usize = 48;
+const AFTER: usize = 16;
+const PER_LINE: usize = 8;
+
+/// Writes the bytes around `offset`, where `bytes` starts at offset
+/// `base` of the input. Every line has the offset of its first byte,
+/// the bytes in hex and, for the first line of an annotation, its
+/// label indented by its depth. The line of the byte at `offset`
+/// starts with `>` and the byte is in brackets.
+pub fn write_hex_dump<W: Write + ?Sized>(
+    w: &mut W,
+    base: usize,
+    bytes: &[u8],
+    offset: usize,
+    annotations: &[Annotation],
+) -> io::Result<()> {
+    let end = base + bytes.len();
+    let lo = usize::max(base, offset.saturating_sub(BEFORE));
+    let hi = usize::min(end, offset.saturating_add(AFTER + 1));
+
+    // The annotated spans in the window, and the bytes between them.
+    let mut spans: Vec<(usize, usize, Option<&Annotation>)> = Vec::new();
+    let mut pos = lo;
+    for a in annotations {
+        if a.offset + a.len <= pos || a.offset >= hi {
+            continue;
+        }
+        if a.offset > pos {
+            spans.push((pos, a.offset, None));
+        }
+        let stop = usize::min(a.offset + a.len, hi);
+        spans.push((usize::max(a.offset, pos), stop, Some(a)));
+        pos = stop;
+    }
+    if pos < hi {
+        spans.push((pos, hi, None));
+    }
+
+    for (start, stop, annotation) in spans {
+        let mut label =
+            annotation.map(|a| format!("{:indent$}{}", "", a.label, indent = 2 * a.depth));
+        for line in (start..stop).step_by(PER_LINE) {
+            let line_end = usize::min(line + PER_LINE, stop);
+            let hex: Vec<String> = (line..line_end)
+                .map(|i| {
+                    let b = bytes[i - base];
+                    if i == offset {
+                        format!("[{:02x}]", b)
+                    } else {
+                        format!("{:02x}", b)
+                    }
+                })
+                .collect();
+            let mark = if (line..line_end).contains(&offset) {
+                '>'
+            } else {
+                ' '
+            };
+            let text = format!(
+                "{} {:>8}  {:<26}{}",
+                mark,
+                line,
+                hex.join(" "),
+                label.take().unwrap_or_default()
+            );
+            writeln!(w, "{}", text.trim_end())?;
+        }
+    }
+    if offset >= end {
+        writeln!(w, "> {:>8}  (end of input)", offset)?;
+    }
+    return Ok(());
+}
+
+/// Writes a line for every annotation of `bytes`, which starts at
+/// offset `base` of the input: its offset, its bytes in hex, the
+/// first ones only if there are more than fit on a line, and its
+/// label indented by its depth. The bytes between annotations get
+/// a line without a label.
+pub fn write_explanation<W: Write + ?Sized>(
+    w: &mut W,
+    base: usize,
+    bytes: &[u8],
+    annotations: &[Annotation],
+) -> io::Result<()> {
+    let end = base + bytes.len();
+    let mut pos = base;
+    for a in annotations {
+        if a.offset < pos || a.offset + a.len > end {
+            continue;
+        }
+        if a.offset > pos {
+            write_span(w, pos, &bytes[pos - base..a.offset - base], "")?;
+        }
+        let label = format!("{:indent$}{}", "", a.label, indent = 2 * a.depth);
+        write_span(w, a.offset, &bytes[a.offset - base..][..a.len], &label)?;
+        pos = a.offset + a.len;
+    }
+    if pos < end {
+        write_span(w, pos, &bytes[pos - base..], "")?;
+    }
+    return Ok(());
+}
+
+fn write_span<W: Write + ?Sized>(
+    w: &mut W,
+    offset: usize,
+    bytes: &[u8],
+    label: &str,
+) -> io::Result<()> {
+    let shown = if bytes.len() > PER_LINE {
+        &bytes[..PER_LINE - 1]
+    } else {
+        bytes
+    };
+    let mut hex: Vec<String> = shown.iter().map(|b| format!("{:02x}", b)).collect();
+    if shown.len() < bytes.len() {
+        hex.push("..".to_string());
+    }
+    let text = format!("{:>8}  {:<26}{}", offset, hex.join(" "), label);
+    return writeln!(w, "{}", text.trim_end());
+}
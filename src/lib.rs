@@ -1,11 +1,18 @@
 pub mod bertterm;
 pub mod consts;
+pub mod decoder;
 pub mod error;
+pub mod framing;
+pub mod hexdump;
+pub mod lint;
 pub mod parser;
+pub mod pcap;
 pub mod pp;
+pub mod stream;
+pub mod wrap_log;
 
 pub mod prelude {
-    pub use crate::bertterm::BertTerm;
+    pub use crate::bertterm::{BertFun, BertFunRef, BertTerm, BertTermRef};
     pub use crate::consts::*;
-    pub use crate::error::{BertError, Result};
+    pub use crate::error::{BertError, BertWarning, PathStep, Result};
 }
@@ -0,0 +1,138 @@
+use std::fmt;
+
+use crate::parser::{Annotation, BertParser, Trace};
+use crate::pp::BertWriter;
+use crate::prelude::*;
+
+// The number of bytes shown of an encoding.
+const SHOWN: usize = 16;
+
+/// A term of the input that `term_to_binary/1` would encode
+/// differently.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Difference {
+    /// The offset of the term in the input.
+    pub offset: usize,
+    /// The encoding of the term in the input, and its canonical
+    /// encoding. A container only spans its header, unless its
+    /// elements are encoded as different terms, e.g. a list of bytes
+    /// as a STRING_EXT.
+    pub actual: Vec<u8>,
+    pub expected: Vec<u8>,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "at offset {}: expected {}, found {}",
+            self.offset,
+            Encoding(&self.expected),
+            Encoding(&self.actual)
+        )
+    }
+}
+
+// The name of the tag of an encoding, then its first bytes in hex.
+struct Encoding<'a>(&'a [u8]);
+
+impl<'a> fmt::Display for Encoding<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes = self.0;
+        if let Some(name) = bytes.first().and_then(|&tag| tag_name(tag)) {
+            write!(f, "{} ", name)?;
+        }
+        let shown = &bytes[..usize::min(SHOWN, bytes.len())];
+        let hex: Vec<String> = shown.iter().map(|b| format!("{:02x}", b)).collect();
+        write!(f, "<{}", hex.join(" "))?;
+        if shown.len() < bytes.len() {
+            write!(f, " ...")?;
+        }
+        return write!(f, ">");
+    }
+}
+
+/// Compares the encoding of `term` with its canonical encoding, see
+/// `BertWriter::canonical`. `bytes` is the encoding of the term,
+/// without the magic number, at `offset` of the input, and
+/// `annotations` the terms recorded while parsing it with
+/// `Trace::Terms`.
+///
+/// The terms of both encodings are compared in order, so the order
+/// of the keys of maps is not checked.
+pub fn lint(
+    term: &BertTermRef<'_>,
+    bytes: &[u8],
+    offset: usize,
+    annotations: &[Annotation],
+) -> Result<Vec<Difference>> {
+    let end = offset + bytes.len();
+    let actual: Vec<&Annotation> = annotations
+        .iter()
+        .filter(|a| a.offset >= offset && a.offset + a.len <= end)
+        .collect();
+
+    let mut canonical = Vec::new();
+    BertWriter::canonical().write_bert(term, &mut canonical)?;
+    let mut parser = BertParser::new(canonical);
+    parser.set_tracing(Trace::Terms);
+    parser.bert_term_ref()?;
+    let expected = parser.annotations();
+    let expected: Vec<&Annotation> = expected.iter().collect();
+    let (_, canonical) = parser.buffer();
+
+    let input = |start: usize, stop: usize| bytes[start - offset..stop - offset].to_vec();
+    let output = |start: usize, stop: usize| canonical[start..stop].to_vec();
+    let actual_ends = subtree_ends(&actual);
+    let expected_ends = subtree_ends(&expected);
+    let mut differences = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < actual.len() && j < expected.len() {
+        let (a, e) = (actual[i], expected[j]);
+        let head = input(a.offset, a.offset + a.len);
+        let canonical_head = output(e.offset, e.offset + e.len);
+        if head == canonical_head {
+            i += 1;
+            j += 1;
+        } else if actual_ends[i] - i == expected_ends[j] - j {
+            differences.push(Difference {
+                offset: a.offset,
+                actual: head,
+                expected: canonical_head,
+            });
+            i += 1;
+            j += 1;
+        } else {
+            // The elements differ too: compare the whole terms.
+            let stop = actual.get(actual_ends[i]).map_or(end, |a| a.offset);
+            let canonical_stop = expected
+                .get(expected_ends[j])
+                .map_or(canonical.len(), |e| e.offset);
+            differences.push(Difference {
+                offset: a.offset,
+                actual: input(a.offset, stop),
+                expected: output(e.offset, canonical_stop),
+            });
+            i = actual_ends[i];
+            j = expected_ends[j];
+        }
+    }
+    return Ok(differences);
+}
+
+// The index after the last element of every term, nested or not.
+fn subtree_ends(annotations: &[&Annotation]) -> Vec<usize> {
+    let mut ends = vec![annotations.len(); annotations.len()];
+    let mut open: Vec<usize> = Vec::new();
+    for (i, a) in annotations.iter().enumerate() {
+        while let Some(&j) = open.last() {
+            if annotations[j].depth < a.depth {
+                break;
+            }
+            ends[j] = i;
+            open.pop();
+        }
+        open.push(i);
+    }
+    return ends;
+}
@@ -1,13 +1,22 @@
+use std::cell::Cell;
 use std::env;
 use std::fs;
-use std::io::{self, BufWriter, ErrorKind, Read};
+use std::io::{self, BufWriter, ErrorKind, Read, Write};
+use std::net::SocketAddr;
 use std::path::Path;
 use std::process::exit;
+use std::rc::Rc;
 use std::time::{Duration, Instant};
 
+use ppbert::framing::*;
+use ppbert::hexdump::{write_explanation, write_hex_dump};
+use ppbert::lint::lint;
 use ppbert::parser::*;
+use ppbert::pcap::dist_messages_with_limits;
 use ppbert::pp::*;
 use ppbert::prelude::*;
+use ppbert::stream::StreamParser;
+use ppbert::wrap_log::{is_wrap_log, wrap_log_files};
 
 const PROG_NAME: &str = env!("CARGO_BIN_NAME");
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -23,9 +32,44 @@ struct Opts {
     #[options(short = "v", long = "verbose", help = "show diagnostics on stderr")]
     verbose: bool,
 
+    #[options(
+        no_short,
+        long = "strict",
+        help = "reject suspicious terms, which --verbose warns about"
+    )]
+    strict: bool,
+
     #[options(short = "p", long = "parse", help = "only parse, do not pretty print")]
     parse: bool,
 
+    #[options(
+        no_short,
+        long = "hexdump",
+        help = "show the bytes around parse errors, labelled with their terms"
+    )]
+    hexdump: bool,
+
+    #[options(
+        no_short,
+        long = "explain",
+        help = "print every field of the encoding with its offset and bytes"
+    )]
+    explain: bool,
+
+    #[options(
+        no_short,
+        long = "lint",
+        help = "report where terms differ from what term_to_binary/1 encodes"
+    )]
+    lint: bool,
+
+    #[options(
+        no_short,
+        long = "lenient",
+        help = "print what was decoded of a term cut short by the end of a file"
+    )]
+    lenient: bool,
+
     #[options(
         short = "1",
         long = "bert1",
@@ -43,6 +87,82 @@ struct Opts {
     )]
     disk_log: bool,
 
+    #[options(
+        short = "H",
+        long = "halt-log",
+        help = "force ppbert to use the parser for disk_log halt logs"
+    )]
+    halt_log: bool,
+
+    #[options(
+        short = "e",
+        long = "external-log",
+        help = "force ppbert to use the parser for external disk_logs"
+    )]
+    external_log: bool,
+
+    #[options(
+        short = "D",
+        long = "dist",
+        help = "force ppbert to use the Erlang distribution message parser"
+    )]
+    dist: bool,
+
+    #[options(
+        short = "C",
+        long = "pcap",
+        help = "decode the distribution messages in a pcap or pcapng capture"
+    )]
+    pcap: bool,
+
+    #[options(
+        no_short,
+        long = "dist-port",
+        help = "only decode the captured connections to or from PORT",
+        meta = "PORT"
+    )]
+    dist_port: Option<u16>,
+
+    #[options(
+        short = "P",
+        long = "packet",
+        help = "force ppbert to use {packet, N} framing (N = 1, 2 or 4)",
+        meta = "N"
+    )]
+    packet: Option<u8>,
+
+    #[options(
+        no_short,
+        long = "max-depth",
+        help = "reject terms nested more than NUM levels deep (default: 100000)",
+        meta = "NUM"
+    )]
+    max_depth: Option<usize>,
+
+    #[options(
+        no_short,
+        long = "max-elements",
+        help = "reject terms with more than NUM elements in all",
+        meta = "NUM"
+    )]
+    max_elements: Option<usize>,
+
+    #[options(
+        no_short,
+        long = "max-binary-size",
+        help = "reject binaries of more than NUM bytes (default: 64 MiB)",
+        meta = "NUM"
+    )]
+    max_binary_size: Option<usize>,
+
+    #[options(
+        no_short,
+        long = "max-atom-length",
+        help = "reject atoms of more than NUM bytes",
+        meta = "NUM"
+    )]
+    max_atom_length: Option<usize>,
+
     #[options(
         short = "i",
         long = "indent",
@@ -81,6 +201,14 @@ struct Opts {
     #[options(short = "b", long = "bert", help = "print as BERT")]
     bert: bool,
 
+    #[options(
+        short = "z",
+        long = "compress",
+        help = "compress BERT output with zlib at LEVEL (0-9)",
+        meta = "LEVEL"
+    )]
+    compress: Option<u32>,
+
     #[options(help = "files to process", free)]
     files: Vec<String>,
 }
@@ -98,20 +226,53 @@ fn main() {
         opts.files.push("-".to_string());
     }
 
-    let parser_choice: Option<ParserNext> = if opts.bert1 {
-        Some(BertParser::bert1_next)
+    let framing_choice: Option<Box<dyn Framing>> = if opts.bert1 {
+        Some(Box::new(Bert1))
     } else if opts.bert2 {
-        Some(BertParser::bert2_next)
+        Some(Box::new(Bert2))
     } else if opts.disk_log {
-        Some(BertParser::disk_log_next)
+        Some(Box::new(DiskLog))
+    } else if opts.halt_log {
+        Some(Box::new(HaltLog))
+    } else if opts.external_log {
+        Some(Box::new(ExternalLog))
+    } else if opts.dist {
+        Some(Box::new(Dist))
+    } else if let Some(n) = opts.packet {
+        match Packet::new(n) {
+            Some(packet) => Some(Box::new(packet)),
+            None => {
+                eprintln!(
+                    "{}: invalid packet size {}; expected 1, 2 or 4",
+                    PROG_NAME, n
+                );
+                exit(1);
+            }
+        }
     } else {
         None
     };
 
+    if opts.compress.is_some() && !opts.bert {
+        eprintln!("{}: --compress requires --bert", PROG_NAME);
+        exit(1);
+    }
+
+    let defaults = ParserLimits::default();
+    let limits = ParserLimits {
+        max_depth: opts.max_depth.unwrap_or(defaults.max_depth),
+        max_elements: opts.max_elements.unwrap_or(defaults.max_elements),
+        max_binary_size: opts.max_binary_size.unwrap_or(defaults.max_binary_size),
+        max_atom_length: opts.max_atom_length.unwrap_or(defaults.max_atom_length),
+    };
+
     let pp: Box<dyn PrettyPrinter> = if opts.json {
         Box::new(JsonPrettyPrinter::new(opts.transform))
     } else if opts.bert {
-        Box::new(BertWriter::new())
+        match opts.compress {
+            Some(level) => Box::new(BertWriter::with_compression(level)),
+            None => Box::new(BertWriter::new()),
+        }
     } else {
         let terminator = if opts.append { "." } else { "" };
         Box::new(ErlangPrettyPrinter::new(
@@ -122,18 +283,73 @@ fn main() {
     };
 
     let mut return_code = 0;
-    for file in &opts.files {
-        if let Err(ref e) = handle_file(file, opts.parse, opts.verbose, parser_choice, &*pp) {
-            if broken_pipe(e) {
-                break;
+    'files: for file in &opts.files {
+        if opts.pcap || is_capture(file) {
+            match handle_capture(file, &opts, limits, &*pp) {
+                Ok(0) => (),
+                Ok(_) => return_code = 1,
+                Err(ref e) => {
+                    if broken_pipe(e) {
+                        break;
+                    }
+                    return_code = 1;
+                    eprintln!("{}: {:?}: {}", PROG_NAME, file, e);
+                }
+            }
+            continue;
+        }
+        let inputs = match inputs(file, framing_choice.as_deref()) {
+            Ok(inputs) => inputs,
+            Err(ref e) => {
+                return_code = 1;
+                eprintln!("{}: {:?}: {}", PROG_NAME, file, e);
+                continue;
+            }
+        };
+        for (input, framing_choice) in inputs {
+            match handle_file(&input, &opts, framing_choice, limits, &*pp) {
+                Ok(0) => (),
+                Ok(_) => return_code = 1,
+                Err(ref e) => {
+                    if broken_pipe(e) {
+                        break 'files;
+                    }
+                    return_code = 1;
+                    eprintln!("{}: {:?}: {}", PROG_NAME, input, e);
+                }
             }
-            return_code = 1;
-            eprintln!("{}: {:?}: {}", PROG_NAME, file, e);
         }
     }
     exit(return_code);
 }
 
+// A wrap log stands for its files, in chronological order.
+fn inputs<'f>(
+    file: &str,
+    framing_choice: Option<&'f dyn Framing>,
+) -> Result<Vec<(String, Option<&'f dyn Framing>)>> {
+    if file == "-" || !is_wrap_log(file) {
+        return Ok(vec![(file.to_string(), framing_choice)]);
+    }
+    let files = wrap_log_files(Path::new(file))?
+        .into_iter()
+        .map(|path| {
+            let framing: &dyn Framing = &DiskLog;
+            (path.to_string_lossy().into_owned(), Some(framing))
+        })
+        .collect();
+    return Ok(files);
+}
+
+// The warnings about suspicious terms are only shown with --verbose.
+fn print_warnings(filename: &str, warnings: Vec<BertWarning>, verbose: bool) {
+    for w in warnings {
+        if verbose || !w.is_suspicious() {
+            eprintln!("{}: {:?}: warning: {}", PROG_NAME, filename, w);
+        }
+    }
+}
+
 fn broken_pipe(err: &BertError) -> bool {
     match *err {
         BertError::IoError(ref ioerr) => ioerr.kind() == ErrorKind::BrokenPipe,
@@ -141,78 +357,316 @@ fn broken_pipe(err: &BertError) -> bool {
     }
 }
 
-fn read_bytes(filename: &str) -> Result<Vec<u8>> {
+// Keeps track of the time spent waiting for input, since
+// reading is interleaved with parsing.
+struct TimedReader<R> {
+    reader: R,
+    elapsed: Rc<Cell<Duration>>,
+}
+
+impl<R: Read> Read for TimedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let now = Instant::now();
+        let res = self.reader.read(buf);
+        self.elapsed.set(self.elapsed.get() + now.elapsed());
+        return res;
+    }
+}
+
+fn open_input(filename: &str) -> Result<Box<dyn Read>> {
     if filename == "-" {
-        let stdin = io::stdin();
-        let mut stdin = stdin.lock();
-        let mut buf: Vec<u8> = Vec::with_capacity(4096);
-        stdin.read_to_end(&mut buf)?;
-        return Ok(buf);
+        return Ok(Box::new(io::stdin()));
     } else {
-        let buf = fs::read(filename)?;
-        return Ok(buf);
+        return Ok(Box::new(fs::File::open(filename)?));
     }
 }
 
-fn parser_from_ext(filename: &str) -> ParserNext {
+fn is_capture(filename: &str) -> bool {
+    let ext: Option<&str> = Path::new(filename).extension().and_then(|x| x.to_str());
+    return matches!(ext, Some("pcap") | Some("pcapng"));
+}
+
+// Prints every distribution message of a capture as a map of its
+// time, sender, receiver and message; returns the number of
+// messages that could not be decoded.
+fn handle_capture(
+    filename: &str,
+    opts: &Opts,
+    limits: ParserLimits,
+    pp: &dyn PrettyPrinter,
+) -> Result<usize> {
+    // The messages are parsed as their connections are reassembled,
+    // with none of the settings of the parser of a file.
+    if opts.strict || opts.lenient || opts.hexdump || opts.explain || opts.lint {
+        eprintln!(
+            "{}: {:?}: --strict, --lenient, --hexdump, --explain and --lint do not apply to captures",
+            PROG_NAME, filename
+        );
+        return Ok(1);
+    }
+
+    let mut capture = Vec::new();
+    open_input(filename)?.read_to_end(&mut capture)?;
+
+    let mut failed = 0;
+    for m in dist_messages_with_limits(&capture, opts.dist_port, limits)? {
+        let from = endpoint(m.src, m.src_node);
+        let to = endpoint(m.dst, m.dst_node);
+        let message = match m.message {
+            Ok(message) => message,
+            Err(e) => {
+                failed += 1;
+                eprintln!(
+                    "{}: {:?}: message from {} to {}: {}",
+                    PROG_NAME, filename, from, to, e
+                );
+                continue;
+            }
+        };
+        if opts.parse {
+            continue;
+        }
+        let keys = ["time", "from", "to", "message"]
+            .iter()
+            .map(|k| BertTerm::Atom(k.to_string()))
+            .collect();
+        let values = vec![
+            BertTerm::Float(m.time.as_secs_f64()),
+            BertTerm::Binary(from.into_bytes()),
+            BertTerm::Binary(to.into_bytes()),
+            message,
+        ];
+        let entry = BertTerm::Map(keys, values);
+        let stdout = BufWriter::new(io::stdout());
+        pp.write(&entry, Box::new(stdout))?;
+        entry.drop_deep();
+    }
+    return Ok(failed);
+}
+
+fn endpoint(addr: SocketAddr, node: Option<String>) -> String {
+    match node {
+        Some(node) => format!("{} ({})", node, addr),
+        None => addr.to_string(),
+    }
+}
+
+fn framing_from_ext(filename: &str) -> &'static dyn Framing {
     let ext: Option<&str> = Path::new(filename).extension().and_then(|x| x.to_str());
     match ext {
-        Some("bert") | Some("bert1") => BertParser::bert1_next,
-        Some("bert2") => BertParser::bert2_next,
-        Some("log") => BertParser::disk_log_next,
+        Some("bert") | Some("bert1") => &Bert1,
+        Some("bert2") => &Bert2,
+        Some("log") => &DiskLog,
         _ => {
             eprintln!(
                 "{}: cannot find an appropriate parser for {}; using BERT",
                 PROG_NAME, filename
             );
-            BertParser::bert1_next
+            &Bert1
         }
     }
 }
 
+// Prints the terms of a file; returns the number of parse errors,
+// which are printed here so that they can be followed by a hex dump.
 fn handle_file(
     filename: &str,
-    parse_only: bool,
-    verbose: bool,
-    parser_choice: Option<ParserNext>,
+    opts: &Opts,
+    framing_choice: Option<&dyn Framing>,
+    limits: ParserLimits,
     pp: &dyn PrettyPrinter,
-) -> Result<()> {
-    // Read file or stdin into buffer
-    let now = Instant::now();
-    let bytes = read_bytes(filename)?;
-    let read_dur = now.elapsed();
-    let mut parser = BertParser::new(bytes);
-
-    let parser_next: ParserNext = match parser_choice {
+) -> Result<usize> {
+    let read_dur = Rc::new(Cell::new(Duration::new(0, 0)));
+    let mut reader = TimedReader {
+        reader: open_input(filename)?,
+        elapsed: read_dur.clone(),
+    };
+
+    // Without a flag, the format is detected from the first bytes,
+    // which are then parsed with the rest. Only one read is made, so
+    // that a pipe whose first term is shorter than `DETECT_LEN` is
+    // printed without waiting for more bytes.
+    let mut head = vec![0; DETECT_LEN];
+    let framing = match framing_choice {
         Some(f) => f,
-        None => parser_from_ext(filename),
+        None => {
+            let n = loop {
+                match reader.read(&mut head) {
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                    res => break res?,
+                }
+            };
+            head.truncate(n);
+            match detect(&head) {
+                Some(format) => {
+                    if opts.verbose {
+                        eprintln!("{}: {} format: {}", PROG_NAME, filename, format);
+                    }
+                    format.framing()
+                }
+                None => framing_from_ext(filename),
+            }
+        }
     };
+    let reader = io::Cursor::new(head).chain(reader);
+    let mut parser = StreamParser::with_limits(reader, framing, limits);
+    if opts.explain {
+        parser.set_tracing(Trace::Fields);
+    } else if opts.hexdump || opts.lint {
+        parser.set_tracing(Trace::Terms);
+    }
+
+    parser.set_lenient(opts.lenient);
+    parser.set_strict(opts.strict);
 
     let mut parse_dur = Duration::new(0, 0);
     let mut pp_dur = Duration::new(0, 0);
 
+    // The warnings cannot be taken while a term borrows the parser.
+    let mut error = None;
+    let mut truncated = false;
+    let mut terms = 0;
+    let mut differences = 0;
     loop {
         let now = Instant::now();
-        let term = match parser_next(&mut parser) {
-            Some(term) => term?,
+        let term = match parser.next_ref() {
+            Some(Ok(term)) => term,
+            Some(Err(e)) => {
+                error = Some(e);
+                break;
+            }
             None => break,
         };
         parse_dur += now.elapsed();
-        if !parse_only {
+        if !opts.parse && !opts.explain && !opts.lint {
             let now = Instant::now();
             let stdout = BufWriter::new(io::stdout());
-            pp.write(&term, Box::new(stdout))?;
+            pp.write_ref(&term, Box::new(stdout))?;
             pp_dur += now.elapsed();
         }
+        // The annotations cannot be read while the term borrows the
+        // parser.
+        let owned = if opts.lint && !opts.explain {
+            Some(term.into_owned())
+        } else {
+            drop(term);
+            None
+        };
+        if let Some(term) = owned {
+            differences += print_lint(filename, terms, &parser, &term)?;
+            term.drop_deep();
+        }
+        terms += 1;
+        if opts.explain {
+            print_explanation(&parser, parser.position())?;
+        }
+        // A disk_log record that is cut short is skipped.
+        truncated |= print_partial(&mut parser, opts, pp)?;
+        print_warnings(filename, parser.take_warnings(), opts.verbose);
+    }
+    truncated |= print_partial(&mut parser, opts, pp)?;
+    print_warnings(filename, parser.take_warnings(), opts.verbose);
+    if let Some(ref e) = error {
+        if opts.explain {
+            // What was parsed of the failing term.
+            let annotations = parser.annotations();
+            let end = annotations.iter().map(|a| a.offset + a.len).max();
+            print_explanation(&parser, end.unwrap_or(0))?;
+        }
+        eprintln!("{}: {:?}: {}", PROG_NAME, filename, e);
+        if opts.hexdump {
+            print_hex_dump(&parser, e);
+        }
+        return Ok(1);
+    }
+    if truncated || differences > 0 {
+        return Ok(1);
     }
 
-    if verbose {
+    if opts.verbose {
+        // Reading happens during parsing.
+        let read_dur = read_dur.get();
+        let parse_dur = parse_dur.checked_sub(read_dur).unwrap_or_default();
         eprintln!("{}: {} read time: {:?}", PROG_NAME, filename, read_dur);
         eprintln!("{}: {} parse time: {:?}", PROG_NAME, filename, parse_dur);
-        if !parse_only {
+        if !opts.parse {
             eprintln!("{}: {} print time: {:?}", PROG_NAME, filename, pp_dur);
         }
     }
 
+    return Ok(0);
+}
+
+// Prints what was decoded of a term cut short by the end of the
+// input, if there is one; returns whether there was.
+fn print_partial<R: Read, F: Framing>(
+    parser: &mut StreamParser<R, F>,
+    opts: &Opts,
+    pp: &dyn PrettyPrinter,
+) -> Result<bool> {
+    let partial = match parser.take_partial() {
+        Some(partial) => partial,
+        None => return Ok(false),
+    };
+    if !opts.parse && !opts.explain {
+        let stdout = BufWriter::new(io::stdout());
+        pp.write(&partial, Box::new(stdout))?;
+    }
+    partial.drop_deep();
+    return Ok(true);
+}
+
+// Prints where the encoding of a term differs from the canonical
+// one; returns the number of differences.
+fn print_lint<R: Read, F: Framing>(
+    filename: &str,
+    n: usize,
+    parser: &StreamParser<R, F>,
+    term: &BertTerm,
+) -> Result<usize> {
+    let annotations = parser.annotations();
+    let start = match annotations.first() {
+        Some(a) => a.offset,
+        None => return Ok(0),
+    };
+    let (base, bytes) = parser.buffer();
+    let bytes = &bytes[start - base..parser.position() - base];
+    let differences = lint(&term.as_term_ref(), bytes, start, &annotations)?;
+    for d in &differences {
+        println!("{}: term #{} {}", filename, n, d);
+    }
+    return Ok(differences.len());
+}
+
+// Prints the annotations of the buffered bytes before `end`.
+fn print_explanation<R: Read, F: Framing>(parser: &StreamParser<R, F>, end: usize) -> Result<()> {
+    let (base, bytes) = parser.buffer();
+    let len = usize::min(end.saturating_sub(base), bytes.len());
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    write_explanation(&mut stdout, base, &bytes[..len], &parser.annotations())?;
+    writeln!(stdout)?;
+    stdout.flush()?;
     return Ok(());
 }
+
+// The offsets in a compressed term are in its inflated bytes, which
+// are not kept.
+fn print_hex_dump<R: Read, F: Framing>(parser: &StreamParser<R, F>, err: &BertError) {
+    let offset = match err.offset() {
+        Some(offset) if !err.path().contains(&PathStep::Compressed) => offset,
+        _ => return,
+    };
+    let (base, bytes) = parser.buffer();
+    if offset < base {
+        return;
+    }
+    let stderr = io::stderr();
+    let _ = write_hex_dump(
+        &mut stderr.lock(),
+        base,
+        bytes,
+        offset,
+        &parser.annotations(),
+    );
+}
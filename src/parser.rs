@@ -1,64 +1,600 @@
+use crate::framing::{Framing, Terms};
 use crate::prelude::*;
 
 use num_bigint::{BigInt, ToBigInt};
-use num_traits::{One, Zero};
+use num_traits::{One, ToPrimitive, Zero};
+
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::mem;
 
 use byteorder::{BigEndian, ReadBytesExt};
 
+use flate2::read::ZlibDecoder;
+
 use encoding::all::ISO_8859_1;
 use encoding::{DecoderTrap, Encoding};
 
-pub type ParserNext = fn(&mut BertParser) -> Option<Result<BertTerm>>;
+/// Limits on the terms a parser accepts, so that hostile input
+/// cannot exhaust the stack or the memory. A term that exceeds a
+/// limit is an error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParserLimits {
+    /// The deepest nesting of terms; a term at the top level has
+    /// depth 1.
+    pub max_depth: usize,
+    /// The total number of elements of the tuples, lists, maps
+    /// (keys and values) and fun environments in a top-level term.
+    pub max_elements: usize,
+    /// The largest binary, string, big integer or compressed term,
+    /// in bytes.
+    pub max_binary_size: usize,
+    /// The longest atom, in bytes.
+    pub max_atom_length: usize,
+}
+
+impl ParserLimits {
+    /// Limits that accept every term.
+    pub fn none() -> ParserLimits {
+        ParserLimits {
+            max_depth: usize::MAX,
+            max_elements: usize::MAX,
+            max_binary_size: usize::MAX,
+            max_atom_length: usize::MAX,
+        }
+    }
+}
+
+impl Default for ParserLimits {
+    /// Terms nested at most `DEFAULT_MAX_DEPTH` levels deep, inflated
+    /// terms included, and binaries of at most 64 MiB, so that a
+    /// compressed term cannot inflate to its declared size of up to
+    /// 4 GiB; no other limits, since the other lengths are not trusted
+    /// past the size of the input.
+    fn default() -> ParserLimits {
+        ParserLimits {
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_binary_size: 64 << 20,
+            ..ParserLimits::none()
+        }
+    }
+}
+
+/// What a parser records while parsing, see `BertParser::set_tracing`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trace {
+    Off,
+    /// The terms; a container only spans its header.
+    Terms,
+    /// Every field of the encoding: magic numbers, tags, lengths,
+    /// values, and the fields of the framing.
+    Fields,
+}
+
+/// A span of the input and what it encodes, recorded while
+/// parsing when tracing is on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Annotation {
+    pub offset: usize,
+    pub len: usize,
+    /// The nesting of the term that the span belongs to; 0 for a
+    /// top-level term.
+    pub depth: usize,
+    pub label: String,
+}
+
+// A container whose elements are being parsed.
+enum Frame<'a> {
+    Tuple(Vec<BertTermRef<'a>>, usize),
+    // The elements, their number, and the tail once it is parsed.
+    List(Vec<BertTermRef<'a>>, usize, Option<BertTermRef<'a>>),
+    Map(
+        Vec<BertTermRef<'a>>,
+        Vec<BertTermRef<'a>>,
+        usize,
+        MapKeys<'a>,
+    ),
+    // The fun, and the number of its free variables.
+    Fun(Box<BertFunRef<'a>>, usize),
+    Local(u32, Option<BertTermRef<'a>>),
+}
+
+// The encodings of the keys of a map with their offsets, to find
+// duplicates, and the offset of the key being parsed.
+struct MapKeys<'a> {
+    encodings: Vec<(&'a [u8], usize)>,
+    start: usize,
+}
+
+impl<'a> Frame<'a> {
+    fn push(&mut self, term: BertTermRef<'a>) {
+        match *self {
+            Frame::Tuple(ref mut terms, _) => terms.push(term),
+            Frame::List(ref mut terms, len, ref mut tail) => {
+                if terms.len() < len {
+                    terms.push(term);
+                } else {
+                    *tail = Some(term);
+                }
+            }
+            Frame::Map(ref mut keys, ref mut vals, ..) => {
+                if keys.len() == vals.len() {
+                    keys.push(term);
+                } else {
+                    vals.push(term);
+                }
+            }
+            Frame::Fun(ref mut fun, _) => fun.free_vars.push(term),
+            Frame::Local(_, ref mut inner) => *inner = Some(term),
+        }
+    }
+
+    // The step to the element being parsed.
+    fn step(&self) -> PathStep {
+        match *self {
+            Frame::Tuple(ref terms, _) => PathStep::Tuple(terms.len()),
+            Frame::List(ref terms, len, _) if terms.len() < len => PathStep::List(terms.len()),
+            Frame::List(..) => PathStep::ListTail,
+            Frame::Map(ref keys, ref vals, ..) if keys.len() == vals.len() => {
+                PathStep::MapKey(keys.len())
+            }
+            Frame::Map(_, ref vals, ..) => PathStep::MapValue(vals.len()),
+            Frame::Fun(ref fun, _) => PathStep::FunFreeVar(fun.free_vars.len()),
+            Frame::Local(..) => PathStep::Local,
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        match *self {
+            Frame::Tuple(ref terms, len) => terms.len() == len,
+            Frame::List(_, _, ref tail) => tail.is_some(),
+            Frame::Map(_, ref vals, len, _) => vals.len() == len,
+            Frame::Fun(ref fun, num_free) => fun.free_vars.len() == num_free,
+            Frame::Local(_, ref inner) => inner.is_some(),
+        }
+    }
+
+    fn into_term(self) -> BertTermRef<'a> {
+        match self {
+            Frame::Tuple(terms, _) => BertTermRef::Tuple(terms),
+            Frame::List(terms, _, Some(BertTermRef::Nil)) => BertTermRef::List(terms),
+            Frame::List(terms, _, tail) => {
+                BertTermRef::ImproperList(terms, Box::new(tail.unwrap()))
+            }
+            Frame::Map(keys, vals, ..) => BertTermRef::Map(keys, vals),
+            Frame::Fun(fun, _) => BertTermRef::Fun(fun),
+            Frame::Local(hash, inner) => BertTermRef::Local(hash, Box::new(inner.unwrap())),
+        }
+    }
+
+    // Closes the container after `last`, the partial term or the
+    // marker where the input ended.
+    fn into_partial(self, last: BertTermRef<'a>) -> BertTermRef<'a> {
+        match self {
+            Frame::Tuple(mut terms, _) => {
+                terms.push(last);
+                BertTermRef::Tuple(terms)
+            }
+            Frame::List(mut terms, len, _) if terms.len() < len => {
+                terms.push(last);
+                BertTermRef::List(terms)
+            }
+            Frame::List(terms, _, _) => BertTermRef::ImproperList(terms, Box::new(last)),
+            Frame::Map(mut keys, mut vals, ..) => {
+                if keys.len() == vals.len() {
+                    keys.push(last);
+                    vals.push(BertTermRef::Truncated);
+                } else {
+                    vals.push(last);
+                }
+                BertTermRef::Map(keys, vals)
+            }
+            Frame::Fun(mut fun, _) => {
+                fun.free_vars.push(last);
+                BertTermRef::Fun(fun)
+            }
+            Frame::Local(hash, _) => BertTermRef::Local(hash, Box::new(last)),
+        }
+    }
+}
 
+// What a tag starts: a whole term, or a container.
+enum Parsed<'a> {
+    Term(BertTermRef<'a>),
+    Open(Frame<'a>),
+}
+
+// `pos` is the offset from the start of the input, not from the
+// start of `contents`: when the bytes that were already parsed are
+// discarded, `base` is the offset of the first byte of `contents`.
+//
+// Parsing methods take `&self` so that the terms they return can
+// borrow from `contents`; `pos` and `warnings` are therefore cells.
+//
+// `more_input` is set while bytes may still be fed: until then, a
+// truncated disk_log record or FLOAT_EXT is incomplete rather than
+// corrupt.
+//
+// When `lenient` is set and the input ends in the middle of a term,
+// what was decoded of the term is kept in `partial`, with markers
+// where the input ended.
+//
+// `depth` is the nesting of the term being parsed and `elements`
+// the number of elements reserved since the start of the top-level
+// term; both are checked against `limits`.
+//
+// When `trace` is not `Off`, the terms or the fields that are parsed
+// are recorded in `annotations`, until their bytes are discarded.
+//
+// `inflated` is set in the parser of the bytes of a compressed
+// term, which must not hold another one: every level would be
+// parsed by a new parser, on the native stack.
+//
+// Distribution messages update `atom_cache`, the atoms the sending
+// node has cached, and set `atom_cache_refs`, the cache entries
+// that ATOM_CACHE_REF terms refer to. Fragments are kept by
+// sequence id and fragment id until the last one arrives.
 #[derive(Debug)]
 pub struct BertParser {
     contents: Vec<u8>,
-    pos: usize,
+    pos: Cell<usize>,
+    base: usize,
+    more_input: bool,
+    lenient: bool,
+    partial: RefCell<Option<BertTerm>>,
+    strict: bool,
+    limits: ParserLimits,
+    depth: Cell<usize>,
+    elements: Cell<usize>,
+    inflated: bool,
+    trace: Trace,
+    annotations: RefCell<Vec<Annotation>>,
+    warnings: RefCell<Vec<BertWarning>>,
+    atom_cache: RefCell<Vec<Option<String>>>,
+    atom_cache_refs: RefCell<Vec<Option<String>>>,
+    fragments: RefCell<BTreeMap<(u64, u64), Vec<u8>>>,
+    fragment_refs: RefCell<BTreeMap<u64, Vec<Option<String>>>>,
 }
 
 impl BertParser {
     pub fn new(contents: Vec<u8>) -> BertParser {
+        BertParser::with_limits(contents, ParserLimits::default())
+    }
+
+    pub fn with_limits(contents: Vec<u8>, limits: ParserLimits) -> BertParser {
+        BertParser {
+            contents,
+            pos: Cell::new(0),
+            base: 0,
+            more_input: false,
+            lenient: false,
+            partial: RefCell::new(None),
+            strict: false,
+            limits,
+            depth: Cell::new(0),
+            elements: Cell::new(0),
+            inflated: false,
+            trace: Trace::Off,
+            annotations: RefCell::new(Vec::new()),
+            warnings: RefCell::new(Vec::new()),
+            atom_cache: RefCell::new(Vec::new()),
+            atom_cache_refs: RefCell::new(Vec::new()),
+            fragments: RefCell::new(BTreeMap::new()),
+            fragment_refs: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    /// Tells the parser whether more bytes may be fed after the
+    /// current ones.
+    pub fn set_more_input(&mut self, more_input: bool) {
+        self.more_input = more_input;
+    }
+
+    /// Keeps what was decoded of a term that the end of the input
+    /// cuts short, for `take_partial`. The term is only kept once no
+    /// more bytes may be fed.
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    /// Returns the term that the last `NotEnoughData` error cut
+    /// short, if parsing is lenient. `BertTerm::Truncated` marks
+    /// where the input ended; a truncated atom, string or binary is
+    /// a marker as a whole.
+    pub fn take_partial(&mut self) -> Option<BertTerm> {
+        self.partial.take()
+    }
+
+    /// Makes the warnings about suspicious terms errors, see
+    /// `BertWarning::is_suspicious`.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Returns the warnings collected since the last call.
+    pub fn take_warnings(&mut self) -> Vec<BertWarning> {
+        self.warnings.take()
+    }
+
+    /// Records the terms or the fields that are parsed, with their
+    /// offsets, for `annotations`.
+    pub fn set_tracing(&mut self, trace: Trace) {
+        self.trace = trace;
+    }
+
+    /// The annotations recorded since tracing was set, in the bytes
+    /// that were not discarded, by offset.
+    pub fn annotations(&self) -> Vec<Annotation> {
+        self.annotations.borrow().clone()
+    }
+
+    /// The bytes that were not discarded, and the offset of the
+    /// first one.
+    pub fn buffer(&self) -> (usize, &[u8]) {
+        (self.base, &self.contents)
+    }
+
+    fn warn(&self, warning: BertWarning) {
+        self.warnings.borrow_mut().push(warning);
+    }
+
+    fn suspicious(&self, warning: BertWarning) -> Result<()> {
+        if self.strict {
+            return Err(BertError::Strict(Box::new(warning)));
+        }
+        self.warn(warning);
+        return Ok(());
+    }
+
+    /// Appends `bytes` to the input.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.contents.extend_from_slice(bytes);
+    }
+
+    /// A parser with a copy of the bytes and the state of this one,
+    /// which can be fed while terms still borrow from this one. The
+    /// state that is not copied is moved: this parser must not be
+    /// used to parse after that.
+    pub(crate) fn fork(&self) -> BertParser {
         BertParser {
-            contents: contents,
-            pos: 0,
+            contents: self.contents.clone(),
+            pos: self.pos.clone(),
+            base: self.base,
+            more_input: self.more_input,
+            lenient: self.lenient,
+            partial: RefCell::new(self.partial.take()),
+            strict: self.strict,
+            limits: self.limits,
+            depth: self.depth.clone(),
+            elements: self.elements.clone(),
+            inflated: self.inflated,
+            trace: self.trace,
+            annotations: RefCell::new(self.annotations.take()),
+            warnings: RefCell::new(self.warnings.take()),
+            atom_cache: RefCell::new(self.atom_cache.take()),
+            atom_cache_refs: RefCell::new(self.atom_cache_refs.take()),
+            fragments: RefCell::new(self.fragments.take()),
+            fragment_refs: RefCell::new(self.fragment_refs.take()),
         }
     }
 
+    /// Drops the bytes before the current position; offsets keep
+    /// counting from the start of the input.
+    pub fn discard_parsed(&mut self) {
+        self.contents.drain(..self.pos.get() - self.base);
+        self.base = self.pos.get();
+        let base = self.base;
+        self.annotations.get_mut().retain(|a| a.offset >= base);
+    }
+
+    /// The offset of the next byte to parse.
+    pub fn position(&self) -> usize {
+        self.pos.get()
+    }
+
+    /// Moves back to `pos`, e.g. to retry a term after feeding
+    /// more bytes; `pos` must not be before discarded bytes. The
+    /// warnings about the bytes after `pos` are dropped.
+    pub fn rewind(&mut self, pos: usize) {
+        assert!(pos >= self.base && pos <= self.pos.get());
+        self.pos.set(pos);
+        self.warnings.get_mut().retain(|w| w.offset() < pos);
+        self.annotations.get_mut().retain(|a| a.offset < pos);
+    }
+
+    /// The number of bytes left to parse.
+    pub fn remaining(&self) -> usize {
+        self.rest().len()
+    }
+
+    fn rest(&self) -> &[u8] {
+        &self.contents[self.pos.get() - self.base..]
+    }
+
+    /// Returns an iterator over the terms delimited by `framing`.
+    pub fn terms<F: Framing>(&mut self, framing: F) -> Terms<'_, F> {
+        Terms::new(self, framing)
+    }
+
     // "Iterators"
     pub fn bert1_next(&mut self) -> Option<Result<BertTerm>> {
+        owned(self.bert1_next_ref())
+    }
+
+    pub fn bert2_next(&mut self) -> Option<Result<BertTerm>> {
+        owned(self.bert2_next_ref())
+    }
+
+    pub fn disk_log_next(&mut self) -> Option<Result<BertTerm>> {
+        owned(self.disk_log_next_ref())
+    }
+
+    pub fn halt_log_next(&mut self) -> Option<Result<BertTerm>> {
+        owned(self.halt_log_next_ref())
+    }
+
+    pub fn external_log_next(&mut self) -> Option<Result<BertTerm>> {
+        owned(self.external_log_next_ref())
+    }
+
+    pub fn dist_next(&mut self) -> Option<Result<BertTerm>> {
+        owned(self.dist_next_ref())
+    }
+
+    pub fn packet1_next(&mut self) -> Option<Result<BertTerm>> {
+        owned(self.packet1_next_ref())
+    }
+
+    pub fn packet2_next(&mut self) -> Option<Result<BertTerm>> {
+        owned(self.packet2_next_ref())
+    }
+
+    pub fn packet4_next(&mut self) -> Option<Result<BertTerm>> {
+        owned(self.packet4_next_ref())
+    }
+
+    // Zero-copy "iterators"
+    pub fn bert1_next_ref(&self) -> Option<Result<BertTermRef<'_>>> {
         if self.eof() {
             return None;
         }
-        let result = self.magic_number().and_then(|_| self.bert_term());
+        let result = self.magic_number().and_then(|_| self.bert_term_ref());
         return Some(result);
     }
 
-    pub fn bert2_next(&mut self) -> Option<Result<BertTerm>> {
+    pub fn bert2_next_ref(&self) -> Option<Result<BertTermRef<'_>>> {
         if self.eof() {
             return None;
         }
+        let start = self.pos.get();
         let result = self
             .parse_varint()
+            .map(|len| self.field(start, || format!("length (varint): {}", len)))
             .and_then(|_| self.magic_number())
-            .and_then(|_| self.bert_term());
+            .and_then(|_| self.bert_term_ref());
         return Some(result);
     }
 
-    pub fn disk_log_next(&mut self) -> Option<Result<BertTerm>> {
+    // A corrupt record is skipped with a warning, and parsing
+    // resumes at the next record. A log without a header is read
+    // as a halt log.
+    pub fn disk_log_next_ref(&self) -> Option<Result<BertTermRef<'_>>> {
         if self.eof() {
             return None;
         }
-        let result = self
-            .disk_log_magic()
-            .and_then(|_| self.disk_log_opened_status())
-            .and_then(|_| self.disk_log_term());
-        return Some(result);
+        if self.pos.get() == 0 && !self.starts_with_disk_log_record() {
+            let header = self
+                .disk_log_magic()
+                .and_then(|_| self.disk_log_opened_status());
+            if let Err(e) = header {
+                return Some(Err(e));
+            }
+        }
+        return self.disk_log_record();
+    }
+
+    // Halt logs have no header.
+    pub fn halt_log_next_ref(&self) -> Option<Result<BertTermRef<'_>>> {
+        if self.eof() {
+            return None;
+        }
+        return self.disk_log_record();
+    }
+
+    // External logs hold whatever bytes were written to them: for
+    // terms, the concatenated output of `term_to_binary`.
+    pub fn external_log_next_ref(&self) -> Option<Result<BertTermRef<'_>>> {
+        return self.bert1_next_ref();
+    }
+
+    fn disk_log_record(&self) -> Option<Result<BertTermRef<'_>>> {
+        loop {
+            // Past the header, the next record may still be coming.
+            if self.eof() && !self.more_input {
+                return None;
+            }
+            let start = self.pos.get();
+            match self.disk_log_term() {
+                Ok(term) => return Some(Ok(term)),
+                Err(e)
+                    if self.more_input && matches!(e.root(), BertError::NotEnoughData { .. }) =>
+                {
+                    return Some(Err(e));
+                }
+                Err(e) => {
+                    if let Err(e) = self.disk_log_resync(start, e) {
+                        return Some(Err(e));
+                    }
+                }
+            }
+        }
+    }
+
+    // Distribution messages, each preceded by its length on four
+    // bytes; empty messages are ticks and are skipped, as are the
+    // fragments before the last one of a message.
+    pub fn dist_next_ref(&self) -> Option<Result<BertTermRef<'_>>> {
+        let mut skipped = false;
+        loop {
+            if self.eof() {
+                // The message after a skipped one may still be coming.
+                if skipped && self.more_input {
+                    return Some(Err(BertError::NotEnoughData {
+                        offset: self.pos.get(),
+                        needed: 4,
+                        available: 0,
+                    }));
+                }
+                return None;
+            }
+            let result = self.len_field(4, "message length").and_then(|len| {
+                let available = self.remaining();
+                if available < len {
+                    self.salvage(|| self.dist_message(available));
+                    return Err(BertError::NotEnoughData {
+                        offset: self.pos.get(),
+                        needed: len,
+                        available,
+                    });
+                }
+                self.dist_message(len)
+            });
+            match result {
+                Ok(Some(term)) => return Some(Ok(term)),
+                Ok(None) => skipped = true,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+
+    pub fn packet1_next_ref(&self) -> Option<Result<BertTermRef<'_>>> {
+        if self.eof() {
+            return None;
+        }
+        return Some(self.packet_term(1));
+    }
+
+    pub fn packet2_next_ref(&self) -> Option<Result<BertTermRef<'_>>> {
+        if self.eof() {
+            return None;
+        }
+        return Some(self.packet_term(2));
+    }
+
+    pub fn packet4_next_ref(&self) -> Option<Result<BertTermRef<'_>>> {
+        if self.eof() {
+            return None;
+        }
+        return Some(self.packet_term(4));
     }
 
     // Parsers
-    pub fn magic_number(&mut self) -> Result<()> {
-        let initial_pos = self.pos;
+    pub fn magic_number(&self) -> Result<()> {
+        let initial_pos = self.pos.get();
         let magic = self.eat_u8()?;
+        self.field(initial_pos, || "magic number".to_string());
         if magic != BERT_MAGIC_NUMBER {
             return Err(BertError::InvalidMagicNumber {
                 offset: initial_pos,
@@ -68,9 +604,10 @@ impl BertParser {
         return Ok(());
     }
 
-    pub fn disk_log_magic(&mut self) -> Result<()> {
-        let initial_pos = self.pos;
+    pub fn disk_log_magic(&self) -> Result<()> {
+        let initial_pos = self.pos.get();
         let magic = self.eat_u32_be()?;
+        self.field(initial_pos, || "disk_log magic".to_string());
         if magic != DISK_LOG_MAGIC {
             return Err(BertError::InvalidDiskLogMagic {
                 offset: initial_pos,
@@ -80,9 +617,17 @@ impl BertParser {
         return Ok(());
     }
 
-    pub fn disk_log_opened_status(&mut self) -> Result<()> {
-        let initial_pos = self.pos;
+    pub fn disk_log_opened_status(&self) -> Result<()> {
+        let initial_pos = self.pos.get();
         let status = self.eat_u32_be()?;
+        self.field(initial_pos, || {
+            let status = match status {
+                DISK_LOG_OPENED => "opened",
+                DISK_LOG_CLOSED => "closed",
+                _ => "invalid",
+            };
+            format!("disk_log status: {}", status)
+        });
         if status != DISK_LOG_OPENED && status != DISK_LOG_CLOSED {
             return Err(BertError::InvalidDiskLogOpenedStatus {
                 offset: initial_pos,
@@ -92,286 +637,1108 @@ impl BertParser {
         return Ok(());
     }
 
-    pub fn disk_log_term(&mut self) -> Result<BertTerm> {
-        // XXX(vfoley): should we check that the correct length was read?
-        let _len_offset = self.pos;
-        let _len = self.eat_u32_be()?;
+    pub fn disk_log_term(&self) -> Result<BertTermRef<'_>> {
+        let len = self.len_field(4, "record length")?;
 
-        let magic_pos = self.pos;
+        let magic_pos = self.pos.get();
         let magic = self.eat_u32_be()?;
+        self.field(magic_pos, || "record magic".to_string());
         if magic != DISK_LOG_TERM_MAGIC {
             return Err(BertError::InvalidDiskLogTermMagic {
                 offset: magic_pos,
                 actual: magic,
             });
         }
+        if len >= DISK_LOG_MIN_MD5_TERM {
+            let start = self.pos.get();
+            self.eat_slice(16)?;
+            self.field(start, || "MD5 of the record length".to_string());
+        }
 
-        let magic_pos = self.pos;
-        let magic = self.eat_u8()?;
-        if magic != BERT_MAGIC_NUMBER {
-            return Err(BertError::InvalidMagicNumber {
-                offset: magic_pos,
-                actual: magic,
+        let start = self.pos.get();
+        let available = self.remaining();
+        if available < len {
+            self.salvage(|| self.magic_number().and_then(|_| self.bert_term_ref()));
+            return Err(BertError::NotEnoughData {
+                offset: start,
+                needed: len,
+                available,
             });
         }
 
-        return self.bert_term();
+        self.magic_number()?;
+        let term = self.bert_term_ref()?;
+        let actual = self.pos.get() - start;
+        if actual != len {
+            return Err(BertError::InvalidFrameLength {
+                offset: start,
+                expected: len,
+                actual,
+            });
+        }
+        return Ok(term);
+    }
+
+    fn starts_with_disk_log_record(&self) -> bool {
+        let rest = self.rest();
+        return rest.len() >= 8
+            && rest[..4] != DISK_LOG_MAGIC.to_be_bytes()
+            && rest[4..8] == DISK_LOG_TERM_MAGIC.to_be_bytes();
     }
 
-    pub fn bert_term(&mut self) -> Result<BertTerm> {
-        let initial_pos = self.pos;
-        match self.eat_u8()? {
+    // Moves to the next record after the corrupt one at `start`,
+    // i.e. four bytes before the next term magic, or to the end of
+    // the input if there is none.
+    fn disk_log_resync(&self, start: usize, cause: BertError) -> Result<()> {
+        let magic = DISK_LOG_TERM_MAGIC.to_be_bytes();
+        let from = usize::min(start + 5, self.base + self.contents.len());
+        let next = self.contents[from - self.base..]
+            .windows(magic.len())
+            .position(|w| w == magic);
+        let end = match next {
+            Some(i) => from + i - 4,
+            None if self.more_input => {
+                let available = self.remaining();
+                return Err(BertError::NotEnoughData {
+                    offset: start,
+                    needed: available + 1,
+                    available,
+                });
+            }
+            None => self.base + self.contents.len(),
+        };
+        self.pos.set(end);
+        self.warn(BertWarning::SkippedBytes { start, end, cause });
+        return Ok(());
+    }
+
+    // A term preceded by its length as a big-endian integer of
+    // `header_size` bytes, as with Erlang's `{packet, N}` option. The
+    // parser moves to the end of the frame even if the term is
+    // invalid, so that the next term can be parsed.
+    pub fn packet_term(&self, header_size: u8) -> Result<BertTermRef<'_>> {
+        let len = self.len_field(header_size, "length")?;
+        let start = self.pos.get();
+        let available = self.remaining();
+        if available < len {
+            self.salvage(|| self.magic_number().and_then(|_| self.bert_term_ref()));
+            return Err(BertError::NotEnoughData {
+                offset: start,
+                needed: len,
+                available,
+            });
+        }
+
+        let result = self.magic_number().and_then(|_| self.bert_term_ref());
+        let actual = match result {
+            Ok(_) => self.pos.get() - start,
+            Err(ref e) => match *e.root() {
+                // The term goes past the end of the input, which
+                // holds the whole frame. The offsets of the errors
+                // in a compressed term are not in the input.
+                BertError::NotEnoughData { offset, needed, .. }
+                    if (start..=start + len).contains(&offset) =>
+                {
+                    offset + needed - start
+                }
+                _ => len,
+            },
+        };
+        self.pos.set(start + len);
+        if actual != len {
+            return Err(BertError::InvalidFrameLength {
+                offset: start,
+                expected: len,
+                actual,
+            });
+        }
+        return result;
+    }
+
+    /// Parses a distribution message of `len` bytes into a tuple of
+    /// its control message and, if there is one, its payload.
+    /// Returns `None` for an empty message (a tick) and for the
+    /// fragments before the last one of a message.
+    pub fn dist_message(&self, len: usize) -> Result<Option<BertTermRef<'_>>> {
+        let start = self.pos.get();
+        let end = start + len;
+        if len == 0 {
+            return Ok(None);
+        }
+        // A pass-through message has no magic number before its tag.
+        if self.peek()? != DIST_PASS_THROUGH {
+            self.magic_number()?;
+        }
+        let tag_pos = self.pos.get();
+        let tag = self.eat_u8()?;
+        self.field(tag_pos, || {
+            let name = match tag {
+                DIST_HEADER => "DIST_HEADER",
+                DIST_PASS_THROUGH => "DIST_PASS_THROUGH",
+                DIST_FRAG_HEADER => "DIST_FRAG_HEADER",
+                DIST_FRAG_CONT => "DIST_FRAG_CONT",
+                _ => "invalid tag",
+            };
+            name.to_string()
+        });
+        match tag {
+            DIST_HEADER => {
+                self.dist_atom_cache_refs()?;
+                return self.dist_body(start, end).map(Some);
+            }
+            DIST_PASS_THROUGH => return self.dist_body(start, end).map(Some),
+            DIST_FRAG_HEADER | DIST_FRAG_CONT => {
+                let field_pos = self.pos.get();
+                let seq = self.eat_u64_be()?;
+                self.field(field_pos, || format!("sequence id: {}", seq));
+                let field_pos = self.pos.get();
+                let frag = self.eat_u64_be()?;
+                self.field(field_pos, || format!("fragment id: {}", frag));
+                if tag == DIST_FRAG_HEADER {
+                    self.dist_atom_cache_refs()?;
+                    let refs = self.atom_cache_refs.borrow().clone();
+                    self.fragment_refs.borrow_mut().insert(seq, refs);
+                }
+                let field_pos = self.pos.get();
+                let data = self.eat_slice(end.saturating_sub(self.pos.get()))?;
+                self.field(field_pos, || "fragment data".to_string());
+                self.fragments
+                    .borrow_mut()
+                    .insert((seq, frag), data.to_vec());
+                if frag != 1 {
+                    return Ok(None);
+                }
+                return self.dist_fragments(tag_pos, seq).map(Some);
+            }
+            _ => return Err(BertError::InvalidTag(tag_pos, tag)),
+        }
+    }
+
+    // Reads the atom cache references of a distribution header:
+    // their number, a half-byte of flags for each reference and
+    // one for the whole header, then the references.
+    fn dist_atom_cache_refs(&self) -> Result<()> {
+        let n = self.len_field(1, "atom cache references")?;
+        let mut refs = Vec::with_capacity(n);
+        if n > 0 {
+            let flags_pos = self.pos.get();
+            let flags = self.eat_slice(n / 2 + 1)?;
+            self.field(flags_pos, || "flags".to_string());
+            let flag = |i: usize| (flags[i / 2] >> (4 * (i % 2))) & 0xf;
+            let long_atoms = flag(n) & 0x1 != 0;
+            let mut cache = self.atom_cache.borrow_mut();
+            cache.resize(DIST_ATOM_CACHE_SIZE, None);
+            for i in 0..n {
+                let ref_pos = self.pos.get();
+                let index = (flag(i) as usize & 0x7) << 8 | self.eat_u8()? as usize;
+                self.field(ref_pos, || format!("cache index: {}", index));
+                if flag(i) & 0x8 != 0 {
+                    let len = self.len_field(if long_atoms { 2 } else { 1 }, "length")?;
+                    let offset = self.pos.get();
+                    self.check_atom_length(offset, len)?;
+                    let bytes = self.eat_slice(len)?;
+                    let atom = std::str::from_utf8(bytes)
+                        .map_err(|_| BertError::InvalidUTF8Atom(offset))?;
+                    self.field(offset, || format!("new atom: {:?}", atom));
+                    cache[index] = Some(atom.to_string());
+                }
+                refs.push(cache[index].clone());
+            }
+        }
+        *self.atom_cache_refs.borrow_mut() = refs;
+        return Ok(());
+    }
+
+    // The control message and the optional payload that follow a
+    // distribution header; they have no magic number after a
+    // DIST_HEADER, but do after a DIST_PASS_THROUGH.
+    fn dist_body(&self, start: usize, end: usize) -> Result<BertTermRef<'_>> {
+        let mut terms = Vec::with_capacity(2);
+        while terms.len() < 2 && self.pos.get() < end {
+            if self.peek()? == BERT_MAGIC_NUMBER {
+                self.eat_u8()?;
+            }
+            terms.push(self.bert_term_ref()?);
+        }
+        let actual = self.pos.get() - start;
+        if terms.is_empty() || actual != end - start {
+            return Err(BertError::InvalidFrameLength {
+                offset: start,
+                expected: end - start,
+                actual,
+            });
+        }
+        return Ok(BertTermRef::Tuple(terms));
+    }
+
+    // Parses the message made of the fragments of `seq`; the
+    // fragment ids count down to 1.
+    fn dist_fragments(&self, offset: usize, seq: u64) -> Result<BertTermRef<'_>> {
+        let mut fragments = self.fragments.borrow_mut();
+        let keys: Vec<(u64, u64)> = fragments
+            .range((seq, 0)..=(seq, u64::MAX))
+            .map(|(k, _)| *k)
+            .collect();
+        let refs = self.fragment_refs.borrow_mut().remove(&seq);
+        let refs = match refs {
+            Some(refs) if keys.len() as u64 == keys[keys.len() - 1].1 => refs,
+            _ => return Err(BertError::MissingDistFragments { offset, seq }),
+        };
+        let mut data = Vec::new();
+        for key in keys.iter().rev() {
+            data.extend(fragments.remove(key).unwrap_or_default());
+        }
+
+        // The message cannot borrow from the fragments.
+        let mut parser = BertParser::with_limits(data, self.limits);
+        parser.strict = self.strict;
+        *parser.atom_cache_refs.borrow_mut() = refs;
+        // The message is complete: a term that needs more bytes is
+        // longer than the message, not cut short by the end of the
+        // input.
+        let len = parser.remaining();
+        let term = parser
+            .dist_body(0, len)
+            .map(BertTermRef::into_static)
+            .map_err(|e| {
+                e.map_root(|root| match root {
+                    BertError::NotEnoughData {
+                        offset: at, needed, ..
+                    } => BertError::InvalidFrameLength {
+                        offset,
+                        expected: len,
+                        actual: at + needed,
+                    },
+                    root => root,
+                })
+            });
+        self.warnings.borrow_mut().extend(parser.take_warnings());
+        return term;
+    }
+
+    // The hash of the encoding node, then an ordinary term.
+    fn local(&self) -> Result<Parsed<'_>> {
+        let start = self.pos.get();
+        let hash = self.eat_u32_be()?;
+        self.field(start, || format!("hash: 0x{:08x}", hash));
+        Ok(Parsed::Open(Frame::Local(hash, None)))
+    }
+
+    pub fn atom_cache_ref(&self) -> Result<BertTermRef<'_>> {
+        let offset = self.pos.get();
+        let index = self.eat_u8()?;
+        self.field(offset, || format!("index: {}", index));
+        match self.atom_cache_refs.borrow().get(index as usize) {
+            Some(Some(atom)) => Ok(BertTermRef::Atom(Cow::Owned(atom.clone()))),
+            _ => Err(BertError::InvalidAtomCacheRef { offset, index }),
+        }
+    }
+
+    pub fn bert_term(&self) -> Result<BertTerm> {
+        self.bert_term_ref().map(BertTermRef::into_owned)
+    }
+
+    pub fn bert_term_ref(&self) -> Result<BertTermRef<'_>> {
+        let depth = self.depth.get();
+        if depth == 0 {
+            self.elements.set(0);
+        }
+        let term = self.nested_terms(depth);
+        self.depth.set(depth);
+        return term;
+    }
+
+    // Containers are not parsed recursively: the containers whose
+    // elements are being parsed are kept on a stack, so that the
+    // depth of a term is only limited by memory and by the limits.
+    // When a term is complete, it is added to the container on top
+    // of the stack, which may complete it in turn. The stack is also
+    // the path to the term that an error is about.
+    fn nested_terms(&self, depth: usize) -> Result<BertTermRef<'_>> {
+        let mut stack: Vec<Frame<'_>> = Vec::new();
+        let path = |stack: &[Frame<'_>]| stack.iter().map(Frame::step).collect();
+        loop {
+            let initial_pos = self.pos.get();
+            if let Some(frame) = stack.last_mut() {
+                self.start_element(frame, initial_pos);
+            }
+            if depth + stack.len() >= self.limits.max_depth {
+                let error = BertError::DepthLimitExceeded {
+                    offset: initial_pos,
+                    limit: self.limits.max_depth,
+                };
+                return Err(error.at_path(path(&stack)));
+            }
+            self.depth.set(depth + stack.len() + 1);
+            let mut parsed = match self.term_start(initial_pos) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    let e = e.at_path(path(&stack));
+                    self.keep_partial(&e, stack);
+                    return Err(e);
+                }
+            };
+            if self.trace == Trace::Terms {
+                self.annotate(initial_pos, depth + stack.len(), &parsed);
+            }
+            loop {
+                match parsed {
+                    Parsed::Open(frame) if !frame.is_complete() => {
+                        stack.push(frame);
+                        break;
+                    }
+                    Parsed::Open(frame) => {
+                        if let Frame::Map(_, _, _, ref map_keys) = frame {
+                            self.check_map_keys(map_keys)
+                                .map_err(|e| e.at_path(path(&stack)))?;
+                        }
+                        parsed = Parsed::Term(frame.into_term());
+                    }
+                    Parsed::Term(term) => match stack.pop() {
+                        Some(mut frame) => {
+                            frame.push(term);
+                            parsed = Parsed::Open(frame);
+                        }
+                        None => return Ok(term),
+                    },
+                }
+            }
+        }
+    }
+
+    // Records where the keys of a map start and end: a key ends
+    // where its value starts.
+    fn start_element<'a>(&'a self, frame: &mut Frame<'a>, offset: usize) {
+        if let Frame::Map(ref keys, ref vals, _, ref mut map_keys) = *frame {
+            if keys.len() == vals.len() {
+                map_keys.start = offset;
+            } else {
+                let bytes = &self.contents[map_keys.start - self.base..offset - self.base];
+                map_keys.encodings.push((bytes, map_keys.start));
+            }
+        }
+    }
+
+    // Keys that are equal but encoded differently, e.g. as a Latin-1
+    // and a UTF-8 atom, are not found.
+    fn check_map_keys(&self, map_keys: &MapKeys<'_>) -> Result<()> {
+        let mut encodings = map_keys.encodings.clone();
+        encodings.sort_unstable();
+        for pair in encodings.windows(2) {
+            if pair[0].0 == pair[1].0 {
+                self.suspicious(BertWarning::DuplicateMapKey { offset: pair[1].1 })?;
+            }
+        }
+        return Ok(());
+    }
+
+    fn keep_partial(&self, error: &BertError, mut stack: Vec<Frame<'_>>) {
+        if !self.lenient || self.more_input {
+            return;
+        }
+        if let BertError::NotEnoughData { .. } = *error.root() {
+            let mut term = BertTermRef::Truncated;
+            while let Some(frame) = stack.pop() {
+                term = frame.into_partial(term);
+            }
+            if let Some(old) = self.partial.replace(Some(term.into_owned())) {
+                old.drop_deep();
+            }
+        }
+    }
+
+    // When parsing is lenient, parses what there is of a frame that
+    // goes past the end of the input, for `take_partial`.
+    fn salvage<T>(&self, parse: impl FnOnce() -> Result<T>) {
+        if self.lenient && !self.more_input {
+            let start = self.pos.get();
+            let _ = parse();
+            self.pos.set(start);
+        }
+    }
+
+    fn annotate(&self, offset: usize, depth: usize, parsed: &Parsed<'_>) {
+        let tag = self.contents[offset - self.base];
+        let name = tag_name(tag).unwrap_or("?");
+        let label = match *parsed {
+            Parsed::Open(Frame::Tuple(_, len)) => format!("{} arity={}", name, len),
+            Parsed::Open(Frame::List(_, len, _)) => format!("{} length={}", name, len),
+            Parsed::Open(Frame::Map(_, _, len, _)) => format!("{} arity={}", name, len),
+            Parsed::Open(Frame::Fun(_, num_free)) => format!("{} num_free={}", name, num_free),
+            Parsed::Open(Frame::Local(hash, _)) => format!("{} hash=0x{:08x}", name, hash),
+            Parsed::Term(_) => name.to_string(),
+        };
+        self.record(offset, depth, label);
+    }
+
+    fn record(&self, offset: usize, depth: usize, label: String) {
+        self.annotations.borrow_mut().push(Annotation {
+            offset,
+            len: self.pos.get() - offset,
+            depth,
+            label,
+        });
+    }
+
+    // Records the bytes from `start` as a field of the term being
+    // parsed, or of the framing outside of terms.
+    fn field<F: FnOnce() -> String>(&self, start: usize, label: F) {
+        if self.trace == Trace::Fields {
+            self.record(start, self.depth.get(), label());
+        }
+    }
+
+    // Reads an unsigned integer of 1, 2 or 4 bytes, recorded as the
+    // field `name`.
+    fn len_field(&self, size: u8, name: &str) -> Result<usize> {
+        let start = self.pos.get();
+        let n = match size {
+            1 => self.eat_u8()? as usize,
+            2 => self.eat_u16_be()? as usize,
+            _ => self.eat_u32_be()? as usize,
+        };
+        self.field(start, || format!("{}: {}", name, n));
+        return Ok(n);
+    }
+
+    // Parses a term, or only the header of a container.
+    fn term_start(&self, initial_pos: usize) -> Result<Parsed<'_>> {
+        let tag = self.eat_u8()?;
+        if self.trace == Trace::Fields {
+            let label = tag_name(tag).unwrap_or("invalid tag").to_string();
+            self.record(initial_pos, self.depth.get() - 1, label);
+        }
+        let term = match tag {
             SMALL_INTEGER_EXT => self.small_integer(),
             INTEGER_EXT => self.integer(),
             FLOAT_EXT => self.old_float(),
             NEW_FLOAT_EXT => self.new_float(),
             ATOM_EXT => {
-                let len = self.eat_u16_be()? as usize;
+                let len = self.len_field(2, "length")?;
                 self.atom(len)
             }
             SMALL_ATOM_EXT => {
-                let len = self.eat_u8()? as usize;
+                let len = self.len_field(1, "length")?;
                 self.atom(len)
             }
             ATOM_UTF8_EXT => {
-                let len = self.eat_u16_be()? as usize;
+                let len = self.len_field(2, "length")?;
                 self.atom_utf8(len)
             }
             SMALL_ATOM_UTF8_EXT => {
-                let len = self.eat_u8()? as usize;
+                let len = self.len_field(1, "length")?;
                 self.atom_utf8(len)
             }
             SMALL_TUPLE_EXT => {
-                let len = self.eat_u8()? as usize;
-                self.tuple(len)
+                let len = self.len_field(1, "arity")?;
+                return self.tuple(len);
             }
             LARGE_TUPLE_EXT => {
-                let len = self.eat_u32_be()? as usize;
-                self.tuple(len)
+                let len = self.len_field(4, "arity")?;
+                return self.tuple(len);
             }
-            NIL_EXT => Ok(BertTerm::Nil),
-            LIST_EXT => self.list(),
+            NIL_EXT => Ok(BertTermRef::Nil),
+            LIST_EXT => return self.list(),
             STRING_EXT => self.string(),
             BINARY_EXT => self.binary(),
+            BIT_BINARY_EXT => self.bit_binary(),
             SMALL_BIG_EXT => {
-                let len = self.eat_u8()?;
-                self.bigint(len as usize)
+                let len = self.len_field(1, "length")?;
+                self.bigint(len)
             }
             LARGE_BIG_EXT => {
-                let len = self.eat_u32_be()?;
-                self.bigint(len as usize)
+                let len = self.len_field(4, "length")?;
+                self.bigint(len)
             }
-            MAP_EXT => self.map(),
-            tag => Err(BertError::InvalidTag(initial_pos, tag)),
+            MAP_EXT => return self.map(),
+            COMPRESSED => self.compressed(),
+            PID_EXT => self.pid(false),
+            NEW_PID_EXT => self.pid(true),
+            PORT_EXT | NEW_PORT_EXT | V4_PORT_EXT => self.port(tag),
+            REFERENCE_EXT | NEW_REFERENCE_EXT | NEWER_REFERENCE_EXT => self.reference(tag),
+            NEW_FUN_EXT => return self.new_fun(),
+            FUN_EXT => return self.fun(),
+            EXPORT_EXT => self.export(),
+            ATOM_CACHE_REF => self.atom_cache_ref(),
+            LOCAL_EXT => return self.local(),
+            _ => Err(BertError::InvalidTag(initial_pos, tag)),
+        };
+        let term = term?;
+        self.check_leaf(tag, initial_pos, &term)?;
+        return Ok(Parsed::Term(term));
+    }
+
+    // Warns about leaves that `term_to_binary` does not produce, or
+    // only did in old releases.
+    fn check_leaf(&self, tag: u8, offset: usize, term: &BertTermRef<'_>) -> Result<()> {
+        match *term {
+            BertTermRef::Float(_) if tag == FLOAT_EXT => {
+                self.suspicious(BertWarning::OldFloat { offset })?;
+            }
+            BertTermRef::Atom(ref s) => {
+                if tag == ATOM_EXT || tag == SMALL_ATOM_EXT {
+                    self.suspicious(BertWarning::Latin1Atom { offset })?;
+                }
+                let length = s.chars().count();
+                if length > MAX_ATOM_CHARS {
+                    self.suspicious(BertWarning::LongAtom { offset, length })?;
+                }
+            }
+            BertTermRef::BigInt(ref n) => {
+                // The sign follows the tag and the length.
+                let sign_pos = offset + if tag == SMALL_BIG_EXT { 2 } else { 5 };
+                if n.is_zero() && self.contents[sign_pos - self.base] == 1 {
+                    self.suspicious(BertWarning::NegativeZero { offset })?;
+                } else if n.to_i32().is_some() {
+                    self.suspicious(BertWarning::SmallBigInt { offset })?;
+                }
+            }
+            _ => (),
         }
+        return Ok(());
     }
 
-    pub fn small_integer(&mut self) -> Result<BertTerm> {
+    pub fn small_integer(&self) -> Result<BertTermRef<'_>> {
+        let start = self.pos.get();
         let b = self.eat_u8()?;
-        Ok(BertTerm::Int(b as i32))
+        self.field(start, || format!("value: {}", b));
+        Ok(BertTermRef::Int(b as i32))
     }
 
-    pub fn integer(&mut self) -> Result<BertTerm> {
+    pub fn integer(&self) -> Result<BertTermRef<'_>> {
+        let start = self.pos.get();
         let n = self.eat_i32_be()?;
-        Ok(BertTerm::Int(n))
+        self.field(start, || format!("value: {}", n));
+        Ok(BertTermRef::Int(n))
     }
 
-    pub fn old_float(&mut self) -> Result<BertTerm> {
-        let initial_pos = self.pos;
-        let mut s = String::new();
-        while !self.eof() && self.peek()? != 0 {
-            s.push(self.eat_char()?);
-        }
-
-        while !self.eof() && self.peek()? == 0 {
-            let _ = self.eat_u8()?;
-        }
-
-        s.parse::<f64>()
-            .map_err(|_| BertError::InvalidFloat(initial_pos))
-            .map(|f| BertTerm::Float(f))
+    pub fn old_float(&self) -> Result<BertTermRef<'_>> {
+        // The float is printed in 31 bytes, padded with zeros; the
+        // last float of a complete input may be shorter.
+        let initial_pos = self.pos.get();
+        let len = if self.more_input {
+            31
+        } else {
+            usize::min(31, self.remaining())
+        };
+        let bytes = self.eat_slice(len)?;
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        self.field(initial_pos, || {
+            format!("value: {:?}", String::from_utf8_lossy(&bytes[..len]))
+        });
+        std::str::from_utf8(&bytes[..len])
+            .ok()
+            .and_then(|s| s.parse::<f64>().ok())
+            .map(BertTermRef::Float)
+            .ok_or(BertError::InvalidFloat(initial_pos))
     }
 
-    pub fn new_float(&mut self) -> Result<BertTerm> {
+    pub fn new_float(&self) -> Result<BertTermRef<'_>> {
+        let start = self.pos.get();
         let raw_bytes = self.eat_u64_be()?;
         let f: f64 = f64::from_bits(raw_bytes);
-        Ok(BertTerm::Float(f))
+        self.field(start, || format!("value: {:?}", f));
+        Ok(BertTermRef::Float(f))
     }
 
-    pub fn atom(&mut self, len: usize) -> Result<BertTerm> {
-        let initial_pos = self.pos;
-        let bytes: Vec<u8> = self.eat_slice(len)?.to_owned();
+    pub fn atom(&self, len: usize) -> Result<BertTermRef<'_>> {
+        let initial_pos = self.pos.get();
+        self.check_atom_length(initial_pos, len)?;
+        let bytes = self.eat_slice(len)?;
         let is_ascii = bytes.iter().all(|byte| *byte < 128);
+        self.field(initial_pos, || {
+            let s: String = bytes.iter().map(|&b| b as char).collect();
+            format!("Latin-1 text: {:?}", s)
+        });
 
         // Optimization: ASCII atoms represent the overwhelming
         // majority of use cases of atoms. When we read the bytes
         // of the atom, we record whether they are all ASCII
         // (i.e., small than 128); if it's the case, we don't
-        // need to bother with latin-1 decoding. We use an unsafe
-        // method because ASCII strings are guaranteed to be valid
-        // UTF-8 strings.
+        // need to bother with latin-1 decoding and the atom can
+        // borrow the input. We use an unsafe method because ASCII
+        // strings are guaranteed to be valid UTF-8 strings.
         if is_ascii {
-            let s = unsafe { String::from_utf8_unchecked(bytes) };
-            Ok(BertTerm::Atom(s))
+            let s = unsafe { std::str::from_utf8_unchecked(bytes) };
+            Ok(BertTermRef::Atom(Cow::Borrowed(s)))
         } else {
             ISO_8859_1
-                .decode(&bytes, DecoderTrap::Strict)
-                .map(|s| BertTerm::Atom(s))
+                .decode(bytes, DecoderTrap::Strict)
+                .map(|s| BertTermRef::Atom(Cow::Owned(s)))
                 .map_err(|_| BertError::InvalidLatin1Atom(initial_pos))
         }
     }
 
-    pub fn atom_utf8(&mut self, len: usize) -> Result<BertTerm> {
-        let initial_pos = self.pos;
-        let buf: Vec<u8> = self.eat_slice(len)?.to_owned();
-        String::from_utf8(buf)
-            .map(|s| BertTerm::Atom(s))
+    pub fn atom_utf8(&self, len: usize) -> Result<BertTermRef<'_>> {
+        let initial_pos = self.pos.get();
+        self.check_atom_length(initial_pos, len)?;
+        let bytes = self.eat_slice(len)?;
+        self.field(initial_pos, || {
+            format!("UTF-8 text: {:?}", String::from_utf8_lossy(bytes))
+        });
+        std::str::from_utf8(bytes)
+            .map(|s| BertTermRef::Atom(Cow::Borrowed(s)))
             .map_err(|_| BertError::InvalidUTF8Atom(initial_pos))
     }
 
-    pub fn tuple(&mut self, len: usize) -> Result<BertTerm> {
-        let mut terms = Vec::with_capacity(len);
-        for _ in 0..len {
-            terms.push(self.bert_term()?);
-        }
-        Ok(BertTerm::Tuple(terms))
+    fn tuple(&self, len: usize) -> Result<Parsed<'_>> {
+        let terms = Vec::with_capacity(self.reserve(len)?);
+        Ok(Parsed::Open(Frame::Tuple(terms, len)))
     }
 
-    pub fn string(&mut self) -> Result<BertTerm> {
-        let len = self.eat_u16_be()? as usize;
-        let bytes: Vec<u8> = self.eat_slice(len)?.to_owned();
-        Ok(BertTerm::String(bytes))
+    pub fn string(&self) -> Result<BertTermRef<'_>> {
+        let len = self.len_field(2, "length")?;
+        self.check_binary_size(len)?;
+        let start = self.pos.get();
+        let bytes = self.eat_slice(len)?;
+        self.field(start, || "characters".to_string());
+        Ok(BertTermRef::String(Cow::Borrowed(bytes)))
     }
 
-    pub fn binary(&mut self) -> Result<BertTerm> {
-        let len = self.eat_u32_be()? as usize;
-        let bytes: Vec<u8> = self.eat_slice(len)?.to_owned();
-        Ok(BertTerm::Binary(bytes))
+    pub fn binary(&self) -> Result<BertTermRef<'_>> {
+        let len = self.len_field(4, "length")?;
+        self.check_binary_size(len)?;
+        let start = self.pos.get();
+        let bytes = self.eat_slice(len)?;
+        self.field(start, || "data".to_string());
+        Ok(BertTermRef::Binary(Cow::Borrowed(bytes)))
     }
 
-    pub fn list(&mut self) -> Result<BertTerm> {
-        let len = self.eat_u32_be()?;
-        let mut terms = Vec::with_capacity(len as usize + 1);
-        for _ in 0..len {
-            terms.push(self.bert_term()?);
+    pub fn bit_binary(&self) -> Result<BertTermRef<'_>> {
+        let len = self.len_field(4, "length")?;
+        self.check_binary_size(len)?;
+        let bits_pos = self.pos.get();
+        let bits = self.len_field(1, "bits in the last byte")? as u8;
+        if bits > 8 || (bits == 0) != (len == 0) {
+            return Err(BertError::InvalidBitBinary {
+                offset: bits_pos,
+                bits,
+            });
         }
-        let tail = self.bert_term()?;
-        match tail {
-            BertTerm::Nil => (),
-            last_term => {
-                terms.push(last_term);
-            }
-        };
-        Ok(BertTerm::List(terms))
+        let start = self.pos.get();
+        let bytes = self.eat_slice(len)?;
+        self.field(start, || "data".to_string());
+        Ok(BertTermRef::BitBinary(Cow::Borrowed(bytes), bits))
+    }
+
+    fn list(&self) -> Result<Parsed<'_>> {
+        let len = self.len_field(4, "length")?;
+        let terms = Vec::with_capacity(self.reserve(len)?);
+        Ok(Parsed::Open(Frame::List(terms, len, None)))
     }
 
-    pub fn bigint(&mut self, len: usize) -> Result<BertTerm> {
+    pub fn bigint(&self, len: usize) -> Result<BertTermRef<'_>> {
+        self.check_binary_size(len)?;
+        let start = self.pos.get();
         let sign = self.eat_u8()?;
+        self.field(start, || {
+            let sign = match sign {
+                0 => "positive",
+                1 => "negative",
+                _ => "invalid",
+            };
+            format!("sign: {}", sign)
+        });
+        let start = self.pos.get();
         let mut sum: BigInt = Zero::zero();
         let mut pos: BigInt = One::one();
         for _ in 0..len {
             let d = self.eat_u8()?;
             let t = &pos * &(d.to_bigint().unwrap());
-            sum = sum + &t;
-            pos = pos * (256).to_bigint().unwrap();
+            sum += &t;
+            pos *= (256).to_bigint().unwrap();
         }
+        self.field(start, || format!("digits, little-endian: {}", sum));
         if sign == 1 {
             sum = -sum;
         }
-        Ok(BertTerm::BigInt(sum))
+        Ok(BertTermRef::BigInt(sum))
+    }
+
+    // Duplicate keys are found once the map is complete, see
+    // `check_map_keys`.
+    fn map(&self) -> Result<Parsed<'_>> {
+        let len = self.len_field(4, "arity")?;
+        let capacity = self.reserve(len.saturating_mul(2))? / 2;
+        let keys = Vec::with_capacity(capacity);
+        let vals = Vec::with_capacity(capacity);
+        let map_keys = MapKeys {
+            encodings: Vec::with_capacity(capacity),
+            start: 0,
+        };
+        Ok(Parsed::Open(Frame::Map(keys, vals, len, map_keys)))
+    }
+
+    // term_to_binary(T, [compressed]) output: the size of the
+    // uncompressed term followed by a zlib stream of the term,
+    // without the magic number.
+    pub fn compressed(&self) -> Result<BertTermRef<'_>> {
+        let size_pos = self.pos.get();
+        if self.inflated {
+            return Err(BertError::NestedCompressedTerm(size_pos - 1));
+        }
+        let size = self.len_field(4, "uncompressed size")?;
+        self.check_binary_size(size)?;
+        let data_pos = self.pos.get();
+        let data = self.rest();
+
+        // Never inflate more than one byte past the declared size:
+        // that is enough to detect a mismatch.
+        let mut decoder = ZlibDecoder::new(data);
+        let mut inflated: Vec<u8> = Vec::new();
+        let res = (&mut decoder)
+            .take(size as u64 + 1)
+            .read_to_end(&mut inflated);
+        let consumed = decoder.total_in() as usize;
+
+        // The stream is cut short, possibly in its checksum after
+        // the last inflated byte.
+        let truncated = matches!(res, Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof);
+        if truncated {
+            return Err(BertError::NotEnoughData {
+                offset: data_pos,
+                needed: consumed + 1,
+                available: data.len(),
+            });
+        }
+        if res.is_err() {
+            return Err(BertError::InvalidCompressedData(data_pos));
+        }
+        if inflated.len() != size {
+            return Err(BertError::InvalidCompressedSize {
+                offset: size_pos,
+                expected: size,
+                actual: inflated.len(),
+            });
+        }
+        self.pos.set(self.pos.get() + consumed);
+        self.field(data_pos, || "zlib stream".to_string());
+
+        // The inflated term cannot borrow from the input; it is
+        // still part of the current term for the limits.
+        let mut parser = BertParser::with_limits(inflated, self.limits);
+        parser.strict = self.strict;
+        parser.inflated = true;
+        *parser.atom_cache_refs.borrow_mut() = self.atom_cache_refs.borrow().clone();
+        parser.depth.set(self.depth.get());
+        parser.elements.set(self.elements.get());
+        // The inflated bytes are complete: a term that needs more
+        // is invalid, not cut short by the end of the input.
+        let term = parser
+            .bert_term_ref()
+            .map(BertTermRef::into_static)
+            .map_err(|e| {
+                e.map_root(|root| match root {
+                    BertError::NotEnoughData {
+                        offset,
+                        needed,
+                        available,
+                    } => BertError::TruncatedCompressedTerm {
+                        offset,
+                        needed,
+                        available,
+                    },
+                    root => root,
+                })
+                .at_path(vec![PathStep::Compressed])
+            });
+        self.elements.set(parser.elements.get());
+        self.warnings.borrow_mut().extend(parser.take_warnings());
+        term
     }
 
-    // TODO(vfoley): ensure no duplicate keys
-    pub fn map(&mut self) -> Result<BertTerm> {
-        let len = self.eat_u32_be()? as usize;
-        let mut keys = Vec::with_capacity(len);
-        let mut vals = Vec::with_capacity(len);
+    pub fn pid(&self, wide_creation: bool) -> Result<BertTermRef<'_>> {
+        let node = self.node()?;
+        let id = self.len_field(4, "id")? as u32;
+        let serial = self.len_field(4, "serial")? as u32;
+        let creation = self.creation(wide_creation)?;
+        Ok(BertTermRef::Pid {
+            node,
+            id,
+            serial,
+            creation,
+        })
+    }
+
+    pub fn port(&self, tag: u8) -> Result<BertTermRef<'_>> {
+        let node = self.node()?;
+        let start = self.pos.get();
+        let id = if tag == V4_PORT_EXT {
+            self.eat_u64_be()?
+        } else {
+            self.eat_u32_be()? as u64
+        };
+        self.field(start, || format!("id: {}", id));
+        let creation = self.creation(tag != PORT_EXT)?;
+        Ok(BertTermRef::Port { node, id, creation })
+    }
+
+    pub fn reference(&self, tag: u8) -> Result<BertTermRef<'_>> {
+        // REFERENCE_EXT is the only encoding that puts the ID
+        // after the node and has no length field.
+        if tag == REFERENCE_EXT {
+            let node = self.node()?;
+            let id = self.len_field(4, "id")? as u32;
+            let creation = self.creation(false)?;
+            return Ok(BertTermRef::Reference {
+                node,
+                creation,
+                ids: vec![id],
+            });
+        }
+
+        let len = self.len_field(2, "number of ids")?;
+        let node = self.node()?;
+        let creation = self.creation(tag == NEWER_REFERENCE_EXT)?;
+        let mut ids = Vec::with_capacity(len);
         for _ in 0..len {
-            keys.push(self.bert_term()?);
-            vals.push(self.bert_term()?);
+            ids.push(self.len_field(4, "id")? as u32);
+        }
+        Ok(BertTermRef::Reference {
+            node,
+            creation,
+            ids,
+        })
+    }
+
+    fn new_fun(&self) -> Result<Parsed<'_>> {
+        let _size = self.len_field(4, "size")?;
+        let arity = self.len_field(1, "arity")? as u8;
+        let start = self.pos.get();
+        let mut uniq = [0; 16];
+        uniq.copy_from_slice(self.eat_slice(16)?);
+        self.field(start, || "uniq (MD5)".to_string());
+        let index = self.len_field(4, "index")? as u32;
+        let num_free = self.len_field(4, "free variables")? as u32;
+        let module = self.atom_field(BertError::InvalidFun)?;
+        let old_index = self.int_field(BertError::InvalidFun)?;
+        let old_uniq = self.int_field(BertError::InvalidFun)?;
+        let pid = self.pid_field()?;
+        let free_vars = self.free_vars(num_free)?;
+        let fun = BertFunRef {
+            module,
+            arity: Some(arity),
+            uniq,
+            index,
+            old_index,
+            old_uniq,
+            pid,
+            free_vars,
+        };
+        Ok(Parsed::Open(Frame::Fun(Box::new(fun), num_free as usize)))
+    }
+
+    fn fun(&self) -> Result<Parsed<'_>> {
+        let num_free = self.len_field(4, "free variables")? as u32;
+        let pid = self.pid_field()?;
+        let module = self.atom_field(BertError::InvalidFun)?;
+        let index = self.int_field(BertError::InvalidFun)?;
+        let uniq = self.int_field(BertError::InvalidFun)?;
+        let free_vars = self.free_vars(num_free)?;
+        let fun = BertFunRef {
+            module,
+            arity: None,
+            uniq: [0; 16],
+            index: index as u32,
+            old_index: index,
+            old_uniq: uniq,
+            pid,
+            free_vars,
+        };
+        Ok(Parsed::Open(Frame::Fun(Box::new(fun), num_free as usize)))
+    }
+
+    pub fn export(&self) -> Result<BertTermRef<'_>> {
+        let module = self.atom_field(BertError::InvalidFun)?;
+        let function = self.atom_field(BertError::InvalidFun)?;
+        let initial_pos = self.pos.get();
+        let arity = self.int_field(BertError::InvalidFun)?;
+        if !(0..=255).contains(&arity) {
+            return Err(BertError::InvalidFun(initial_pos));
+        }
+        Ok(BertTermRef::Export {
+            module,
+            function,
+            arity: arity as u8,
+        })
+    }
+
+    // The free variables are parsed as the elements of the fun.
+    fn free_vars(&self, num_free: u32) -> Result<Vec<BertTermRef<'_>>> {
+        Ok(Vec::with_capacity(self.reserve(num_free as usize)?))
+    }
+
+    // The fields of funs, pids, ports and references have a single
+    // valid tag, which is checked first so that an invalid field is
+    // not parsed in full; it could be a term as deep as the input.
+    fn pid_field(&self) -> Result<BertTermRef<'_>> {
+        let initial_pos = self.pos.get();
+        if !matches!(self.peek()?, PID_EXT | NEW_PID_EXT) {
+            return Err(BertError::InvalidFun(initial_pos));
+        }
+        match self.bert_term_ref()? {
+            pid @ BertTermRef::Pid { .. } => Ok(pid),
+            _ => Err(BertError::InvalidFun(initial_pos)),
+        }
+    }
+
+    // The node of a pid, port or reference is always encoded as an atom.
+    fn node(&self) -> Result<Cow<'_, str>> {
+        self.atom_field(BertError::InvalidNode)
+    }
+
+    // Parses a term that must be an atom; `err` builds the
+    // error reported at the term's offset if it is not.
+    fn atom_field(&self, err: fn(usize) -> BertError) -> Result<Cow<'_, str>> {
+        let initial_pos = self.pos.get();
+        if !matches!(
+            self.peek()?,
+            ATOM_EXT | SMALL_ATOM_EXT | ATOM_UTF8_EXT | SMALL_ATOM_UTF8_EXT | ATOM_CACHE_REF
+        ) {
+            return Err(err(initial_pos));
+        }
+        match self.bert_term_ref()? {
+            BertTermRef::Atom(ref mut s) => Ok(mem::take(s)),
+            _ => Err(err(initial_pos)),
+        }
+    }
+
+    fn int_field(&self, err: fn(usize) -> BertError) -> Result<i32> {
+        let initial_pos = self.pos.get();
+        if !matches!(self.peek()?, SMALL_INTEGER_EXT | INTEGER_EXT) {
+            return Err(err(initial_pos));
+        }
+        match self.bert_term_ref()? {
+            BertTermRef::Int(n) => Ok(n),
+            _ => Err(err(initial_pos)),
+        }
+    }
+
+    // Counts `n` more elements against the limit and returns how
+    // many can be preallocated: every element takes at least one
+    // byte, so a length larger than the input is not trusted.
+    fn reserve(&self, n: usize) -> Result<usize> {
+        let elements = self.elements.get().saturating_add(n);
+        if elements > self.limits.max_elements {
+            return Err(BertError::ElementLimitExceeded {
+                offset: self.pos.get(),
+                limit: self.limits.max_elements,
+            });
         }
-        Ok(BertTerm::Map(keys, vals))
+        self.elements.set(elements);
+        return Ok(usize::min(n, self.remaining()));
+    }
+
+    // Checked before reading the bytes, so that a stream parser
+    // does not wait for them.
+    fn check_binary_size(&self, size: usize) -> Result<()> {
+        if size > self.limits.max_binary_size {
+            return Err(BertError::BinaryLimitExceeded {
+                offset: self.pos.get(),
+                size,
+                limit: self.limits.max_binary_size,
+            });
+        }
+        return Ok(());
+    }
+
+    fn check_atom_length(&self, offset: usize, length: usize) -> Result<()> {
+        if length > self.limits.max_atom_length {
+            return Err(BertError::AtomLimitExceeded {
+                offset,
+                length,
+                limit: self.limits.max_atom_length,
+            });
+        }
+        return Ok(());
+    }
+
+    // Older encodings store the creation in a single byte.
+    fn creation(&self, wide: bool) -> Result<u32> {
+        let size = if wide { 4 } else { 1 };
+        self.len_field(size, "creation").map(|n| n as u32)
     }
 
     // Low-level parsing methods
     pub fn eof(&self) -> bool {
-        self.pos >= self.contents.len()
+        self.rest().is_empty()
     }
 
     pub fn peek(&self) -> Result<u8> {
         if self.eof() {
             return Err(BertError::NotEnoughData {
-                offset: self.pos,
+                offset: self.pos.get(),
                 needed: 1,
                 available: 0,
             });
         } else {
-            return Ok(self.contents[self.pos]);
+            return Ok(self.rest()[0]);
         }
     }
 
     pub fn can_read(&self, n: usize) -> bool {
-        (self.pos + n) <= self.contents.len()
+        n <= self.remaining()
     }
 
-    pub fn eat_slice(&mut self, len: usize) -> Result<&[u8]> {
+    pub fn eat_slice(&self, len: usize) -> Result<&[u8]> {
         if !self.can_read(len) {
             return Err(BertError::NotEnoughData {
-                offset: self.pos,
+                offset: self.pos.get(),
                 needed: len,
-                available: self.contents.len() - self.pos,
+                available: self.remaining(),
             });
         }
-        let slice = &self.contents[self.pos..self.pos + len];
-        self.pos += len;
+        let start = self.pos.get() - self.base;
+        let slice = &self.contents[start..start + len];
+        self.pos.set(self.pos.get() + len);
         return Ok(slice);
     }
 
-    pub fn eat_u8(&mut self) -> Result<u8> {
+    pub fn eat_u8(&self) -> Result<u8> {
         let b = self.peek()?;
-        self.pos += 1;
+        self.pos.set(self.pos.get() + 1);
         return Ok(b);
     }
 
-    pub fn eat_char(&mut self) -> Result<char> {
+    pub fn eat_char(&self) -> Result<char> {
         let b = self.eat_u8()?;
         return Ok(b as char);
     }
 
-    pub fn eat_u16_be(&mut self) -> Result<u16> {
+    pub fn eat_u16_be(&self) -> Result<u16> {
         let mut bytes = self.eat_slice(2)?;
         let n = bytes.read_u16::<BigEndian>()?;
         return Ok(n);
     }
 
-    pub fn eat_i32_be(&mut self) -> Result<i32> {
+    pub fn eat_i32_be(&self) -> Result<i32> {
         let mut bytes = self.eat_slice(4)?;
         let n = bytes.read_i32::<BigEndian>()?;
         return Ok(n);
     }
 
-    pub fn eat_u32_be(&mut self) -> Result<u32> {
+    pub fn eat_u32_be(&self) -> Result<u32> {
         let mut bytes = self.eat_slice(4)?;
         let n = bytes.read_u32::<BigEndian>()?;
         return Ok(n);
     }
 
-    pub fn eat_u64_be(&mut self) -> Result<u64> {
+    pub fn eat_u64_be(&self) -> Result<u64> {
         let mut bytes = self.eat_slice(8)?;
         let n = bytes.read_u64::<BigEndian>()?;
         return Ok(n);
     }
 
     // https://developers.google.com/protocol-buffers/docs/encoding#varints
-    pub fn parse_varint(&mut self) -> Result<u64> {
+    pub fn parse_varint(&self) -> Result<u64> {
         const MAX_LEN: u64 = 8;
-        let start_pos = self.pos;
+        let start_pos = self.pos.get();
         let mut i: u64 = 0;
         let mut val: u64 = 0;
 
-        while !self.eof() && i < MAX_LEN {
+        while i < MAX_LEN {
             let b = self.eat_u8()?;
-            val = val | ((b as u64 & 0x7f) << (7 * i));
+            val |= (b as u64 & 0x7f) << (7 * i);
             if b & 0x80 == 0 {
                 break;
             }
@@ -385,21 +1752,23 @@ impl BertParser {
     }
 }
 
+fn owned(next: Option<Result<BertTermRef<'_>>>) -> Option<Result<BertTerm>> {
+    next.map(|res| res.map(BertTermRef::into_owned))
+}
+
 #[test]
 fn test_varint() {
-    assert_eq!(1, {
-        match BertParser::new(vec![1]).parse_varint() {
-            Ok(x) => x,
-            Err(_) => u64::max_value(),
-        }
-    });
+    assert_eq!(
+        1,
+        BertParser::new(vec![1]).parse_varint().unwrap_or(u64::MAX)
+    );
 
-    assert_eq!(300, {
-        match BertParser::new(vec![0b1010_1100, 0b0000_0010]).parse_varint() {
-            Ok(x) => x,
-            Err(_) => u64::max_value(),
-        }
-    });
+    assert_eq!(
+        300,
+        BertParser::new(vec![0b1010_1100, 0b0000_0010])
+            .parse_varint()
+            .unwrap_or(u64::MAX)
+    );
 
     assert!(
         BertParser::new(vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f])
@@ -0,0 +1,529 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+use crate::parser::{BertParser, ParserLimits};
+use crate::prelude::*;
+
+const PCAP_MAGIC_USEC: u32 = 0xa1b2c3d4;
+const PCAP_MAGIC_NSEC: u32 = 0xa1b23c4d;
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const PCAPNG_IF_TSRESOL: u16 = 9;
+
+const LINKTYPE_NULL: u16 = 0;
+const LINKTYPE_ETHERNET: u16 = 1;
+const LINKTYPE_RAW: u16 = 101;
+const LINKTYPE_LINUX_SLL: u16 = 113;
+const LINKTYPE_LINUX_SLL2: u16 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const IPPROTO_TCP: u8 = 6;
+const TCP_SYN: u8 = 0x02;
+
+const EPMD_PORT: u16 = 4369;
+
+/// A distribution message found in a capture.
+#[derive(Debug)]
+pub struct DistMessage {
+    /// The capture time of the packet that completed the message,
+    /// since the Unix epoch.
+    pub time: Duration,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    /// The node names, if the capture has the connection handshake.
+    pub src_node: Option<String>,
+    pub dst_node: Option<String>,
+    /// A tuple of the control message and its payload, as returned
+    /// by `BertParser::dist_message`.
+    pub message: Result<BertTerm>,
+}
+
+/// Decodes the distribution messages of the TCP connections in a
+/// pcap or pcapng capture, in the order in which they were
+/// completed.
+///
+/// With a `port`, only the connections to or from it are decoded.
+/// Otherwise, the connections are recognized by their handshake or
+/// by their first message.
+pub fn dist_messages(capture: &[u8], port: Option<u16>) -> Result<Vec<DistMessage>> {
+    return dist_messages_with_limits(capture, port, ParserLimits::default());
+}
+
+/// Like `dist_messages`, with `limits` on the decoded terms.
+pub fn dist_messages_with_limits(
+    capture: &[u8],
+    port: Option<u16>,
+    limits: ParserLimits,
+) -> Result<Vec<DistMessage>> {
+    let mut streams = DistStreams {
+        port,
+        limits,
+        directions: HashMap::new(),
+        messages: Vec::new(),
+    };
+    for packet in packets(capture)? {
+        if let Some(segment) = tcp_segment(packet.linktype, packet.data) {
+            streams.segment(packet.time, segment);
+        }
+    }
+    return Ok(streams.messages);
+}
+
+struct Packet<'a> {
+    time: Duration,
+    linktype: u16,
+    data: &'a [u8],
+}
+
+// Reads the fields of a capture file in its byte order.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn slice(&self, offset: usize, len: usize) -> Result<&'a [u8]> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.bytes.len() => Ok(&self.bytes[offset..end]),
+            _ => Err(BertError::InvalidCapture(offset)),
+        }
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16> {
+        let bytes = self.slice(offset, 2)?;
+        if self.little_endian {
+            return Ok(LittleEndian::read_u16(bytes));
+        }
+        return Ok(BigEndian::read_u16(bytes));
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32> {
+        let bytes = self.slice(offset, 4)?;
+        if self.little_endian {
+            return Ok(LittleEndian::read_u32(bytes));
+        }
+        return Ok(BigEndian::read_u32(bytes));
+    }
+}
+
+fn packets(capture: &[u8]) -> Result<Vec<Packet<'_>>> {
+    let mut r = Reader {
+        bytes: capture,
+        little_endian: true,
+    };
+    let magic = r.u32(0)?;
+    if magic == PCAPNG_SECTION_HEADER {
+        return pcapng_packets(r);
+    }
+    r.little_endian = magic == PCAP_MAGIC_USEC || magic == PCAP_MAGIC_NSEC;
+    let magic = r.u32(0)?;
+    let nanos = match magic {
+        PCAP_MAGIC_USEC => false,
+        PCAP_MAGIC_NSEC => true,
+        _ => return Err(BertError::InvalidCapture(0)),
+    };
+    let linktype = r.u32(20)? as u16;
+
+    let mut packets = Vec::new();
+    let mut offset = 24;
+    while offset < capture.len() {
+        let secs = r.u32(offset)? as u64;
+        let frac = r.u32(offset + 4)?;
+        let len = r.u32(offset + 8)? as usize;
+        let frac = if nanos {
+            frac
+        } else {
+            frac.saturating_mul(1000)
+        };
+        packets.push(Packet {
+            time: Duration::new(secs, 0) + Duration::from_nanos(frac as u64),
+            linktype,
+            data: r.slice(offset + 16, len)?,
+        });
+        offset += 16 + len;
+    }
+    return Ok(packets);
+}
+
+// A pcapng file is a sequence of blocks: `<<Type:32, Length:32,
+// Body, Length:32>>`, in the byte order of their section.
+fn pcapng_packets(mut r: Reader<'_>) -> Result<Vec<Packet<'_>>> {
+    // The link type and timestamp units per second of every
+    // interface of the current section.
+    let mut interfaces: Vec<(u16, u64)> = Vec::new();
+    let mut packets = Vec::new();
+    let mut offset = 0;
+    while offset < r.bytes.len() {
+        let block_type = r.u32(offset)?;
+        if block_type == PCAPNG_SECTION_HEADER {
+            r.little_endian = true;
+            match r.u32(offset + 8)? {
+                PCAPNG_BYTE_ORDER_MAGIC => (),
+                m if m.swap_bytes() == PCAPNG_BYTE_ORDER_MAGIC => r.little_endian = false,
+                _ => return Err(BertError::InvalidCapture(offset + 8)),
+            }
+            interfaces.clear();
+        }
+        let len = r.u32(offset + 4)? as usize;
+        if len < 12 || len % 4 != 0 {
+            return Err(BertError::InvalidCapture(offset + 4));
+        }
+        let body = offset + 8;
+        r.slice(offset, len)?;
+
+        match block_type {
+            PCAPNG_INTERFACE_DESCRIPTION => {
+                let linktype = r.u16(body)?;
+                interfaces.push((linktype, if_tsresol(&r, body + 8, offset + len - 4)?));
+            }
+            PCAPNG_ENHANCED_PACKET => {
+                let interface = r.u32(body)? as usize;
+                let (linktype, units) = match interfaces.get(interface) {
+                    Some(&interface) => interface,
+                    None => return Err(BertError::InvalidCapture(body)),
+                };
+                let ts = (r.u32(body + 4)? as u64) << 32 | r.u32(body + 8)? as u64;
+                let caplen = r.u32(body + 12)? as usize;
+                let nanos = (ts % units) as u128 * 1_000_000_000;
+                packets.push(Packet {
+                    time: Duration::new(ts / units, (nanos / units as u128) as u32),
+                    linktype,
+                    data: r.slice(body + 20, caplen)?,
+                });
+            }
+            PCAPNG_SIMPLE_PACKET => {
+                let linktype = match interfaces.first() {
+                    Some(&(linktype, _)) => linktype,
+                    None => return Err(BertError::InvalidCapture(body)),
+                };
+                let caplen = usize::min(r.u32(body)? as usize, len - 16);
+                packets.push(Packet {
+                    time: Duration::new(0, 0),
+                    linktype,
+                    data: r.slice(body + 4, caplen)?,
+                });
+            }
+            _ => (),
+        }
+        offset += len;
+    }
+    return Ok(packets);
+}
+
+// Returns the timestamp units per second from the options of an
+// interface description block; the default is microseconds.
+fn if_tsresol(r: &Reader<'_>, mut offset: usize, end: usize) -> Result<u64> {
+    while offset + 4 <= end {
+        let code = r.u16(offset)?;
+        let len = r.u16(offset + 2)? as usize;
+        if code == 0 {
+            break;
+        }
+        if code == PCAPNG_IF_TSRESOL && len == 1 {
+            let v = r.slice(offset + 4, 1)?[0];
+            let units = if v & 0x80 == 0 {
+                10u64.checked_pow(v as u32)
+            } else {
+                1u64.checked_shl((v & 0x7f) as u32)
+            };
+            return units.ok_or(BertError::InvalidCapture(offset + 4));
+        }
+        offset += 4 + len.div_ceil(4) * 4;
+    }
+    return Ok(1_000_000);
+}
+
+struct Segment<'a> {
+    src: SocketAddr,
+    dst: SocketAddr,
+    seq: u32,
+    syn: bool,
+    payload: &'a [u8],
+}
+
+// Returns the TCP segment in a link-layer frame, if there is one.
+// Frames that cannot be decoded are not part of a dist connection.
+fn tcp_segment(linktype: u16, frame: &[u8]) -> Option<Segment<'_>> {
+    let ip = match linktype {
+        LINKTYPE_NULL => frame.get(4..)?,
+        LINKTYPE_RAW => frame,
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = u16::from_be_bytes([*frame.get(offset)?, *frame.get(offset + 1)?]);
+            while ethertype == ETHERTYPE_VLAN {
+                offset += 4;
+                ethertype = u16::from_be_bytes([*frame.get(offset)?, *frame.get(offset + 1)?]);
+            }
+            if ethertype != ETHERTYPE_IPV4 && ethertype != ETHERTYPE_IPV6 {
+                return None;
+            }
+            frame.get(offset + 2..)?
+        }
+        LINKTYPE_LINUX_SLL => frame.get(16..)?,
+        LINKTYPE_LINUX_SLL2 => frame.get(20..)?,
+        _ => return None,
+    };
+
+    let (src, dst, tcp) = match ip.first()? >> 4 {
+        4 => {
+            let header_len = (ip[0] & 0x0f) as usize * 4;
+            let total_len = u16::from_be_bytes([*ip.get(2)?, *ip.get(3)?]) as usize;
+            if *ip.get(9)? != IPPROTO_TCP {
+                return None;
+            }
+            let src: [u8; 4] = ip.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = ip.get(16..20)?.try_into().ok()?;
+            (
+                IpAddr::from(Ipv4Addr::from(src)),
+                IpAddr::from(Ipv4Addr::from(dst)),
+                ip.get(header_len..usize::min(total_len, ip.len()))?,
+            )
+        }
+        6 => {
+            let payload_len = u16::from_be_bytes([*ip.get(4)?, *ip.get(5)?]) as usize;
+            if *ip.get(6)? != IPPROTO_TCP {
+                return None;
+            }
+            let src: [u8; 16] = ip.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = ip.get(24..40)?.try_into().ok()?;
+            (
+                IpAddr::from(Ipv6Addr::from(src)),
+                IpAddr::from(Ipv6Addr::from(dst)),
+                ip.get(40..usize::min(40 + payload_len, ip.len()))?,
+            )
+        }
+        _ => return None,
+    };
+
+    let header_len = (*tcp.get(12)? >> 4) as usize * 4;
+    return Some(Segment {
+        src: SocketAddr::new(src, u16::from_be_bytes([tcp[0], tcp[1]])),
+        dst: SocketAddr::new(dst, u16::from_be_bytes([tcp[2], tcp[3]])),
+        seq: u32::from_be_bytes([tcp[4], tcp[5], tcp[6], tcp[7]]),
+        syn: tcp[13] & TCP_SYN != 0,
+        payload: tcp.get(header_len..)?,
+    });
+}
+
+#[derive(PartialEq)]
+enum State {
+    // No data yet.
+    Start,
+    // Reading the handshake, whose messages have a 2-byte length.
+    // The acceptor starts with a status message.
+    Handshake { acceptor: bool },
+    // Reading distribution messages, with a 4-byte length.
+    Connected,
+    // Not a dist connection, or it could not be decoded further.
+    Ignored,
+}
+
+// One direction of a TCP connection.
+struct Direction {
+    next_seq: Option<u32>,
+    // Segments received before the ones that precede them.
+    out_of_order: Vec<(u32, Vec<u8>)>,
+    state: State,
+    handshake: Vec<u8>,
+    // The parser keeps the atom cache of the sending node.
+    parser: BertParser,
+    node: Option<String>,
+}
+
+struct DistStreams {
+    port: Option<u16>,
+    limits: ParserLimits,
+    directions: HashMap<(SocketAddr, SocketAddr), Direction>,
+    messages: Vec<DistMessage>,
+}
+
+impl DistStreams {
+    fn segment(&mut self, time: Duration, segment: Segment<'_>) {
+        let ports = [segment.src.port(), segment.dst.port()];
+        match self.port {
+            Some(port) if !ports.contains(&port) => return,
+            None if ports.contains(&EPMD_PORT) => return,
+            _ => (),
+        }
+
+        let key = (segment.src, segment.dst);
+        let limits = self.limits;
+        let dir = self.directions.entry(key).or_insert_with(|| Direction {
+            next_seq: None,
+            out_of_order: Vec::new(),
+            state: State::Start,
+            handshake: Vec::new(),
+            parser: BertParser::with_limits(Vec::new(), limits),
+            node: None,
+        });
+        let data = dir.reassemble(&segment);
+        if data.is_empty() || dir.state == State::Ignored {
+            return;
+        }
+        let explicit_port = self.port.is_some();
+        let terms = dir.receive(&data, explicit_port);
+
+        let peer = self.directions.get(&(segment.dst, segment.src));
+        let src_node = self.directions[&key].node.clone();
+        let dst_node = peer.and_then(|dir| dir.node.clone());
+        for message in terms {
+            self.messages.push(DistMessage {
+                time,
+                src: segment.src,
+                dst: segment.dst,
+                src_node: src_node.clone(),
+                dst_node: dst_node.clone(),
+                message,
+            });
+        }
+    }
+}
+
+impl Direction {
+    // Returns the bytes that the segment makes contiguous with the
+    // ones already received; retransmitted bytes are dropped.
+    fn reassemble(&mut self, segment: &Segment<'_>) -> Vec<u8> {
+        let mut seq = segment.seq;
+        if segment.syn {
+            seq = seq.wrapping_add(1);
+            self.next_seq = Some(seq);
+        }
+        let next = *self.next_seq.get_or_insert(seq);
+        if segment.payload.is_empty() {
+            return Vec::new();
+        }
+        self.out_of_order.push((seq, segment.payload.to_vec()));
+
+        // Sequence numbers wrap around: a segment starts `behind`
+        // bytes before `next` if that is less than 2^31.
+        let mut data = Vec::new();
+        let mut next = next;
+        loop {
+            let i = self.out_of_order.iter().position(|&(seq, ref bytes)| {
+                let behind = next.wrapping_sub(seq);
+                behind as i32 >= 0 && (behind as usize) < bytes.len()
+            });
+            let (seq, bytes) = match i {
+                Some(i) => self.out_of_order.swap_remove(i),
+                None => break,
+            };
+            let skip = next.wrapping_sub(seq) as usize;
+            data.extend_from_slice(&bytes[skip..]);
+            next = next.wrapping_add((bytes.len() - skip) as u32);
+        }
+        // Drop the retransmissions of bytes already received.
+        self.out_of_order.retain(|&(seq, ref bytes)| {
+            let behind = next.wrapping_sub(seq);
+            (behind as i32) < 0 || (behind as usize) < bytes.len()
+        });
+        self.next_seq = Some(next);
+        return data;
+    }
+
+    // Decodes the messages completed by `data`.
+    fn receive(&mut self, data: &[u8], explicit_port: bool) -> Vec<Result<BertTerm>> {
+        if self.state == State::Start {
+            self.state = match (data.get(2), data.get(4)) {
+                // A message starting with the magic number, or with
+                // the tag of a pass-through message and the magic
+                // number of its control message, or a tick.
+                (_, Some(&BERT_MAGIC_NUMBER)) => State::Connected,
+                (_, Some(&DIST_PASS_THROUGH)) if data.get(5) == Some(&BERT_MAGIC_NUMBER) => {
+                    State::Connected
+                }
+                _ if data.starts_with(&[0, 0, 0, 0]) => State::Connected,
+                (Some(b's'), _) => State::Handshake { acceptor: true },
+                (Some(b'n'), _) | (Some(b'N'), _) => State::Handshake { acceptor: false },
+                _ if explicit_port => State::Connected,
+                _ => State::Ignored,
+            };
+        }
+
+        let mut data = data;
+        if let State::Handshake { acceptor } = self.state {
+            self.handshake.extend_from_slice(data);
+            let rest = self.handshake_messages(acceptor);
+            if self.state != State::Connected {
+                return Vec::new();
+            }
+            self.parser.feed(&rest);
+            data = &[];
+        }
+        self.parser.feed(data);
+
+        let mut terms = Vec::new();
+        while self.state == State::Connected {
+            let start = self.parser.position();
+            let result = self
+                .parser
+                .dist_next_ref()
+                .map(|res| res.map(BertTermRef::into_owned));
+            match result {
+                Some(Err(ref e)) if matches!(e.root(), BertError::NotEnoughData { .. }) => {
+                    self.parser.rewind(start);
+                    break;
+                }
+                Some(Err(e)) => {
+                    // The stream cannot be resynchronized.
+                    self.state = State::Ignored;
+                    terms.push(Err(e));
+                }
+                Some(Ok(term)) => terms.push(Ok(term)),
+                None => break,
+            }
+        }
+        self.parser.discard_parsed();
+        return terms;
+    }
+
+    // Reads the complete handshake messages and returns the bytes
+    // after the last one once the handshake is over. The name of
+    // the node is in its `n` or `N` message, after a challenge for
+    // the acceptor.
+    fn handshake_messages(&mut self, acceptor: bool) -> Vec<u8> {
+        let mut offset = 0;
+        while self.state != State::Connected && offset + 2 <= self.handshake.len() {
+            let len =
+                u16::from_be_bytes([self.handshake[offset], self.handshake[offset + 1]]) as usize;
+            let message = match self.handshake.get(offset + 2..offset + 2 + len) {
+                Some(message) if !message.is_empty() => message,
+                Some(_) => {
+                    self.state = State::Ignored;
+                    return Vec::new();
+                }
+                None => break,
+            };
+            let challenge = if acceptor { 4 } else { 0 };
+            let name = match message[0] {
+                b'n' => message.get(7 + challenge..),
+                b'N' => message
+                    .get(13 + challenge..15 + challenge)
+                    .map(|n| u16::from_be_bytes([n[0], n[1]]) as usize)
+                    .and_then(|n| message.get(15 + challenge..15 + challenge + n)),
+                _ => None,
+            };
+            if let Some(name) = name {
+                self.node = Some(String::from_utf8_lossy(name).into_owned());
+            }
+            match (message[0], acceptor) {
+                (b'r', false) | (b'a', true) => self.state = State::Connected,
+                _ => (),
+            }
+            offset += 2 + len;
+        }
+        self.handshake.drain(..offset);
+        if self.state == State::Connected {
+            return std::mem::take(&mut self.handshake);
+        }
+        return Vec::new();
+    }
+}
@@ -12,5 +12,9 @@ use crate::prelude::*;
 use std::io;
 
 pub trait PrettyPrinter {
-    fn write(&self, term: &BertTerm, w: Box<dyn io::Write>) -> Result<()>;
+    fn write(&self, term: &BertTerm, w: Box<dyn io::Write>) -> Result<()> {
+        self.write_ref(&term.as_term_ref(), w)
+    }
+
+    fn write_ref(&self, term: &BertTermRef, w: Box<dyn io::Write>) -> Result<()>;
 }
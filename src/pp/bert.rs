@@ -1,39 +1,152 @@
-use std::io;
+use std::io::{self, Write};
 
 use byteorder::{BigEndian, WriteBytesExt};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
 use num_bigint::Sign;
+use num_traits::ToPrimitive;
 
 use crate::pp::PrettyPrinter;
 use crate::prelude::*;
 
-pub struct BertWriter;
+// The rest of a term to write: terms are written with an explicit
+// stack rather than recursively, so that their depth is only
+// limited by memory.
+enum Work<'t, 'a> {
+    Term(&'t BertTermRef<'a>),
+    Terms(&'t [BertTermRef<'a>]),
+    Pairs(&'t [BertTermRef<'a>], &'t [BertTermRef<'a>]),
+    Byte(u8),
+    // The end of the body of a NEW_FUN_EXT, which is preceded by
+    // its size.
+    FunEnd,
+}
+
+#[derive(Default)]
+pub struct BertWriter {
+    compression: Option<u32>,
+    canonical: bool,
+}
 
 impl PrettyPrinter for BertWriter {
-    fn write(&self, term: &BertTerm, mut w: Box<dyn io::Write>) -> Result<()> {
+    fn write_ref(&self, term: &BertTermRef, mut w: Box<dyn io::Write>) -> Result<()> {
         w.write_u8(BERT_MAGIC_NUMBER)?;
-        self.write_bert(term, &mut w)?;
+        match self.compression {
+            None => self.write_bert(term, &mut w)?,
+            Some(level) => {
+                let mut buf: Vec<u8> = Vec::new();
+                self.write_bert(term, &mut buf)?;
+                w.write_u8(COMPRESSED)?;
+                w.write_u32::<BigEndian>(buf.len() as u32)?;
+                let mut encoder = ZlibEncoder::new(w, Compression::new(level));
+                encoder.write_all(&buf)?;
+                encoder.finish()?;
+            }
+        }
         return Ok(());
     }
 }
 
 impl BertWriter {
     pub fn new() -> Self {
-        BertWriter {}
+        BertWriter::default()
     }
 
-    fn write_bert<W: io::Write>(&self, term: &BertTerm, w: &mut W) -> io::Result<()> {
-        match *term {
-            BertTerm::Nil => w.write_u8(NIL_EXT),
-            BertTerm::Int(n) => {
-                if n >= 0 && n < 256 {
-                    w.write_u8(SMALL_INTEGER_EXT)?;
-                    w.write_u8(n as u8)
-                } else {
-                    w.write_u8(INTEGER_EXT)?;
-                    w.write_i32::<BigEndian>(n)
+    /// Creates a writer that makes the choices of `term_to_binary/1`:
+    /// integer tags for bigints that fit in 32 bits, SMALL_ATOM_UTF8_EXT
+    /// for short atoms, STRING_EXT for lists of bytes, and no LOCAL_EXT.
+    pub fn canonical() -> Self {
+        BertWriter {
+            compression: None,
+            canonical: true,
+        }
+    }
+
+    /// Creates a writer that compresses every term with zlib,
+    /// like `term_to_binary(T, [{compressed, Level}])`; `level`
+    /// ranges from 0 (no compression) to 9 (best compression).
+    pub fn with_compression(level: u32) -> Self {
+        BertWriter {
+            compression: Some(u32::min(level, 9)),
+            canonical: false,
+        }
+    }
+
+    pub(crate) fn write_bert<W: io::Write>(&self, term: &BertTermRef, w: &mut W) -> io::Result<()> {
+        // The bodies of the funs being written, innermost last;
+        // terms are written to the innermost one.
+        let mut bodies: Vec<Vec<u8>> = Vec::new();
+        let mut stack = vec![Work::Term(term)];
+        while let Some(work) = stack.pop() {
+            match work {
+                Work::Term(BertTermRef::Fun(fun)) if fun.arity.is_some() => {
+                    bodies.push(self.start_fun(fun, &mut stack)?);
+                }
+                Work::Term(term) => {
+                    self.write_basic_term(term, output(&mut bodies, w), &mut stack)?;
+                }
+                Work::Terms(terms) => {
+                    if let Some((first, rest)) = terms.split_first() {
+                        stack.push(Work::Terms(rest));
+                        stack.push(Work::Term(first));
+                    }
+                }
+                Work::Pairs(keys, vals) => {
+                    if let (Some((key, keys)), Some((val, vals))) =
+                        (keys.split_first(), vals.split_first())
+                    {
+                        stack.push(Work::Pairs(keys, vals));
+                        stack.push(Work::Term(val));
+                        stack.push(Work::Term(key));
+                    }
+                }
+                Work::Byte(b) => output(&mut bodies, w).write_u8(b)?,
+                Work::FunEnd => {
+                    let body = bodies.pop().unwrap();
+                    let w = output(&mut bodies, w);
+                    // The size field includes itself.
+                    w.write_u8(NEW_FUN_EXT)?;
+                    w.write_u32::<BigEndian>(body.len() as u32 + 4)?;
+                    w.write_all(&body)?;
                 }
             }
-            BertTerm::BigInt(ref b) => {
+        }
+        return Ok(());
+    }
+
+    // Writes a term without elements, or the start of a container
+    // and pushes the rest on `stack`.
+    fn write_basic_term<'t, 'a>(
+        &self,
+        term: &'t BertTermRef<'a>,
+        w: &mut dyn io::Write,
+        stack: &mut Vec<Work<'t, 'a>>,
+    ) -> io::Result<()> {
+        if self.canonical {
+            match *term {
+                BertTermRef::BigInt(ref b) => {
+                    if let Some(n) = b.to_i32() {
+                        return self.write_int(n, w);
+                    }
+                }
+                BertTermRef::List(ref terms) => {
+                    if let Some(bytes) = byte_list(terms) {
+                        w.write_u8(STRING_EXT)?;
+                        w.write_u16::<BigEndian>(bytes.len() as u16)?;
+                        return w.write_all(&bytes);
+                    }
+                }
+                BertTermRef::Local(_, ref term) => {
+                    stack.push(Work::Term(term));
+                    return Ok(());
+                }
+                _ => (),
+            }
+        }
+        match *term {
+            BertTermRef::Nil => w.write_u8(NIL_EXT),
+            BertTermRef::Int(n) => self.write_int(n, w),
+            BertTermRef::BigInt(ref b) => {
                 let (sign, bytes) = b.to_bytes_le();
                 let len = bytes.len();
                 if len < 256 {
@@ -50,11 +163,11 @@ impl BertWriter {
                 }
                 w.write_all(&bytes)
             }
-            BertTerm::Float(f) => {
+            BertTermRef::Float(f) => {
                 w.write_u8(NEW_FLOAT_EXT)?;
                 w.write_f64::<BigEndian>(f)
             }
-            BertTerm::Tuple(ref terms) => {
+            BertTermRef::Tuple(ref terms) => {
                 let len = terms.len();
                 if len < 256 {
                     w.write_u8(SMALL_TUPLE_EXT)?;
@@ -63,49 +176,199 @@ impl BertWriter {
                     w.write_u8(LARGE_TUPLE_EXT)?;
                     w.write_u32::<BigEndian>(len as u32)?;
                 }
-                for t in terms {
-                    self.write_bert(t, w)?;
-                }
+                stack.push(Work::Terms(terms));
                 Ok(())
             }
-            BertTerm::List(ref terms) => {
+            BertTermRef::List(ref terms) => {
                 let len = terms.len();
                 w.write_u8(LIST_EXT)?;
                 w.write_u32::<BigEndian>(len as u32)?;
-                for t in terms {
-                    self.write_bert(t, w)?;
-                }
-                w.write_u8(NIL_EXT)
+                stack.push(Work::Byte(NIL_EXT));
+                stack.push(Work::Terms(terms));
+                Ok(())
+            }
+            BertTermRef::ImproperList(ref terms, ref tail) => {
+                let len = terms.len();
+                w.write_u8(LIST_EXT)?;
+                w.write_u32::<BigEndian>(len as u32)?;
+                stack.push(Work::Term(tail));
+                stack.push(Work::Terms(terms));
+                Ok(())
             }
-            BertTerm::Map(ref keys, ref vals) => {
+            BertTermRef::Map(ref keys, ref vals) => {
                 let len = keys.len();
                 w.write_u8(MAP_EXT)?;
                 w.write_u32::<BigEndian>(len as u32)?;
-                for (k, v) in keys.iter().zip(vals) {
-                    self.write_bert(k, w)?;
-                    self.write_bert(v, w)?;
-                }
+                stack.push(Work::Pairs(keys, vals));
                 Ok(())
             }
-            BertTerm::Atom(ref chars) => {
-                let bytes = chars.as_bytes();
-                let len = bytes.len();
-                w.write_u8(ATOM_UTF8_EXT)?;
-                w.write_u16::<BigEndian>(len as u16)?;
-                w.write_all(bytes)
-            }
-            BertTerm::String(ref bytes) => {
+            BertTermRef::Atom(ref chars) => self.write_atom(chars, w),
+            BertTermRef::String(ref bytes) => {
                 let len = bytes.len();
                 w.write_u8(STRING_EXT)?;
                 w.write_u16::<BigEndian>(len as u16)?;
                 w.write_all(bytes)
             }
-            BertTerm::Binary(ref bytes) => {
+            BertTermRef::Binary(ref bytes) => {
                 let len = bytes.len();
                 w.write_u8(BINARY_EXT)?;
                 w.write_u32::<BigEndian>(len as u32)?;
                 w.write_all(bytes)
             }
+            BertTermRef::BitBinary(ref bytes, bits) => {
+                w.write_u8(BIT_BINARY_EXT)?;
+                w.write_u32::<BigEndian>(bytes.len() as u32)?;
+                w.write_u8(bits)?;
+                w.write_all(bytes)
+            }
+            BertTermRef::Pid {
+                ref node,
+                id,
+                serial,
+                creation,
+            } => {
+                w.write_u8(NEW_PID_EXT)?;
+                self.write_atom(node, w)?;
+                w.write_u32::<BigEndian>(id)?;
+                w.write_u32::<BigEndian>(serial)?;
+                w.write_u32::<BigEndian>(creation)
+            }
+            BertTermRef::Port {
+                ref node,
+                id,
+                creation,
+            } => {
+                if id <= u32::MAX as u64 {
+                    w.write_u8(NEW_PORT_EXT)?;
+                    self.write_atom(node, w)?;
+                    w.write_u32::<BigEndian>(id as u32)?;
+                } else {
+                    w.write_u8(V4_PORT_EXT)?;
+                    self.write_atom(node, w)?;
+                    w.write_u64::<BigEndian>(id)?;
+                }
+                w.write_u32::<BigEndian>(creation)
+            }
+            BertTermRef::Reference {
+                ref node,
+                creation,
+                ref ids,
+            } => {
+                w.write_u8(NEWER_REFERENCE_EXT)?;
+                w.write_u16::<BigEndian>(ids.len() as u16)?;
+                self.write_atom(node, w)?;
+                w.write_u32::<BigEndian>(creation)?;
+                for &id in ids {
+                    w.write_u32::<BigEndian>(id)?;
+                }
+                Ok(())
+            }
+            BertTermRef::Fun(ref fun) => self.write_old_fun(fun, w, stack),
+            BertTermRef::Export {
+                ref module,
+                ref function,
+                arity,
+            } => {
+                w.write_u8(EXPORT_EXT)?;
+                self.write_atom(module, w)?;
+                self.write_atom(function, w)?;
+                w.write_u8(SMALL_INTEGER_EXT)?;
+                w.write_u8(arity)
+            }
+            BertTermRef::Local(hash, ref term) => {
+                w.write_u8(LOCAL_EXT)?;
+                w.write_u32::<BigEndian>(hash)?;
+                stack.push(Work::Term(term));
+                Ok(())
+            }
+            BertTermRef::Truncated => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a truncated term cannot be encoded",
+            )),
         }
     }
+
+    // Legacy FUN_EXT encoding, for funs without an arity.
+    fn write_old_fun<'t, 'a>(
+        &self,
+        fun: &'t BertFunRef<'a>,
+        mut w: &mut dyn io::Write,
+        stack: &mut Vec<Work<'t, 'a>>,
+    ) -> io::Result<()> {
+        w.write_u8(FUN_EXT)?;
+        w.write_u32::<BigEndian>(fun.free_vars.len() as u32)?;
+        self.write_bert(&fun.pid, &mut w)?;
+        self.write_atom(&fun.module, w)?;
+        self.write_bert(&BertTermRef::Int(fun.old_index), &mut w)?;
+        self.write_bert(&BertTermRef::Int(fun.old_uniq), &mut w)?;
+        stack.push(Work::Terms(&fun.free_vars));
+        Ok(())
+    }
+
+    // The size of a NEW_FUN_EXT comes first, so its body is written
+    // to a buffer until `Work::FunEnd`; returns the buffer.
+    fn start_fun<'t, 'a>(
+        &self,
+        fun: &'t BertFunRef<'a>,
+        stack: &mut Vec<Work<'t, 'a>>,
+    ) -> io::Result<Vec<u8>> {
+        let mut body: Vec<u8> = Vec::new();
+        body.write_u8(fun.arity.unwrap_or(0))?;
+        body.write_all(&fun.uniq)?;
+        body.write_u32::<BigEndian>(fun.index)?;
+        body.write_u32::<BigEndian>(fun.free_vars.len() as u32)?;
+        self.write_atom(&fun.module, &mut body)?;
+        self.write_bert(&BertTermRef::Int(fun.old_index), &mut body)?;
+        self.write_bert(&BertTermRef::Int(fun.old_uniq), &mut body)?;
+        stack.push(Work::FunEnd);
+        stack.push(Work::Terms(&fun.free_vars));
+        stack.push(Work::Term(&fun.pid));
+        Ok(body)
+    }
+
+    fn write_int<W: io::Write + ?Sized>(&self, n: i32, w: &mut W) -> io::Result<()> {
+        if (0..256).contains(&n) {
+            w.write_u8(SMALL_INTEGER_EXT)?;
+            w.write_u8(n as u8)
+        } else {
+            w.write_u8(INTEGER_EXT)?;
+            w.write_i32::<BigEndian>(n)
+        }
+    }
+
+    fn write_atom<W: io::Write + ?Sized>(&self, chars: &str, w: &mut W) -> io::Result<()> {
+        let bytes = chars.as_bytes();
+        let len = bytes.len();
+        if self.canonical && len < 256 {
+            w.write_u8(SMALL_ATOM_UTF8_EXT)?;
+            w.write_u8(len as u8)?;
+        } else {
+            w.write_u8(ATOM_UTF8_EXT)?;
+            w.write_u16::<BigEndian>(len as u16)?;
+        }
+        w.write_all(bytes)
+    }
+}
+
+// The bytes of a list that `term_to_binary` encodes as a STRING_EXT:
+// a list of fewer than 65536 integers from 0 to 255.
+fn byte_list(terms: &[BertTermRef]) -> Option<Vec<u8>> {
+    if terms.is_empty() || terms.len() > u16::MAX as usize {
+        return None;
+    }
+    terms
+        .iter()
+        .map(|t| match *t {
+            BertTermRef::Int(n) if (0..256).contains(&n) => Some(n as u8),
+            BertTermRef::BigInt(ref b) => b.to_u8(),
+            _ => None,
+        })
+        .collect()
+}
+
+fn output<'o, W: io::Write>(bodies: &'o mut [Vec<u8>], w: &'o mut W) -> &'o mut dyn io::Write {
+    match bodies.last_mut() {
+        Some(body) => body,
+        None => w,
+    }
 }
@@ -6,6 +6,26 @@ use crate::prelude::*;
 
 const SPACES: [u8; 4096] = [b' '; 4096];
 
+// The rest of a term to print: terms are printed with an explicit
+// stack rather than recursively, so that their depth is only
+// limited by memory.
+enum Work<'t, 'a> {
+    Term(&'t BertTermRef<'a>, usize),
+    // The elements left to print, their depth, whether they are
+    // printed on their own line, and the separator before the next.
+    Elements(&'t [BertTermRef<'a>], usize, bool, &'static [u8]),
+    // The same for the keys and values of a map.
+    Pairs(
+        &'t [BertTermRef<'a>],
+        &'t [BertTermRef<'a>],
+        usize,
+        bool,
+        &'static [u8],
+    ),
+    Bytes(&'static [u8]),
+    Indent(usize),
+}
+
 pub struct ErlangPrettyPrinter {
     indent_width: usize,
     max_terms_per_line: usize,
@@ -13,7 +33,7 @@ pub struct ErlangPrettyPrinter {
 }
 
 impl PrettyPrinter for ErlangPrettyPrinter {
-    fn write(&self, term: &BertTerm, mut w: Box<dyn io::Write>) -> Result<()> {
+    fn write_ref(&self, term: &BertTermRef, mut w: Box<dyn io::Write>) -> Result<()> {
         self.write_term(term, &mut w, 0)?;
         writeln!(w, "{}", self.terminator)?;
         return Ok(());
@@ -33,21 +53,125 @@ impl ErlangPrettyPrinter {
         }
     }
 
-    fn write_term<W: io::Write>(&self, term: &BertTerm, w: &mut W, depth: usize) -> io::Result<()> {
+    fn write_term<W: io::Write>(
+        &self,
+        term: &BertTermRef,
+        w: &mut W,
+        depth: usize,
+    ) -> io::Result<()> {
+        let mut stack = vec![Work::Term(term, depth)];
+        while let Some(work) = stack.pop() {
+            match work {
+                Work::Term(term, depth) => self.write_basic_term(term, w, depth, &mut stack)?,
+                Work::Elements(terms, depth, multi_line, comma) => {
+                    if let Some((first, rest)) = terms.split_first() {
+                        w.write_all(comma)?;
+                        if multi_line {
+                            w.write_all(self.indentation(depth))?;
+                        }
+                        stack.push(Work::Elements(rest, depth, multi_line, b", "));
+                        stack.push(Work::Term(first, depth));
+                    }
+                }
+                Work::Pairs(keys, vals, depth, multi_line, comma) => {
+                    if let (Some((key, keys)), Some((val, vals))) =
+                        (keys.split_first(), vals.split_first())
+                    {
+                        w.write_all(comma)?;
+                        if multi_line {
+                            w.write_all(self.indentation(depth))?;
+                        }
+                        stack.push(Work::Pairs(keys, vals, depth, multi_line, b", "));
+                        stack.push(Work::Term(val, depth));
+                        stack.push(Work::Bytes(b" => "));
+                        stack.push(Work::Term(key, depth));
+                    }
+                }
+                Work::Bytes(bytes) => w.write_all(bytes)?,
+                Work::Indent(depth) => w.write_all(self.indentation(depth))?,
+            }
+        }
+        return Ok(());
+    }
+
+    // Writes a term without elements, or the start of a container
+    // and pushes the rest on `stack`.
+    fn write_basic_term<'t, 'a, W: io::Write>(
+        &self,
+        term: &'t BertTermRef<'a>,
+        w: &mut W,
+        depth: usize,
+        stack: &mut Vec<Work<'t, 'a>>,
+    ) -> io::Result<()> {
         match *term {
-            BertTerm::Nil => w.write_all(b"[]"),
-            BertTerm::Int(n) => itoa::write(w, n).map(|_| ()),
-            BertTerm::BigInt(ref n) => write!(w, "{}", n),
-            BertTerm::Float(x) => {
+            BertTermRef::Nil => w.write_all(b"[]"),
+            BertTermRef::Int(n) => itoa::write(w, n).map(|_| ()),
+            BertTermRef::BigInt(ref n) => write!(w, "{}", n),
+            BertTermRef::Float(x) => {
                 let mut buf = ryu::Buffer::new();
                 w.write_all(buf.format(x).as_bytes())
             }
-            BertTerm::Atom(ref s) => w.write_all(s.as_bytes()),
-            BertTerm::String(ref bytes) => self.write_string(bytes, w, b"\"", b"\""),
-            BertTerm::Binary(ref bytes) => self.write_string(bytes, w, b"<<\"", b"\">>"),
-            BertTerm::List(ref terms) => self.write_collection(terms, w, depth, b"[", b"]"),
-            BertTerm::Tuple(ref terms) => self.write_collection(terms, w, depth, b"{", b"}"),
-            BertTerm::Map(ref keys, ref vals) => self.write_map(keys, vals, w, depth),
+            BertTermRef::Atom(ref s) => w.write_all(s.as_bytes()),
+            BertTermRef::String(ref bytes) => self.write_string(bytes, w, b"\"", b"\""),
+            BertTermRef::Binary(ref bytes) => self.write_string(bytes, w, b"<<\"", b"\">>"),
+            BertTermRef::BitBinary(ref bytes, bits) => self.write_bitstring(bytes, bits, w),
+            BertTermRef::List(ref terms) => {
+                w.write_all(b"[")?;
+                self.push_collection(terms, None, depth, b"]", stack);
+                Ok(())
+            }
+            BertTermRef::ImproperList(ref terms, ref tail) => {
+                w.write_all(b"[")?;
+                self.push_collection(terms, Some(tail), depth, b"]", stack);
+                Ok(())
+            }
+            BertTermRef::Tuple(ref terms) => {
+                w.write_all(b"{")?;
+                self.push_collection(terms, None, depth, b"}", stack);
+                Ok(())
+            }
+            BertTermRef::Map(ref keys, ref vals) => {
+                w.write_all(b"#{")?;
+                self.push_map(keys, vals, depth, stack);
+                Ok(())
+            }
+            // The Erlang shell prints a node number, which is only
+            // known to the node that decoded the term: print the
+            // name of the node instead.
+            BertTermRef::Pid {
+                ref node,
+                id,
+                serial,
+                ..
+            } => write!(w, "<{}.{}.{}>", node, id, serial),
+            BertTermRef::Port { ref node, id, .. } => write!(w, "#Port<{}.{}>", node, id),
+            BertTermRef::Reference {
+                ref node, ref ids, ..
+            } => {
+                // Like the Erlang shell, print the most significant word first.
+                write!(w, "#Ref<{}", node)?;
+                for id in ids.iter().rev() {
+                    write!(w, ".{}", id)?;
+                }
+                w.write_all(b">")
+            }
+            BertTermRef::Fun(ref fun) => {
+                w.write_all(b"#Fun<")?;
+                w.write_all(fun.module.as_bytes())?;
+                write!(w, ".{}.{}>", fun.old_index, fun.old_uniq)
+            }
+            BertTermRef::Export {
+                ref module,
+                ref function,
+                arity,
+            } => write!(w, "fun {}:{}/{}", module, function, arity),
+            BertTermRef::Local(hash, ref term) => {
+                write!(w, "#Local<{:08x}>(", hash)?;
+                stack.push(Work::Bytes(b")"));
+                stack.push(Work::Term(term, depth));
+                Ok(())
+            }
+            BertTermRef::Truncated => w.write_all(b"...truncated here"),
         }
     }
 
@@ -78,73 +202,65 @@ impl ErlangPrettyPrinter {
         w.write_all(close)
     }
 
-    fn write_collection<W: io::Write>(
+    // Like the Erlang shell, the bytes of a bitstring are printed as
+    // integers and the trailing bits as a sized segment, e.g. <<1,2,3:5>>.
+    fn write_bitstring<W: io::Write>(&self, bytes: &[u8], bits: u8, w: &mut W) -> io::Result<()> {
+        w.write_all(b"<<")?;
+        if let Some((&last, init)) = bytes.split_last() {
+            for b in init {
+                itoa::write(&mut *w, *b)?;
+                w.write_all(b",")?;
+            }
+            if bits == 0 || bits >= 8 {
+                itoa::write(&mut *w, last)?;
+            } else {
+                write!(w, "{}:{}", last >> (8 - bits), bits)?;
+            }
+        }
+        w.write_all(b">>")
+    }
+
+    // Pushes what follows the opening bracket of a collection, in
+    // reverse order.
+    fn push_collection<'t, 'a>(
         &self,
-        terms: &[BertTerm],
-        w: &mut W,
+        terms: &'t [BertTermRef<'a>],
+        tail: Option<&'t BertTermRef<'a>>,
         depth: usize,
-        open: &[u8],
-        close: &[u8],
-    ) -> io::Result<()> {
-        let multi_line = !self.is_small_collection(terms);
-
-        // Every element will have the same indentation,
-        // so pre-compute it once.
-        let prefix = if multi_line {
-            self.indentation(depth + 1)
-        } else {
-            &[]
-        };
-
-        w.write_all(open)?;
-        let mut comma: &[u8] = b"";
-        for t in terms {
-            w.write_all(comma)?;
-            w.write_all(prefix)?;
-            self.write_term(t, w, depth + 1)?;
-            comma = b", ";
-        }
+        close: &'static [u8],
+        stack: &mut Vec<Work<'t, 'a>>,
+    ) {
+        let multi_line = !self.is_small_collection(terms) || tail.is_some_and(|t| !t.is_basic());
 
+        stack.push(Work::Bytes(close));
         if multi_line {
-            w.write_all(&self.indentation(depth))?;
+            stack.push(Work::Indent(depth));
         }
-
-        w.write_all(close)
+        if let Some(tail) = tail {
+            stack.push(Work::Term(tail, depth + 1));
+            stack.push(Work::Bytes(b" | "));
+        }
+        stack.push(Work::Elements(terms, depth + 1, multi_line, b""));
     }
 
-    fn write_map<W: io::Write>(
+    fn push_map<'t, 'a>(
         &self,
-        keys: &[BertTerm],
-        vals: &[BertTerm],
-        w: &mut W,
+        keys: &'t [BertTermRef<'a>],
+        vals: &'t [BertTermRef<'a>],
         depth: usize,
-    ) -> io::Result<()> {
+        stack: &mut Vec<Work<'t, 'a>>,
+    ) {
         let multi_line = !self.is_small_collection(keys) || !self.is_small_collection(vals);
-        let prefix = if multi_line {
-            self.indentation(depth + 1)
-        } else {
-            &[]
-        };
-
-        w.write_all(b"#{")?;
-        let mut comma: &[u8] = b"";
-        for i in 0..keys.len() {
-            w.write_all(comma)?;
-            w.write_all(prefix)?;
-            self.write_term(&keys[i], w, depth + 1)?;
-            w.write_all(b" => ")?;
-            self.write_term(&vals[i], w, depth + 1)?;
-            comma = b", ";
-        }
 
+        stack.push(Work::Bytes(b"}"));
         if multi_line {
-            w.write_all(&self.indentation(depth))?;
+            stack.push(Work::Indent(depth));
         }
-        w.write_all(b"}")
+        stack.push(Work::Pairs(keys, vals, depth + 1, multi_line, b""));
     }
 
-    fn is_small_collection(&self, terms: &[BertTerm]) -> bool {
-        terms.len() <= self.max_terms_per_line && terms.iter().all(BertTerm::is_basic)
+    fn is_small_collection(&self, terms: &[BertTermRef]) -> bool {
+        terms.len() <= self.max_terms_per_line && terms.iter().all(BertTermRef::is_basic)
     }
 
     fn indentation(&self, depth: usize) -> &[u8] {
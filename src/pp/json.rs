@@ -4,14 +4,28 @@ use crate::pp::utils::*;
 use crate::pp::PrettyPrinter;
 use crate::prelude::*;
 
+// The rest of a term to print: terms are printed with an explicit
+// stack rather than recursively, so that their depth is only
+// limited by memory.
+enum Work<'t, 'a> {
+    Term(&'t BertTermRef<'a>),
+    // The elements left to print and the separator before the next.
+    Elements(&'t [BertTermRef<'a>], &'static [u8]),
+    // The same for the keys and values of a map.
+    Pairs(&'t [BertTermRef<'a>], &'t [BertTermRef<'a>], &'static [u8]),
+    // The same for the entries of a proplist.
+    Entries(&'t [BertTermRef<'a>], &'static [u8]),
+    Bytes(&'static [u8]),
+}
+
 pub struct JsonPrettyPrinter {
     transform_proplists: bool,
 }
 
 impl PrettyPrinter for JsonPrettyPrinter {
-    fn write(&self, term: &BertTerm, mut w: Box<dyn io::Write>) -> Result<()> {
+    fn write_ref(&self, term: &BertTermRef, mut w: Box<dyn io::Write>) -> Result<()> {
         self.write_term(term, &mut w)?;
-        writeln!(w, "")?;
+        writeln!(w)?;
         return Ok(());
     }
 }
@@ -23,78 +37,192 @@ impl JsonPrettyPrinter {
         }
     }
 
-    fn write_term<W: io::Write>(&self, term: &BertTerm, w: &mut W) -> io::Result<()> {
+    fn write_term<W: io::Write>(&self, term: &BertTermRef, w: &mut W) -> io::Result<()> {
+        let mut stack = vec![Work::Term(term)];
+        while let Some(work) = stack.pop() {
+            match work {
+                Work::Term(term) => self.write_basic_term(term, w, &mut stack)?,
+                Work::Elements(terms, comma) => {
+                    if let Some((first, rest)) = terms.split_first() {
+                        w.write_all(comma)?;
+                        stack.push(Work::Elements(rest, b","));
+                        stack.push(Work::Term(first));
+                    }
+                }
+                Work::Pairs(keys, vals, comma) => {
+                    if let (Some((key, keys)), Some((val, vals))) =
+                        (keys.split_first(), vals.split_first())
+                    {
+                        w.write_all(comma)?;
+                        stack.push(Work::Pairs(keys, vals, b","));
+                        stack.push(Work::Term(val));
+                        stack.push(Work::Bytes(b":"));
+                        stack.push(Work::Term(key));
+                    }
+                }
+                Work::Entries(terms, comma) => {
+                    if let Some((first, rest)) = terms.split_first() {
+                        w.write_all(comma)?;
+                        stack.push(Work::Entries(rest, b","));
+                        self.push_kv_pair(first, &mut stack);
+                    }
+                }
+                Work::Bytes(bytes) => w.write_all(bytes)?,
+            }
+        }
+        return Ok(());
+    }
+
+    // Writes a term without elements, or the start of a container
+    // and pushes the rest on `stack`.
+    fn write_basic_term<'t, 'a, W: io::Write>(
+        &self,
+        term: &'t BertTermRef<'a>,
+        w: &mut W,
+        stack: &mut Vec<Work<'t, 'a>>,
+    ) -> io::Result<()> {
         match *term {
-            BertTerm::Nil => w.write_all(b"[]"),
-            BertTerm::Int(n) => itoa::write(w, n).map(|_| ()),
-            BertTerm::BigInt(ref b) => write!(w, "\"{}\"", b),
-            BertTerm::Float(x) => {
+            BertTermRef::Nil => w.write_all(b"[]"),
+            BertTermRef::Int(n) => itoa::write(w, n).map(|_| ()),
+            BertTermRef::BigInt(ref b) => write!(w, "\"{}\"", b),
+            BertTermRef::Float(x) => {
                 let mut buf = ryu::Buffer::new();
                 w.write_all(buf.format(x).as_bytes())
             }
-            BertTerm::Atom(ref s) => {
+            BertTermRef::Atom(ref s) => {
                 if s == "true" {
                     write!(w, "true")
                 } else if s == "false" {
                     write!(w, "false")
                 } else {
-                    write!(w, "\"{}\"", s)
+                    write_text(s, w)
                 }
             }
-            BertTerm::List(ref terms) => {
+            BertTermRef::List(ref terms) => {
                 if self.transform_proplists && term.is_proplist() {
                     w.write_all(b"{")?;
-                    let mut comma = "";
-                    for term in terms {
-                        w.write_all(comma.as_bytes())?;
-                        comma = ",";
-                        self.write_as_kv_pair(term, w)?;
-                    }
-                    w.write_all(b"}")
+                    stack.push(Work::Bytes(b"}"));
+                    stack.push(Work::Entries(terms, b""));
+                    Ok(())
                 } else {
-                    self.write_list(terms, w)
+                    self.write_list(terms, w, stack)
                 }
             }
-            BertTerm::Tuple(ref terms) => self.write_list(terms, w),
-            BertTerm::Binary(ref bytes) | BertTerm::String(ref bytes) => {
-                w.write_all(b"\"")?;
-                let mut start = 0;
-                for (i, &b) in bytes.iter().enumerate() {
-                    if must_be_escaped(b) {
-                        w.write_all(&bytes[start..i])?;
-                        start = i + 1;
-                        write!(w, "\\{}", b as char)?;
-                    } else if !is_printable(b) {
-                        w.write_all(&bytes[start..i])?;
-                        start = i + 1;
-                        write!(w, "\\u{:04x}", b)?;
-                    }
+            BertTermRef::ImproperList(ref terms, ref tail) => {
+                w.write_all(b"{\"type\":\"improper_list\",\"elements\":")?;
+                stack.push(Work::Bytes(b"}"));
+                stack.push(Work::Term(tail));
+                stack.push(Work::Bytes(b",\"tail\":"));
+                self.write_list(terms, w, stack)
+            }
+            BertTermRef::Tuple(ref terms) => self.write_list(terms, w, stack),
+            BertTermRef::Binary(ref bytes) | BertTermRef::String(ref bytes) => {
+                write_string(bytes, false, w)
+            }
+            BertTermRef::BitBinary(ref bytes, bits) => {
+                write!(w, "{{\"type\":\"bitstring\",\"bits\":{},\"bytes\":[", bits)?;
+                let mut comma = "";
+                for b in bytes.iter() {
+                    write!(w, "{}{}", comma, b)?;
+                    comma = ",";
                 }
-                w.write_all(&bytes[start..])?;
-                w.write_all(b"\"")
+                w.write_all(b"]}")
             }
-            BertTerm::Map(ref keys, ref values) => {
+            BertTermRef::Map(ref keys, ref values) => {
                 w.write_all(b"{")?;
+                stack.push(Work::Bytes(b"}"));
+                stack.push(Work::Pairs(keys, values, b""));
+                Ok(())
+            }
+            BertTermRef::Pid {
+                ref node,
+                id,
+                serial,
+                creation,
+            } => {
+                w.write_all(b"{\"type\":\"pid\",\"node\":")?;
+                write_text(node, w)?;
+                write!(
+                    w,
+                    ",\"id\":{},\"serial\":{},\"creation\":{}}}",
+                    id, serial, creation
+                )
+            }
+            BertTermRef::Port {
+                ref node,
+                id,
+                creation,
+            } => {
+                w.write_all(b"{\"type\":\"port\",\"node\":")?;
+                write_text(node, w)?;
+                write!(w, ",\"id\":{},\"creation\":{}}}", id, creation)
+            }
+            BertTermRef::Reference {
+                ref node,
+                creation,
+                ref ids,
+            } => {
+                w.write_all(b"{\"type\":\"reference\",\"node\":")?;
+                write_text(node, w)?;
+                write!(w, ",\"creation\":{},\"ids\":[", creation)?;
                 let mut comma = "";
-                for (key, value) in keys.iter().zip(values) {
-                    w.write_all(comma.as_bytes())?;
+                for id in ids {
+                    write!(w, "{}{}", comma, id)?;
                     comma = ",";
-                    self.write_term(key, w)?;
-                    w.write_all(b":")?;
-                    self.write_term(value, w)?;
                 }
-                w.write_all(b"}")
+                w.write_all(b"]}")
+            }
+            BertTermRef::Fun(ref fun) => {
+                w.write_all(b"{\"type\":\"fun\",\"module\":")?;
+                write_text(&fun.module, w)?;
+                w.write_all(b",\"arity\":")?;
+                match fun.arity {
+                    Some(arity) => write!(w, "{}", arity)?,
+                    None => w.write_all(b"null")?,
+                }
+                w.write_all(b",\"uniq\":\"")?;
+                for b in &fun.uniq {
+                    write!(w, "{:02x}", b)?;
+                }
+                write!(
+                    w,
+                    "\",\"index\":{},\"old_index\":{},\"old_uniq\":{},\"pid\":",
+                    fun.index, fun.old_index, fun.old_uniq
+                )?;
+                stack.push(Work::Bytes(b"]}"));
+                stack.push(Work::Elements(&fun.free_vars, b""));
+                stack.push(Work::Bytes(b",\"free_vars\":["));
+                stack.push(Work::Term(&fun.pid));
+                Ok(())
+            }
+            BertTermRef::Export {
+                ref module,
+                ref function,
+                arity,
+            } => {
+                w.write_all(b"{\"type\":\"export\",\"module\":")?;
+                write_text(module, w)?;
+                w.write_all(b",\"function\":")?;
+                write_text(function, w)?;
+                write!(w, ",\"arity\":{}}}", arity)
             }
+            BertTermRef::Local(hash, ref term) => {
+                write!(w, "{{\"type\":\"local\",\"hash\":{},\"term\":", hash)?;
+                stack.push(Work::Bytes(b"}"));
+                stack.push(Work::Term(term));
+                Ok(())
+            }
+            BertTermRef::Truncated => w.write_all(b"{\"type\":\"truncated\"}"),
         }
     }
 
-    fn write_as_kv_pair<W: io::Write>(&self, term: &BertTerm, w: &mut W) -> io::Result<()> {
+    fn push_kv_pair<'t, 'a>(&self, term: &'t BertTermRef<'a>, stack: &mut Vec<Work<'t, 'a>>) {
         match *term {
-            BertTerm::Tuple(ref kv) => {
+            BertTermRef::Tuple(ref kv) => {
                 assert_eq!(2, kv.len());
-                self.write_term(&kv[0], w)?;
-                w.write_all(b":")?;
-                self.write_term(&kv[1], w)
+                stack.push(Work::Term(&kv[1]));
+                stack.push(Work::Bytes(b":"));
+                stack.push(Work::Term(&kv[0]));
             }
             _ => {
                 panic!("{:?} is not a proplist item", term)
@@ -102,14 +230,42 @@ impl JsonPrettyPrinter {
         }
     }
 
-    fn write_list<W: io::Write>(&self, terms: &[BertTerm], w: &mut W) -> io::Result<()> {
+    fn write_list<'t, 'a, W: io::Write>(
+        &self,
+        terms: &'t [BertTermRef<'a>],
+        w: &mut W,
+        stack: &mut Vec<Work<'t, 'a>>,
+    ) -> io::Result<()> {
         w.write_all(b"[")?;
-        let mut comma = "";
-        for term in terms {
-            w.write_all(comma.as_bytes())?;
-            comma = ",";
-            self.write_term(term, w)?;
+        stack.push(Work::Bytes(b"]"));
+        stack.push(Work::Elements(terms, b""));
+        Ok(())
+    }
+}
+
+// Writes the text of an atom as a JSON string; its UTF-8 characters
+// are kept, but quotes, backslashes and control characters are
+// escaped like in binaries.
+fn write_text<W: io::Write>(s: &str, w: &mut W) -> io::Result<()> {
+    write_string(s.as_bytes(), true, w)
+}
+
+// Writes bytes as a JSON string, escaping the bytes that are not
+// printable; with `utf8`, the bytes above 0x7f are kept.
+fn write_string<W: io::Write>(bytes: &[u8], utf8: bool, w: &mut W) -> io::Result<()> {
+    w.write_all(b"\"")?;
+    let mut start = 0;
+    for (i, &b) in bytes.iter().enumerate() {
+        if must_be_escaped(b) {
+            w.write_all(&bytes[start..i])?;
+            start = i + 1;
+            write!(w, "\\{}", b as char)?;
+        } else if !is_printable(b) && (!utf8 || b < 0x80) {
+            w.write_all(&bytes[start..i])?;
+            start = i + 1;
+            write!(w, "\\u{:04x}", b)?;
         }
-        w.write_all(b"]")
     }
+    w.write_all(&bytes[start..])?;
+    w.write_all(b"\"")
 }
@@ -1,5 +1,5 @@
 pub fn is_printable(b: u8) -> bool {
-    b >= 0x20 && b <= 0x7e
+    (0x20..=0x7e).contains(&b)
 }
 
 pub fn must_be_escaped(b: u8) -> bool {
@@ -0,0 +1,210 @@
+use std::io::{self, Read};
+
+use crate::framing::Framing;
+use crate::parser::{Annotation, BertParser, ParserLimits, Trace};
+use crate::prelude::*;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A parser that pulls its input from an `io::Read` and yields
+/// every term, delimited by a `Framing`, as soon as all its bytes
+/// have arrived.
+///
+/// When a term is incomplete, the parser reads more bytes and
+/// parses the term again from its first byte; the bytes of the
+/// terms that were already returned are discarded. The iterator
+/// ends after the first error.
+///
+/// `next_ref` returns terms that borrow from the parser's buffer
+/// until the next call, except the terms that were split across
+/// reads, which are copied; the `Iterator` implementation returns
+/// owned terms. The path of a parse error starts with the number
+/// of the term in the stream.
+pub struct StreamParser<R, F> {
+    reader: R,
+    parser: BertParser,
+    // A term that was split across reads is parsed by a fork of
+    // `parser`, which cannot be fed while the failed attempt
+    // borrows it; the fork replaces it before the next term.
+    fork: Option<BertParser>,
+    framing: F,
+    reader_eof: bool,
+    failed: bool,
+    // The number of terms returned so far.
+    terms: usize,
+}
+
+impl<R: Read, F: Framing> StreamParser<R, F> {
+    pub fn new(reader: R, framing: F) -> Self {
+        StreamParser::with_limits(reader, framing, ParserLimits::default())
+    }
+
+    pub fn with_limits(reader: R, framing: F, limits: ParserLimits) -> Self {
+        let mut parser = BertParser::with_limits(Vec::with_capacity(CHUNK_SIZE), limits);
+        parser.set_more_input(true);
+        StreamParser {
+            reader,
+            parser,
+            fork: None,
+            framing,
+            reader_eof: false,
+            failed: false,
+            terms: 0,
+        }
+    }
+
+    // The parser of the last term.
+    fn current(&self) -> &BertParser {
+        self.fork.as_ref().unwrap_or(&self.parser)
+    }
+
+    // Makes the parser of the last term the parser of the next one.
+    fn settle(&mut self) {
+        if let Some(fork) = self.fork.take() {
+            self.parser = fork;
+        }
+    }
+
+    /// Keeps what was decoded of a term that the end of the input
+    /// cuts short, see `BertParser::set_lenient`.
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.settle();
+        self.parser.set_lenient(lenient);
+    }
+
+    /// Makes the warnings about suspicious terms errors, see
+    /// `BertParser::set_strict`.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.settle();
+        self.parser.set_strict(strict);
+    }
+
+    /// See `BertParser::take_partial`.
+    pub fn take_partial(&mut self) -> Option<BertTerm> {
+        self.settle();
+        self.parser.take_partial()
+    }
+
+    /// Returns the warnings collected since the last call.
+    pub fn take_warnings(&mut self) -> Vec<BertWarning> {
+        self.settle();
+        self.parser.take_warnings()
+    }
+
+    /// Records the terms or the fields that are parsed, see
+    /// `BertParser::set_tracing`.
+    pub fn set_tracing(&mut self, trace: Trace) {
+        self.settle();
+        self.parser.set_tracing(trace);
+    }
+
+    /// The annotations recorded in the current term.
+    pub fn annotations(&self) -> Vec<Annotation> {
+        self.current().annotations()
+    }
+
+    /// The offset of the next byte to parse.
+    pub fn position(&self) -> usize {
+        self.current().position()
+    }
+
+    /// The bytes from the start of the current term, and the offset
+    /// of the first one.
+    pub fn buffer(&self) -> (usize, &[u8]) {
+        self.current().buffer()
+    }
+
+    pub fn next_ref(&mut self) -> Option<Result<BertTermRef<'_>>> {
+        if self.failed {
+            return None;
+        }
+        // The previous term, if any, is no longer borrowed.
+        self.settle();
+        self.parser.discard_parsed();
+        while self.parser.eof() && !self.reader_eof {
+            if let Err(e) = fill(&mut self.reader, &mut self.parser, &mut self.reader_eof) {
+                self.failed = true;
+                return Some(Err(e));
+            }
+        }
+
+        let start = self.parser.position();
+        let result = self.framing.next_term(&self.parser);
+        if self.reader_eof || !is_incomplete(&result) {
+            return finish(result, &mut self.terms, &mut self.failed);
+        }
+
+        // The term goes past the buffered bytes. `parser` cannot be
+        // fed while `result` borrows it, so its fork is fed instead,
+        // and the term is copied out of the fork's buffer.
+        let mut fork = self.parser.fork();
+        let result = loop {
+            fork.rewind(start);
+            if let Err(e) = fill(&mut self.reader, &mut fork, &mut self.reader_eof) {
+                self.failed = true;
+                return Some(Err(e));
+            }
+            let result = self.framing.next_term(&fork);
+            if self.reader_eof || !is_incomplete(&result) {
+                break result.map(|res| res.map(BertTermRef::into_static));
+            }
+        };
+        self.fork = Some(fork);
+        return finish(result, &mut self.terms, &mut self.failed);
+    }
+}
+
+impl<R: Read, F: Framing> Iterator for StreamParser<R, F> {
+    type Item = Result<BertTerm>;
+
+    fn next(&mut self) -> Option<Result<BertTerm>> {
+        self.next_ref().map(|res| res.map(BertTermRef::into_owned))
+    }
+}
+
+// Reads at least one byte into `parser`, unless the reader is
+// exhausted. The read size grows with the buffered data so that a
+// large term is not re-parsed once per chunk.
+fn fill<R: Read>(reader: &mut R, parser: &mut BertParser, reader_eof: &mut bool) -> Result<()> {
+    let size = usize::max(CHUNK_SIZE, parser.remaining());
+    let mut buf = vec![0; size];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => {
+                *reader_eof = true;
+                parser.set_more_input(false);
+                return Ok(());
+            }
+            Ok(n) => {
+                parser.feed(&buf[..n]);
+                return Ok(());
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(BertError::IoError(e)),
+        }
+    }
+}
+
+// Whether the term needs bytes that have not been read yet.
+fn is_incomplete(result: &Option<Result<BertTermRef<'_>>>) -> bool {
+    return matches!(result, Some(Err(ref e)) if matches!(e.root(), BertError::NotEnoughData { .. }));
+}
+
+// Counts the terms, and ends the iteration after an error; it takes
+// the fields apart from the parser that `result` may borrow.
+fn finish<'a>(
+    result: Option<Result<BertTermRef<'a>>>,
+    terms: &mut usize,
+    failed: &mut bool,
+) -> Option<Result<BertTermRef<'a>>> {
+    match result {
+        Some(Err(e)) => {
+            *failed = true;
+            return Some(Err(e.at_path(vec![PathStep::Term(*terms)])));
+        }
+        result => {
+            *terms += 1;
+            return result;
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::parser::BertParser;
+use crate::prelude::*;
+
+/// The index of a wrap log, stored in `<name>.idx`.
+#[derive(Debug, PartialEq)]
+pub struct WrapLogIndex {
+    /// The file being written to, numbered from 1.
+    pub current: u32,
+    /// The number of items in each file, from file 1.
+    pub items: Vec<u64>,
+}
+
+impl WrapLogIndex {
+    // Version 2 starts with `<<0, 0:32, 2, CurF:32>>` and stores the
+    // number of items in every file on 64 bits; version 1 starts
+    // with `<<0, CurF:32>>` and the oldest version with `<<CurF:8>>`,
+    // and both store them on 32 bits.
+    pub fn parse(bytes: &[u8]) -> Result<WrapLogIndex> {
+        let parser = BertParser::new(bytes.to_vec());
+        let (current, version) = if bytes.len() >= 6 && bytes[..5] == [0; 5] {
+            parser.eat_slice(5)?;
+            let version = parser.eat_u8()?;
+            if version != 2 {
+                return Err(BertError::InvalidWrapLogIndex(5));
+            }
+            (parser.eat_u32_be(), 2)
+        } else if bytes.first() == Some(&0) {
+            parser.eat_u8()?;
+            (parser.eat_u32_be(), 1)
+        } else {
+            (parser.eat_u8().map(u32::from), 0)
+        };
+        let current = match current {
+            Ok(n) if n > 0 && n < WRAP_LOG_MAX_FILES => n,
+            _ => {
+                let offset = [0, 1, 6][version];
+                return Err(BertError::InvalidWrapLogIndex(offset));
+            }
+        };
+
+        let mut items = Vec::new();
+        while !parser.eof() {
+            let offset = parser.position();
+            let n = if version == 2 {
+                parser.eat_u64_be()
+            } else {
+                parser.eat_u32_be().map(u64::from)
+            };
+            match n {
+                Ok(n) if items.len() < WRAP_LOG_MAX_FILES as usize => items.push(n),
+                _ => return Err(BertError::InvalidWrapLogIndex(offset)),
+            }
+        }
+        return Ok(WrapLogIndex { current, items });
+    }
+}
+
+/// Whether `name` is the name of a wrap log, i.e. `<name>.idx`
+/// exists, or the name of its index file.
+pub fn is_wrap_log(name: &str) -> bool {
+    if name.ends_with(".idx") {
+        return true;
+    }
+    return !Path::new(name).exists() && with_suffix(Path::new(name), "idx").exists();
+}
+
+/// Returns the existing files of the wrap log `name`, oldest first.
+/// `name` may also be the name of its index file.
+///
+/// The oldest file is the one after the current file, and the
+/// files wrap around after the last one. The number of files is
+/// read from `<name>.siz`, or guessed from the index.
+pub fn wrap_log_files(name: &Path) -> Result<Vec<PathBuf>> {
+    let base = match name.to_str().and_then(|s| s.strip_suffix(".idx")) {
+        Some(base) => Path::new(base),
+        None => name,
+    };
+    let index = WrapLogIndex::parse(&fs::read(with_suffix(base, "idx"))?)?;
+
+    // The size file ends with `MaxFiles:32`.
+    let max_files = match fs::read(with_suffix(base, "siz")) {
+        Ok(bytes) if bytes.len() >= 8 => {
+            let n = &bytes[bytes.len() - 4..];
+            match u32::from_be_bytes([n[0], n[1], n[2], n[3]]) {
+                n if n > 0 && n < WRAP_LOG_MAX_FILES => n,
+                _ => return Err(BertError::InvalidWrapLogSize),
+            }
+        }
+        Ok(_) => return Err(BertError::InvalidWrapLogSize),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            u32::max(index.current, index.items.len() as u32)
+        }
+        Err(e) => return Err(BertError::IoError(e)),
+    };
+
+    let files = (index.current + 1..=max_files)
+        .chain(1..=index.current)
+        .map(|n| with_suffix(base, &n.to_string()))
+        .filter(|path| path.exists())
+        .collect();
+    return Ok(files);
+}
+
+fn with_suffix(base: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(base);
+    name.push(".");
+    name.push(suffix);
+    return PathBuf::from(name);
+}
@@ -0,0 +1,49 @@
+use ppbert::parser::*;
+use ppbert::pp::*;
+use ppbert::prelude::*;
+
+mod common;
+use common::Output;
+
+fn roundtrip(bytes: &[u8]) -> Result<Vec<u8>> {
+    return roundtrip_with(BertWriter::new(), bytes);
+}
+
+fn roundtrip_with(writer: BertWriter, bytes: &[u8]) -> Result<Vec<u8>> {
+    let term = BertParser::new(bytes.to_vec()).bert1_next().unwrap()?;
+    let buf = Output::default();
+    writer.write(&term, Box::new(buf.clone()))?;
+    let out = buf.0.borrow().clone();
+    return Ok(out);
+}
+
+#[test]
+fn improper_list() {
+    // [1, 2 | 3]
+    let b = &[131, 108, 0, 0, 0, 2, 97, 1, 97, 2, 97, 3];
+    assert_eq!(b.to_vec(), roundtrip(b).unwrap());
+
+    // [1, 2, 3]
+    let b = &[131, 108, 0, 0, 0, 3, 97, 1, 97, 2, 97, 3, 106];
+    assert_eq!(b.to_vec(), roundtrip(b).unwrap());
+}
+
+#[test]
+fn local() {
+    let b = &[131, 121, 0, 0, 0, 7, 104, 1, 97, 1];
+    assert_eq!(b.to_vec(), roundtrip(b).unwrap());
+}
+
+#[test]
+fn canonical() {
+    // {ok, [1, 2], 5.0} with the longest encodings
+    let b = &[
+        131, 105, 0, 0, 0, 3, 100, 0, 2, b'o', b'k', 108, 0, 0, 0, 2, 98, 0, 0, 0, 1, 110, 1, 0, 2,
+        106, 99, b'5', b'.', b'0', 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0,
+    ];
+    assert_eq!(
+        vec![131, 104, 3, 119, 2, b'o', b'k', 107, 0, 2, 1, 2, 70, 64, 20, 0, 0, 0, 0, 0, 0],
+        roundtrip_with(BertWriter::canonical(), b).unwrap()
+    );
+}
@@ -0,0 +1,32 @@
+// Helpers shared by the integration tests; each test uses some of
+// them.
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+// `PrettyPrinter::write` takes ownership of its writer, so the
+// output is collected in a buffer shared with the test.
+#[derive(Clone, Default)]
+pub struct Output(pub Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// [[[...[]...]]], `depth` levels deep.
+pub fn nested_lists(depth: usize) -> Vec<u8> {
+    let mut bytes = vec![131];
+    for _ in 1..depth {
+        bytes.extend_from_slice(&[108, 0, 0, 0, 1]);
+    }
+    bytes.resize(bytes.len() + depth, 106);
+    return bytes;
+}
@@ -0,0 +1,171 @@
+use ppbert::decoder::*;
+use ppbert::framing::{Bert1, Bert2, Packet};
+use ppbert::prelude::*;
+
+#[test]
+fn bert1_byte_by_byte() {
+    let bytes = [131, 100, 0, 2, b'o', b'k'];
+    let mut decoder = Decoder::new(Bert1);
+    for b in &bytes[..5] {
+        assert!(matches!(decoder.feed(&[*b]), Ok(Decoded::NeedMore(_))));
+    }
+    assert_eq!(
+        Decoded::Complete(BertTerm::Atom("ok".to_string()), 6),
+        decoder.feed(&bytes[5..]).unwrap()
+    );
+    assert_eq!(Decoded::NeedMore(1), decoder.decode().unwrap());
+}
+
+#[test]
+fn warnings() {
+    let bytes = [131, 100, 0, 2, b'o', b'k'];
+    let mut decoder = Decoder::new(Bert1);
+    for b in &bytes[..5] {
+        assert!(matches!(decoder.feed(&[*b]), Ok(Decoded::NeedMore(_))));
+        assert!(decoder.take_warnings().is_empty());
+    }
+    assert!(matches!(
+        decoder.feed(&bytes[5..]),
+        Ok(Decoded::Complete(..))
+    ));
+    assert!(matches!(
+        decoder.take_warnings()[..],
+        [BertWarning::Latin1Atom { offset: 1 }]
+    ));
+    assert!(decoder.take_warnings().is_empty());
+
+    // The offsets count the bytes fed so far.
+    assert!(matches!(decoder.feed(&bytes), Ok(Decoded::Complete(..))));
+    assert!(matches!(
+        decoder.take_warnings()[..],
+        [BertWarning::Latin1Atom { offset: 7 }]
+    ));
+}
+
+#[test]
+fn bert1_need_more() {
+    let mut decoder = Decoder::new(Bert1);
+    assert_eq!(
+        Decoded::NeedMore(3),
+        decoder.feed(&[131, 109, 0, 0, 0, 3]).unwrap()
+    );
+    assert_eq!(
+        Decoded::Complete(BertTerm::Binary(b"abc".to_vec()), 9),
+        decoder.feed(b"abc").unwrap()
+    );
+}
+
+#[test]
+fn bert1_invalid_compressed_term() {
+    // <<104, 2, 97, 1>> compressed: more bytes cannot complete it.
+    let mut decoder = Decoder::new(Bert1);
+    let bytes = [
+        131, 80, 0, 0, 0, 4, 120, 156, 203, 96, 74, 100, 4, 0, 2, 109, 0, 205,
+    ];
+    assert!(matches!(
+        decoder.feed(&bytes).unwrap_err().root(),
+        BertError::TruncatedCompressedTerm { .. }
+    ));
+    assert!(decoder.feed(&[131, 97, 1]).is_err());
+}
+
+#[test]
+fn bert2_several_terms_per_chunk() {
+    let mut decoder = Decoder::new(Bert2);
+    assert_eq!(
+        Decoded::Complete(BertTerm::Int(1), 4),
+        decoder.feed(&[3, 131, 97, 1, 3, 131, 97, 2, 3]).unwrap()
+    );
+    assert_eq!(
+        Decoded::Complete(BertTerm::Int(2), 4),
+        decoder.decode().unwrap()
+    );
+    // Like `Bert2` elsewhere, the decoder ignores the length.
+    assert_eq!(Decoded::NeedMore(1), decoder.decode().unwrap());
+    assert_eq!(
+        Decoded::Complete(BertTerm::Int(3), 4),
+        decoder.feed(&[131, 97, 3]).unwrap()
+    );
+}
+
+#[test]
+fn packet() {
+    let mut decoder = Decoder::new(Packet::new(4).unwrap());
+    assert_eq!(Decoded::NeedMore(2), decoder.feed(&[0, 0]).unwrap());
+    assert_eq!(Decoded::NeedMore(3), decoder.feed(&[0, 3]).unwrap());
+    assert_eq!(
+        Decoded::Complete(BertTerm::Int(7), 7),
+        decoder.feed(&[131, 97, 7]).unwrap()
+    );
+
+    let mut decoder = Decoder::new(Packet::new(2).unwrap());
+    assert_eq!(
+        Decoded::Complete(BertTerm::Nil, 4),
+        decoder.feed(&[0, 2, 131, 106]).unwrap()
+    );
+}
+
+#[test]
+fn packet_invalid_length() {
+    // The first frame is one byte too long; the decoder skips it.
+    let mut decoder = Decoder::new(Packet::new(1).unwrap());
+    assert!(matches!(
+        decoder.feed(&[4, 131, 97, 1, 0, 3, 131, 97, 2]),
+        Err(BertError::InvalidFrameLength {
+            offset: 1,
+            expected: 4,
+            actual: 3,
+        })
+    ));
+    assert_eq!(
+        Decoded::Complete(BertTerm::Int(2), 4),
+        decoder.decode().unwrap()
+    );
+
+    // The term is longer than its frame.
+    let mut decoder = Decoder::new(Packet::new(1).unwrap());
+    assert!(matches!(
+        decoder.feed(&[2, 131, 97, 1]),
+        Err(BertError::InvalidFrameLength {
+            offset: 1,
+            expected: 2,
+            actual: 3,
+        })
+    ));
+    assert_eq!(Decoded::NeedMore(1), decoder.decode().unwrap());
+}
+
+#[test]
+fn packet_invalid_compressed_term() {
+    // The errors in a compressed term have offsets in its inflated
+    // bytes, which may be smaller than the offset of its frame.
+    let mut bytes = Vec::new();
+    for _ in 0..40 {
+        bytes.extend_from_slice(&[0, 0, 0, 3, 131, 97, 1]);
+    }
+    // <<104, 2, 97, 1>> compressed
+    bytes.extend_from_slice(&[
+        0, 0, 0, 18, 131, 80, 0, 0, 0, 4, 120, 156, 203, 96, 74, 100, 4, 0, 2, 109, 0, 205,
+    ]);
+    bytes.extend_from_slice(&[0, 0, 0, 3, 131, 97, 2]);
+
+    let mut decoder = Decoder::new(Packet::new(4).unwrap());
+    assert_eq!(
+        Decoded::Complete(BertTerm::Int(1), 7),
+        decoder.feed(&bytes).unwrap()
+    );
+    for _ in 1..40 {
+        assert_eq!(
+            Decoded::Complete(BertTerm::Int(1), 7),
+            decoder.decode().unwrap()
+        );
+    }
+    assert!(matches!(
+        decoder.decode().unwrap_err().root(),
+        BertError::TruncatedCompressedTerm { .. }
+    ));
+    assert_eq!(
+        Decoded::Complete(BertTerm::Int(2), 7),
+        decoder.decode().unwrap()
+    );
+}
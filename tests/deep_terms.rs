@@ -0,0 +1,187 @@
+use std::io::{self, Write};
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use ppbert::parser::*;
+use ppbert::pp::*;
+use ppbert::prelude::*;
+
+mod common;
+use common::{nested_lists, Output};
+
+// The terms are parsed without limits, so that their depth is only
+// limited by memory.
+const DEPTH: usize = 100_000;
+
+#[test]
+fn parse_and_drop() {
+    let bytes = nested_lists(DEPTH);
+    let mut parser = BertParser::with_limits(bytes.clone(), ParserLimits::none());
+    let term = parser.bert1_next().unwrap().unwrap();
+    assert!(matches!(term, BertTerm::List(_)));
+    term.drop_deep();
+
+    let parser = BertParser::with_limits(bytes, ParserLimits::none());
+    let term = parser.bert1_next_ref().unwrap().unwrap();
+    let copy = term.clone().into_static();
+    drop(term);
+    let owned = copy.to_owned();
+    drop(owned.as_term_ref());
+    owned.drop_deep();
+}
+
+#[test]
+fn print() {
+    let bytes = nested_lists(DEPTH);
+    let term = BertParser::with_limits(bytes.clone(), ParserLimits::none())
+        .bert1_next()
+        .unwrap()
+        .unwrap();
+
+    let out = Output::default();
+    BertWriter::new()
+        .write(&term, Box::new(out.clone()))
+        .unwrap();
+    assert_eq!(bytes, *out.0.borrow());
+
+    let out = Output::default();
+    JsonPrettyPrinter::new(false)
+        .write(&term, Box::new(out.clone()))
+        .unwrap();
+    let json = "[".repeat(DEPTH - 1) + "[]" + &"]".repeat(DEPTH - 1) + "\n";
+    assert_eq!(json.as_bytes(), &out.0.borrow()[..]);
+
+    let pp = ErlangPrettyPrinter::new(0, 6, "");
+    let out = Output::default();
+    pp.write(&term, Box::new(out.clone())).unwrap();
+    let erlang = "[".repeat(DEPTH - 1) + "[]" + &"]".repeat(DEPTH - 1) + "\n";
+    assert_eq!(erlang.as_bytes(), &out.0.borrow()[..]);
+    term.drop_deep();
+}
+
+#[test]
+fn nested_funs_and_locals() {
+    // fun() -> X end whose environment holds the same fun, and so
+    // on, all wrapped in LOCAL_EXT terms.
+    let mut bytes = vec![131];
+    for _ in 0..DEPTH {
+        bytes.extend_from_slice(&[121, 0, 0, 0, 1]);
+        bytes.extend_from_slice(&[117, 0, 0, 0, 1]);
+        bytes.extend_from_slice(&[88, 100, 0, 1, b'n', 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 0]);
+        bytes.extend_from_slice(&[100, 0, 1, b'm', 97, 1, 97, 2]);
+    }
+    bytes.push(106);
+
+    let term = BertParser::with_limits(bytes.clone(), ParserLimits::none())
+        .bert1_next()
+        .unwrap()
+        .unwrap();
+    let out = Output::default();
+    BertWriter::new()
+        .write(&term, Box::new(out.clone()))
+        .unwrap();
+    // Only the atom tags differ: the writer encodes atoms as UTF-8.
+    assert_eq!(bytes.len(), out.0.borrow().len());
+    JsonPrettyPrinter::new(false)
+        .write(&term, Box::new(io::sink()))
+        .unwrap();
+    term.drop_deep();
+}
+
+#[test]
+fn compare_and_format() {
+    let bytes = nested_lists(DEPTH);
+    let term = BertParser::with_limits(bytes.clone(), ParserLimits::none())
+        .bert1_next()
+        .unwrap()
+        .unwrap();
+    let same = BertParser::with_limits(bytes.clone(), ParserLimits::none())
+        .bert1_next()
+        .unwrap()
+        .unwrap();
+    assert!(term == same);
+
+    // The innermost [] is 1 instead.
+    let mut other = bytes.clone();
+    other[1 + 5 * (DEPTH - 1)] = 97;
+    other.insert(2 + 5 * (DEPTH - 1), 1);
+    let other = BertParser::with_limits(other, ParserLimits::none())
+        .bert1_next()
+        .unwrap()
+        .unwrap();
+    assert!(term != other);
+
+    let debug = "List([".repeat(DEPTH - 1) + "Nil" + &"])".repeat(DEPTH - 1);
+    assert_eq!(debug, format!("{:?}", term));
+
+    let parser = BertParser::with_limits(bytes, ParserLimits::none());
+    let term_ref = parser.bert1_next_ref().unwrap().unwrap();
+    assert!(term_ref == term.as_term_ref());
+    assert!(term_ref != other.as_term_ref());
+    assert_eq!(debug, format!("{:?}", term_ref));
+    term.drop_deep();
+    same.drop_deep();
+    other.drop_deep();
+}
+
+#[test]
+fn format_like_derive() {
+    let fun = BertFun {
+        module: "m".to_string(),
+        arity: Some(1),
+        uniq: [0; 16],
+        index: 2,
+        old_index: 3,
+        old_uniq: 4,
+        pid: BertTerm::Pid {
+            node: "n".to_string(),
+            id: 1,
+            serial: 0,
+            creation: 5,
+        },
+        free_vars: vec![BertTerm::Int(1), BertTerm::Nil],
+    };
+    let term = BertTerm::Tuple(vec![
+        BertTerm::ImproperList(
+            vec![BertTerm::Float(1.5)],
+            Box::new(BertTerm::Atom("a".to_string())),
+        ),
+        BertTerm::Map(
+            vec![BertTerm::Binary(vec![1, 2])],
+            vec![BertTerm::Local(7, Box::new(BertTerm::Truncated))],
+        ),
+        BertTerm::Fun(Box::new(fun)),
+    ]);
+    let expected = "Tuple([ImproperList([Float(1.5)], Atom(\"a\")), \
+                    Map([Binary([1, 2])], [Local(7, Truncated)]), \
+                    Fun(BertFun { module: \"m\", arity: Some(1), \
+                    uniq: [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], index: 2, \
+                    old_index: 3, old_uniq: 4, \
+                    pid: Pid { node: \"n\", id: 1, serial: 0, creation: 5 }, \
+                    free_vars: [Int(1), Nil] })])";
+    assert_eq!(expected, format!("{:?}", term));
+    assert_eq!(
+        expected.replace("BertFun", "BertFunRef"),
+        format!("{:?}", term.as_term_ref())
+    );
+}
+
+#[test]
+fn nested_compressed_terms() {
+    // 1, compressed 1000 times over.
+    let mut term = vec![97, 1];
+    for _ in 0..1000 {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&term).unwrap();
+        let mut compressed = vec![80];
+        compressed.extend_from_slice(&(term.len() as u32).to_be_bytes());
+        compressed.extend_from_slice(&encoder.finish().unwrap());
+        term = compressed;
+    }
+    term.insert(0, 131);
+
+    let err = BertParser::new(term).bert1_next().unwrap().unwrap_err();
+    assert_eq!(&[PathStep::Compressed], err.path());
+    assert!(matches!(err.root(), BertError::NestedCompressedTerm(0)));
+}
@@ -0,0 +1,39 @@
+use ppbert::parser::*;
+use ppbert::pp::*;
+
+mod common;
+use common::Output;
+
+fn erlang(bytes: &[u8]) -> String {
+    let term = BertParser::new(bytes.to_vec())
+        .bert1_next()
+        .unwrap()
+        .unwrap();
+    let out = Output::default();
+    ErlangPrettyPrinter::new(2, 6, "")
+        .write(&term, Box::new(out.clone()))
+        .unwrap();
+    let s = String::from_utf8(out.0.borrow().clone()).unwrap();
+    return s;
+}
+
+#[test]
+fn nodes() {
+    // a pid of the node a@b
+    assert_eq!(
+        "<a@b.1.0>\n",
+        erlang(b"\x83\x58\x77\x03a@b\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00\x02")
+    );
+
+    // a port of the node a@b
+    assert_eq!(
+        "#Port<a@b.5>\n",
+        erlang(b"\x83\x59\x77\x03a@b\x00\x00\x00\x05\x00\x00\x00\x02")
+    );
+
+    // a reference of the node a@b, whose ids are [1, 2, 3]
+    assert_eq!(
+        "#Ref<a@b.3.2.1>\n",
+        erlang(b"\x83\x5a\x00\x03\x77\x03a@b\x00\x00\x00\x02\x00\x00\x00\x01\x00\x00\x00\x02\x00\x00\x00\x03")
+    );
+}
@@ -0,0 +1,130 @@
+#!/usr/bin/env python3
+# Writes dist.pcap and dist.pcapng: a distribution connection between
+# a@host (10.0.0.1 / fd00::1, port 51000) and b@host (10.0.0.2 /
+# fd00::2, port 4370), with its handshake, a tick, and two messages,
+# one of which is split into segments sent out of order and then
+# retransmitted.
+
+import struct
+
+A = ("a@host", 51000)
+B = ("b@host", 4370)
+
+
+def atom_cache_header(atoms):
+    # Every entry is new, in segment 0.
+    n = len(atoms)
+    flags = bytearray(n // 2 + 1)
+    for i in range(n):
+        flags[i // 2] |= 0x8 << (4 * (i % 2))
+    refs = b""
+    for i, atom in enumerate(atoms):
+        refs += bytes([i + 1, len(atom)]) + atom
+    return bytes([131, 68, n]) + bytes(flags) + refs
+
+
+def dist(message):
+    return struct.pack(">I", len(message)) + message
+
+
+def handshake(message):
+    return struct.pack(">H", len(message)) + message
+
+
+def name_message(name, challenge=None):
+    flags = struct.pack(">Q", 0x1000000)
+    creation = struct.pack(">I", 7)
+    name = name.encode()
+    if challenge is None:
+        return b"N" + flags + creation + struct.pack(">H", len(name)) + name
+    return (b"N" + flags + struct.pack(">I", challenge) + creation
+            + struct.pack(">H", len(name)) + name)
+
+
+# {6, hello, [], world} with a payload {hello, 42}
+a_message = dist(atom_cache_header([b"hello", b"world"])
+                 + bytes([104, 4, 97, 6, 82, 0, 106, 82, 1])
+                 + bytes([104, 2, 82, 0, 97, 42]))
+# {2, [], pong}, without a payload
+b_message = dist(atom_cache_header([b"pong"]) + bytes([104, 3, 97, 2, 106, 82, 0]))
+
+# (time in microseconds, sender, flags, payload)
+SYN, ACK = 0x02, 0x10
+events = [
+    (0, A, SYN, b""),
+    (10, B, SYN | ACK, b""),
+    (20, A, ACK, handshake(name_message(A[0]))),
+    (30, B, ACK, handshake(b"sok")),
+    (40, B, ACK, handshake(name_message(B[0], challenge=0x12345678))),
+    (50, A, ACK, handshake(b"r" + struct.pack(">I", 1) + bytes(16))),
+    (60, B, ACK, handshake(b"a" + bytes(16)) + dist(b"")),
+    (1_500_000, A, ACK, None),  # a_message, in three segments
+    (2_000_000, B, ACK, b_message),
+]
+
+seq = {A: 1000, B: 5000}
+
+
+def segments():
+    for t, src, flags, payload in events:
+        dst = B if src == A else A
+        if payload is None:
+            s = seq[src]
+            parts = [(s + 10, a_message[10:]), (s, a_message[:10]), (s, a_message[:20])]
+            for i, (s, part) in enumerate(parts):
+                yield t + i, src, dst, s, ACK, part
+            seq[src] += len(a_message)
+            continue
+        yield t, src, dst, seq[src], flags, payload
+        seq[src] += len(payload) + (1 if flags & SYN else 0)
+
+
+def tcp(src, dst, s, flags, payload):
+    return struct.pack(">HHIIBBHHH", src[1], dst[1], s, 0, 5 << 4, flags, 65535, 0, 0) + payload
+
+
+def ipv4(src, dst, segment):
+    addr = {A: bytes([10, 0, 0, 1]), B: bytes([10, 0, 0, 2])}
+    header = struct.pack(">BBHHHBBH", 0x45, 0, 20 + len(segment), 0, 0, 64, 6, 0)
+    return header + addr[src] + addr[dst] + segment
+
+
+def ipv6(src, dst, segment):
+    addr = {A: bytes(15) + b"\x01", B: bytes(15) + b"\x02"}
+    addr = {k: b"\xfd" + v[1:] for k, v in addr.items()}
+    header = struct.pack(">IHBB", 6 << 28, len(segment), 6, 64)
+    return header + addr[src] + addr[dst] + segment
+
+
+def ethernet(ip, ethertype):
+    return bytes(6) + bytes(6) + struct.pack(">H", ethertype) + ip
+
+
+BASE = 1_700_000_000
+
+with open("dist.pcap", "wb") as f:
+    f.write(struct.pack("<IHHiIII", 0xA1B2C3D4, 2, 4, 0, 0, 65535, 1))
+    for t, src, dst, s, flags, payload in segments():
+        frame = ethernet(ipv4(src, dst, tcp(src, dst, s, flags, payload)), 0x0800)
+        secs, usecs = divmod(t, 1_000_000)
+        f.write(struct.pack("<IIII", BASE + secs, usecs, len(frame), len(frame)) + frame)
+
+seq = {A: 1000, B: 5000}
+
+
+def block(block_type, body):
+    body += bytes(-len(body) % 4)
+    length = 12 + len(body)
+    return struct.pack("<II", block_type, length) + body + struct.pack("<I", length)
+
+
+with open("dist.pcapng", "wb") as f:
+    f.write(block(0x0A0D0D0A, struct.pack("<IHHq", 0x1A2B3C4D, 1, 0, -1)))
+    # Raw IP, with nanosecond timestamps (if_tsresol = 9).
+    options = struct.pack("<HHB", 9, 1, 9) + bytes(3) + struct.pack("<HH", 0, 0)
+    f.write(block(1, struct.pack("<HHI", 101, 0, 65535) + options))
+    for t, src, dst, s, flags, payload in segments():
+        packet = ipv6(src, dst, tcp(src, dst, s, flags, payload))
+        ts = (BASE * 1_000_000 + t) * 1000
+        f.write(block(6, struct.pack("<IIIII", 0, ts >> 32, ts & 0xFFFFFFFF,
+                                     len(packet), len(packet)) + packet))
@@ -0,0 +1,132 @@
+use std::io::Cursor;
+
+use ppbert::framing::*;
+use ppbert::parser::*;
+use ppbert::prelude::*;
+use ppbert::stream::StreamParser;
+
+fn terms<F: Framing>(bytes: &[u8], framing: F) -> Vec<(usize, Result<BertTerm>)> {
+    BertParser::new(bytes.to_vec()).terms(framing).collect()
+}
+
+// Terms preceded by a one-byte tag, which is ignored.
+struct Tagged;
+
+impl Framing for Tagged {
+    fn next_term<'a>(&self, parser: &'a BertParser) -> Option<Result<BertTermRef<'a>>> {
+        if parser.eof() {
+            return None;
+        }
+        let result = parser
+            .eat_u8()
+            .and_then(|_| parser.magic_number())
+            .and_then(|_| parser.bert_term_ref());
+        return Some(result);
+    }
+}
+
+#[test]
+fn offsets() {
+    let bert1 = terms(&[131, 97, 1, 131, 106], Bert1);
+    assert!(matches!(
+        bert1[..],
+        [(0, Ok(BertTerm::Int(1))), (3, Ok(BertTerm::Nil))]
+    ));
+
+    let bert2 = terms(&[3, 131, 97, 1, 2, 131, 106], Bert2);
+    assert!(matches!(
+        bert2[..],
+        [(0, Ok(BertTerm::Int(1))), (4, Ok(BertTerm::Nil))]
+    ));
+
+    // The offset of the first record includes the header.
+    let disk_log = [
+        1, 2, 3, 4, 6, 7, 8, 9, // header
+        0, 0, 0, 3, 0x62, 0x57, 0x4c, 0x41, 131, 97, 1, // 1
+        0, 0, 0, 3, 0x62, 0x57, 0x4c, 0x41, 131, 97, 2, // 2
+    ];
+    assert!(matches!(
+        terms(&disk_log, DiskLog)[..],
+        [(0, Ok(BertTerm::Int(1))), (19, Ok(BertTerm::Int(2)))]
+    ));
+}
+
+#[test]
+fn ends_after_error() {
+    let bytes = [131, 97, 1, 130, 97, 2, 131, 97, 3];
+    let terms = terms(&bytes, Bert1);
+    assert_eq!(2, terms.len());
+    assert!(matches!(terms[0], (0, Ok(BertTerm::Int(1)))));
+    match terms[1] {
+        (3, Err(ref e)) => {
+            assert_eq!(&[PathStep::Term(1)], e.path());
+            assert!(matches!(
+                e.root(),
+                BertError::InvalidMagicNumber { offset: 3, .. }
+            ));
+        }
+        _ => panic!("expected an error"),
+    }
+}
+
+#[test]
+fn packet() {
+    assert!(Packet::new(3).is_none());
+    let packet = Packet::new(2).unwrap();
+    assert!(matches!(
+        terms(&[0, 2, 131, 106, 0, 3, 131, 97, 1], packet)[..],
+        [(0, Ok(BertTerm::Nil)), (4, Ok(BertTerm::Int(1)))]
+    ));
+}
+
+#[test]
+fn custom_framing() {
+    let bytes = [7, 131, 97, 1, 8, 131, 106];
+    assert!(matches!(
+        terms(&bytes, Tagged)[..],
+        [(0, Ok(BertTerm::Int(1))), (4, Ok(BertTerm::Nil))]
+    ));
+
+    let boxed: Box<dyn Framing> = Box::new(Tagged);
+    let stream: Vec<_> = StreamParser::new(Cursor::new(bytes.to_vec()), boxed).collect();
+    assert!(matches!(
+        stream[..],
+        [Ok(BertTerm::Int(1)), Ok(BertTerm::Nil)]
+    ));
+}
+
+#[test]
+fn detect_format() {
+    assert_eq!(Some(Format::Bert1), detect(&[131, 97, 1]));
+    assert_eq!(Some(Format::CompressedBert1), detect(&[131, 80, 0, 0]));
+    assert_eq!(Some(Format::Bert2), detect(&[3, 131, 97, 1]));
+    assert_eq!(Some(Format::Bert2), detect(&[0x80, 0x01, 131, 104, 2]));
+
+    // A BERT2 term of 131 bytes, whose varint starts with the magic
+    // number.
+    let mut bert2 = vec![0x83, 0x01, 131, 109, 0, 0, 0, 125];
+    bert2.extend_from_slice(&[0; 125]);
+    assert_eq!(Some(Format::Bert2), detect(&bert2[..DETECT_LEN]));
+    assert!(matches!(
+        terms(&bert2, Bert2)[..],
+        [(0, Ok(BertTerm::Binary(ref b)))] if b.len() == 125
+    ));
+
+    assert_eq!(Some(Format::Packet4), detect(&[0, 0, 0, 3, 131, 97, 1]));
+    // A BERT2 term of 3,000,000 bytes, whose varint is 4 bytes long.
+    assert_eq!(
+        Some(Format::Bert2),
+        detect(&[0xc0, 0x8d, 0xb7, 0x01, 131, 109, 0, 0x2d, 0xc6])
+    );
+    assert_eq!(Some(Format::DiskLog), detect(&[1, 2, 3, 4, 6, 7, 8, 9, 0]));
+    assert_eq!(
+        Some(Format::HaltLog),
+        detect(&[0, 0, 0, 3, 0x62, 0x57, 0x4c, 0x41, 131])
+    );
+
+    // An empty varint is no term, nor is a varint of 9 bytes.
+    assert_eq!(None, detect(&[0, 131, 106]));
+    assert_eq!(None, detect(&[0x80; 8]));
+    assert_eq!(None, detect(&[]));
+    assert_eq!(None, detect(b"hello"));
+}
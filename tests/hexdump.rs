@@ -0,0 +1,152 @@
+use ppbert::framing::{Bert2, DiskLog, Framing};
+use ppbert::hexdump::{write_explanation, write_hex_dump};
+use ppbert::parser::*;
+
+fn dump(bytes: &[u8]) -> String {
+    let mut parser = BertParser::new(bytes.to_vec());
+    parser.set_tracing(Trace::Terms);
+    let err = parser.bert1_next().unwrap().unwrap_err();
+    let (base, buffer) = parser.buffer();
+    let mut out = Vec::new();
+    write_hex_dump(
+        &mut out,
+        base,
+        buffer,
+        err.offset().unwrap(),
+        &parser.annotations(),
+    )
+    .unwrap();
+    return String::from_utf8(out).unwrap();
+}
+
+#[test]
+fn annotations() {
+    let mut parser = BertParser::new(vec![131, 104, 2, 97, 1, 108, 0, 0, 0, 1, 106, 106]);
+    parser.set_tracing(Trace::Terms);
+    assert!(parser.bert1_next().unwrap().is_ok());
+    let labels: Vec<_> = parser
+        .annotations()
+        .into_iter()
+        .map(|a| (a.offset, a.len, a.depth, a.label))
+        .collect();
+    assert_eq!(
+        vec![
+            (1, 2, 0, "SMALL_TUPLE_EXT arity=2".to_string()),
+            (3, 2, 1, "SMALL_INTEGER_EXT".to_string()),
+            (5, 5, 1, "LIST_EXT length=1".to_string()),
+            (10, 1, 2, "NIL_EXT".to_string()),
+            (11, 1, 2, "NIL_EXT".to_string()),
+        ],
+        labels
+    );
+
+    // Nothing is recorded without tracing.
+    let mut parser = BertParser::new(vec![131, 97, 1]);
+    assert!(parser.bert1_next().unwrap().is_ok());
+    assert!(parser.annotations().is_empty());
+}
+
+#[test]
+fn invalid_tag() {
+    // {1, [ok, <invalid>]}
+    let bytes = [
+        131, 104, 2, 97, 1, 108, 0, 0, 0, 2, 119, 2, b'o', b'k', 255, 0, 0,
+    ];
+    let expected = [
+        "         0  83",
+        "         1  68 02                     SMALL_TUPLE_EXT arity=2",
+        "         3  61 01                       SMALL_INTEGER_EXT",
+        "         5  6c 00 00 00 02              LIST_EXT length=2",
+        "        10  77 02 6f 6b                   SMALL_ATOM_UTF8_EXT",
+        ">       14  [ff] 00 00",
+    ];
+    assert_eq!(expected.join("\n") + "\n", dump(&bytes));
+}
+
+#[test]
+fn end_of_input() {
+    let bytes = [131, 108, 0, 0, 0, 2, 97, 1];
+    let expected = [
+        "         0  83",
+        "         1  6c 00 00 00 02            LIST_EXT length=2",
+        "         6  61 01                       SMALL_INTEGER_EXT",
+        ">        8  (end of input)",
+    ];
+    assert_eq!(expected.join("\n") + "\n", dump(&bytes));
+}
+
+#[test]
+fn window() {
+    // [<<100 bytes>>, <invalid>]: only the bytes shortly before the
+    // error are shown, in lines of 8 bytes.
+    let mut bytes = vec![131, 108, 0, 0, 0, 2, 109, 0, 0, 0, 100];
+    bytes.resize(111, 0xaa);
+    bytes.push(255);
+    let out = dump(&bytes);
+    let lines: Vec<_> = out.lines().collect();
+    assert_eq!(7, lines.len());
+    assert_eq!(
+        "        63  aa aa aa aa aa aa aa aa     BINARY_EXT",
+        lines[0]
+    );
+    assert_eq!("       103  aa aa aa aa aa aa aa aa", lines[5]);
+    assert_eq!(">      111  [ff]", lines[6]);
+}
+
+fn explain(bytes: &[u8], framing: impl Framing) -> Vec<String> {
+    let mut parser = BertParser::new(bytes.to_vec());
+    parser.set_tracing(Trace::Fields);
+    assert!(framing.next_term(&parser).unwrap().is_ok());
+    let (base, buffer) = parser.buffer();
+    let mut out = Vec::new();
+    write_explanation(&mut out, base, buffer, &parser.annotations()).unwrap();
+    return String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect();
+}
+
+#[test]
+fn explanation() {
+    // {-1, [<<"abcdefghij">>]} in BERT2
+    let bytes = [
+        25, 131, 104, 2, 98, 255, 255, 255, 255, 108, 0, 0, 0, 1, 109, 0, 0, 0, 10, b'a', b'b',
+        b'c', b'd', b'e', b'f', b'g', b'h', b'i', b'j', 106,
+    ];
+    let expected = [
+        "       0  19                        length (varint): 25",
+        "       1  83                        magic number",
+        "       2  68                        SMALL_TUPLE_EXT",
+        "       3  02                          arity: 2",
+        "       4  62                          INTEGER_EXT",
+        "       5  ff ff ff ff                   value: -1",
+        "       9  6c                          LIST_EXT",
+        "      10  00 00 00 01                   length: 1",
+        "      14  6d                            BINARY_EXT",
+        "      15  00 00 00 0a                     length: 10",
+        "      19  61 62 63 64 65 66 67 ..         data",
+        "      29  6a                            NIL_EXT",
+    ];
+    assert_eq!(expected[..], explain(&bytes, Bert2)[..]);
+}
+
+#[test]
+fn explanation_disk_log() {
+    let bytes = [
+        1, 2, 3, 4, 6, 7, 8, 9, // header
+        0, 0, 0, 6, 0x62, 0x57, 0x4c, 0x41, 131, 110, 2, 1, 0, 1,
+    ];
+    let expected = [
+        "       0  01 02 03 04               disk_log magic",
+        "       4  06 07 08 09               disk_log status: opened",
+        "       8  00 00 00 06               record length: 6",
+        "      12  62 57 4c 41               record magic",
+        "      16  83                        magic number",
+        "      17  6e                        SMALL_BIG_EXT",
+        "      18  02                          length: 2",
+        "      19  01                          sign: negative",
+        "      20  00 01                       digits, little-endian: 256",
+    ];
+    assert_eq!(expected[..], explain(&bytes, DiskLog)[..]);
+}
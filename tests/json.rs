@@ -0,0 +1,42 @@
+use ppbert::parser::*;
+use ppbert::pp::*;
+
+mod common;
+use common::Output;
+
+fn json(bytes: &[u8]) -> String {
+    let term = BertParser::new(bytes.to_vec())
+        .bert1_next()
+        .unwrap()
+        .unwrap();
+    let out = Output::default();
+    JsonPrettyPrinter::new(false)
+        .write(&term, Box::new(out.clone()))
+        .unwrap();
+    let s = String::from_utf8(out.0.borrow().clone()).unwrap();
+    return s;
+}
+
+#[test]
+fn escaped_atoms() {
+    // 'a"b\c'
+    assert_eq!("\"a\\\"b\\\\c\"\n", json(b"\x83\x77\x05a\"b\\c"));
+
+    // 'é\n' keeps its UTF-8 character
+    assert_eq!("\"\u{e9}\\u000a\"\n", json(b"\x83\x77\x03\xc3\xa9\n"));
+}
+
+#[test]
+fn escaped_nodes() {
+    // a pid of the node 'a"@b'
+    assert_eq!(
+        "{\"type\":\"pid\",\"node\":\"a\\\"@b\",\"id\":1,\"serial\":0,\"creation\":2}\n",
+        json(b"\x83\x58\x77\x04a\"@b\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00\x02")
+    );
+
+    // fun 'm\\':'f"'/0
+    assert_eq!(
+        "{\"type\":\"export\",\"module\":\"m\\\\\",\"function\":\"f\\\"\",\"arity\":0}\n",
+        json(b"\x83\x71\x77\x02m\\\x77\x02f\"\x61\x00")
+    );
+}
@@ -0,0 +1,110 @@
+use std::io::Cursor;
+
+use ppbert::framing::*;
+use ppbert::parser::*;
+use ppbert::pp::*;
+use ppbert::prelude::*;
+use ppbert::stream::StreamParser;
+
+mod common;
+use common::Output;
+
+// [1, {ok, <<"ab" (of 16 bytes)
+const TRUNCATED: [u8; 22] = [
+    131, 108, 0, 0, 0, 3, 97, 1, 104, 2, 100, 0, 2, b'o', b'k', 109, 0, 0, 0, 16, b'a', b'b',
+];
+
+fn partial(bytes: &[u8], framing: impl Framing) -> Option<BertTerm> {
+    let mut parser = BertParser::new(bytes.to_vec());
+    parser.set_lenient(true);
+    let result = framing
+        .next_term(&parser)
+        .unwrap()
+        .map(BertTermRef::into_owned);
+    assert!(matches!(
+        result.unwrap_err().root(),
+        BertError::NotEnoughData { .. }
+    ));
+    return parser.take_partial();
+}
+
+fn print(pp: &dyn PrettyPrinter, term: &BertTerm) -> String {
+    let out = Output::default();
+    pp.write(term, Box::new(out.clone())).unwrap();
+    return String::from_utf8(out.0.take()).unwrap();
+}
+
+#[test]
+fn containers() {
+    let expected = BertTerm::List(vec![
+        BertTerm::Int(1),
+        BertTerm::Tuple(vec![BertTerm::Atom("ok".to_string()), BertTerm::Truncated]),
+    ]);
+    assert_eq!(Some(expected), partial(&TRUNCATED, Bert1));
+
+    // #{a => 1, b =>
+    let map = [131, 116, 0, 0, 0, 2, 119, 1, b'a', 97, 1, 119, 1, b'b'];
+    let expected = BertTerm::Map(
+        vec![
+            BertTerm::Atom("a".to_string()),
+            BertTerm::Atom("b".to_string()),
+        ],
+        vec![BertTerm::Int(1), BertTerm::Truncated],
+    );
+    assert_eq!(Some(expected), partial(&map, Bert1));
+
+    // [1 | (the tail is missing)
+    let improper = [131, 108, 0, 0, 0, 1, 97, 1];
+    let expected = BertTerm::ImproperList(vec![BertTerm::Int(1)], Box::new(BertTerm::Truncated));
+    assert_eq!(Some(expected), partial(&improper, Bert1));
+
+    // A truncated leaf is a marker.
+    assert_eq!(Some(BertTerm::Truncated), partial(&[131, 98, 0, 0], Bert1));
+}
+
+#[test]
+fn framed() {
+    // The frame announces more bytes than there are.
+    let mut packet = vec![0, 0, 0, 30];
+    packet.extend_from_slice(&TRUNCATED);
+    let term = partial(&packet, Packet::new(4).unwrap()).unwrap();
+    assert!(matches!(term, BertTerm::List(_)));
+}
+
+#[test]
+fn not_lenient() {
+    let mut parser = BertParser::new(TRUNCATED.to_vec());
+    assert!(parser.bert1_next().unwrap().is_err());
+    assert_eq!(None, parser.take_partial());
+
+    // Until the end of the input, a truncated term is incomplete.
+    let mut parser = BertParser::new(TRUNCATED.to_vec());
+    parser.set_lenient(true);
+    parser.set_more_input(true);
+    assert!(parser.bert1_next().unwrap().is_err());
+    assert_eq!(None, parser.take_partial());
+}
+
+#[test]
+fn stream() {
+    let mut bytes = vec![131, 97, 1];
+    bytes.extend_from_slice(&TRUNCATED);
+    let mut stream = StreamParser::new(Cursor::new(bytes), Bert1);
+    stream.set_lenient(true);
+    assert!(matches!(stream.next(), Some(Ok(BertTerm::Int(1)))));
+    assert!(matches!(stream.next(), Some(Err(_))));
+    assert!(matches!(stream.take_partial(), Some(BertTerm::List(_))));
+}
+
+#[test]
+fn printers() {
+    let term = partial(&TRUNCATED, Bert1).unwrap();
+    assert!(print(&ErlangPrettyPrinter::new(2, 4, ""), &term).contains("{ok, ...truncated here}"));
+    assert_eq!(
+        "[1,[\"ok\",{\"type\":\"truncated\"}]]\n",
+        print(&JsonPrettyPrinter::new(false), &term)
+    );
+    assert!(BertWriter::new()
+        .write(&term, Box::new(Output::default()))
+        .is_err());
+}
@@ -0,0 +1,68 @@
+use ppbert::lint::*;
+use ppbert::parser::*;
+
+fn differences(bytes: &[u8]) -> Vec<Difference> {
+    let mut parser = BertParser::new(bytes.to_vec());
+    parser.set_tracing(Trace::Terms);
+    parser.magic_number().unwrap();
+    let term = parser.bert_term_ref().unwrap();
+    return lint(&term, &bytes[1..], 1, &parser.annotations()).unwrap();
+}
+
+fn difference(offset: usize, actual: &[u8], expected: &[u8]) -> Difference {
+    Difference {
+        offset,
+        actual: actual.to_vec(),
+        expected: expected.to_vec(),
+    }
+}
+
+#[test]
+fn canonical() {
+    // {ok, "ab", 300, 1.5, <<>>, #{}}
+    let bytes = [
+        131, 104, 6, 119, 2, b'o', b'k', 107, 0, 2, b'a', b'b', 98, 0, 0, 1, 44, 70, 63, 248, 0, 0,
+        0, 0, 0, 0, 109, 0, 0, 0, 0, 116, 0, 0, 0, 0,
+    ];
+    assert!(differences(&bytes).is_empty());
+}
+
+#[test]
+fn headers() {
+    // {ok} as a LARGE_TUPLE_EXT of an ATOM_UTF8_EXT
+    let bytes = [131, 105, 0, 0, 0, 1, 118, 0, 2, b'o', b'k'];
+    assert_eq!(
+        vec![
+            difference(1, &[105, 0, 0, 0, 1], &[104, 1]),
+            difference(6, &[118, 0, 2, b'o', b'k'], &[119, 2, b'o', b'k']),
+        ],
+        differences(&bytes)
+    );
+
+    // 5 as a SMALL_BIG_EXT, then as an INTEGER_EXT
+    let bytes = [131, 108, 0, 0, 0, 2, 110, 1, 0, 5, 98, 0, 0, 0, 5, 106];
+    assert_eq!(
+        vec![
+            // [5, 5] is a list of bytes.
+            difference(1, &bytes[1..], &[107, 0, 2, 5, 5]),
+        ],
+        differences(&bytes)
+    );
+    let bytes = [131, 104, 2, 110, 1, 0, 5, 98, 0, 0, 0, 5];
+    assert_eq!(
+        vec![
+            difference(3, &[110, 1, 0, 5], &[97, 5]),
+            difference(7, &[98, 0, 0, 0, 5], &[97, 5]),
+        ],
+        differences(&bytes)
+    );
+}
+
+#[test]
+fn display() {
+    let d = difference(3, &[115, 2, b'o', b'k'], &[119, 2, b'o', b'k']);
+    assert_eq!(
+        "at offset 3: expected SMALL_ATOM_UTF8_EXT <77 02 6f 6b>, found SMALL_ATOM_EXT <73 02 6f 6b>",
+        d.to_string()
+    );
+}
@@ -0,0 +1,144 @@
+use ppbert::parser::*;
+use ppbert::prelude::*;
+
+const HEADER: [u8; 8] = [1, 2, 3, 4, 6, 7, 8, 9];
+
+fn record(term: &[u8]) -> Vec<u8> {
+    let mut bytes = (term.len() as u32).to_be_bytes().to_vec();
+    bytes.extend_from_slice(&[0x62, 0x57, 0x4c, 0x41]);
+    if term.len() >= DISK_LOG_MIN_MD5_TERM {
+        bytes.extend_from_slice(&[0; 16]);
+    }
+    bytes.extend_from_slice(term);
+    return bytes;
+}
+
+fn p(bytes: &[u8]) -> (Result<Vec<BertTerm>>, Vec<BertWarning>) {
+    let mut parser = BertParser::new(bytes.to_vec());
+    let mut terms = Vec::new();
+    while let Some(res) = parser.disk_log_next() {
+        match res {
+            Ok(term) => terms.push(term),
+            Err(e) => return (Err(e), parser.take_warnings()),
+        }
+    }
+    return (Ok(terms), parser.take_warnings());
+}
+
+#[test]
+fn records() {
+    let mut bytes = HEADER.to_vec();
+    bytes.extend(record(&[131, 97, 1]));
+    bytes.extend(record(&[131, 97, 2]));
+    let (terms, warnings) = p(&bytes);
+    assert_eq!(vec![BertTerm::Int(1), BertTerm::Int(2)], terms.unwrap());
+    assert!(warnings.is_empty());
+
+    assert!(matches!(
+        p(&[1, 2, 3, 5]).0,
+        Err(BertError::InvalidDiskLogMagic { offset: 0, .. })
+    ));
+}
+
+#[test]
+fn md5_record() {
+    let len = DISK_LOG_MIN_MD5_TERM;
+    let mut term = vec![131, 109];
+    term.extend_from_slice(&(len as u32 - 6).to_be_bytes());
+    term.resize(len, 0);
+    let mut bytes = HEADER.to_vec();
+    bytes.extend(record(&term));
+    let (terms, warnings) = p(&bytes);
+    assert_eq!(vec![BertTerm::Binary(vec![0; len - 6])], terms.unwrap());
+    assert!(warnings.is_empty());
+}
+
+#[test]
+fn wrong_length() {
+    let mut bytes = HEADER.to_vec();
+    bytes.extend(record(&[131, 97, 1]));
+    let mut bad = record(&[131, 97, 2, 0]);
+    bad[3] = 4;
+    bytes.extend(bad);
+    bytes.extend(record(&[131, 97, 3]));
+    let (terms, warnings) = p(&bytes);
+    assert_eq!(vec![BertTerm::Int(1), BertTerm::Int(3)], terms.unwrap());
+    assert_eq!(1, warnings.len());
+    assert!(matches!(
+        warnings[0],
+        BertWarning::SkippedBytes {
+            start: 19,
+            end: 31,
+            cause: BertError::InvalidFrameLength {
+                offset: 27,
+                expected: 4,
+                actual: 3,
+            },
+        }
+    ));
+}
+
+#[test]
+fn garbage() {
+    let mut bytes = HEADER.to_vec();
+    bytes.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef, 0, 0]);
+    bytes.extend(record(&[131, 97, 1]));
+    let (terms, warnings) = p(&bytes);
+    assert_eq!(vec![BertTerm::Int(1)], terms.unwrap());
+    assert!(matches!(
+        warnings[..],
+        [BertWarning::SkippedBytes {
+            start: 8,
+            end: 14,
+            cause: BertError::InvalidDiskLogTermMagic { offset: 12, .. },
+        }]
+    ));
+}
+
+#[test]
+fn truncated() {
+    // A node that crashed while writing leaves a partial record.
+    let mut bytes = HEADER.to_vec();
+    bytes.extend(record(&[131, 97, 1]));
+    bytes.extend(&record(&[131, 97, 2])[..9]);
+    let (terms, warnings) = p(&bytes);
+    assert_eq!(vec![BertTerm::Int(1)], terms.unwrap());
+    assert!(matches!(
+        warnings[..],
+        [BertWarning::SkippedBytes {
+            start: 19,
+            end: 28,
+            cause: BertError::NotEnoughData { .. },
+        }]
+    ));
+}
+
+#[test]
+fn halt_log() {
+    let mut bytes = record(&[131, 97, 1]);
+    bytes.extend(record(&[131, 97, 2]));
+    let expected = vec![BertTerm::Int(1), BertTerm::Int(2)];
+
+    let mut parser = BertParser::new(bytes.clone());
+    let mut terms = Vec::new();
+    while let Some(res) = parser.halt_log_next() {
+        terms.push(res.unwrap());
+    }
+    assert_eq!(expected, terms);
+
+    // Without a header, a disk_log is a halt log.
+    let (terms, warnings) = p(&bytes);
+    assert_eq!(expected, terms.unwrap());
+    assert!(warnings.is_empty());
+}
+
+#[test]
+fn external_log() {
+    let mut parser = BertParser::new(vec![131, 97, 1, 131, 106]);
+    assert_eq!(
+        BertTerm::Int(1),
+        parser.external_log_next().unwrap().unwrap()
+    );
+    assert_eq!(BertTerm::Nil, parser.external_log_next().unwrap().unwrap());
+    assert!(parser.external_log_next().is_none());
+}
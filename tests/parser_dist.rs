@@ -0,0 +1,122 @@
+use ppbert::parser::*;
+use ppbert::prelude::*;
+
+fn p(messages: &[&[u8]]) -> Result<Vec<BertTerm>> {
+    let mut bytes = Vec::new();
+    for m in messages {
+        bytes.extend_from_slice(&(m.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(m);
+    }
+    let mut parser = BertParser::new(bytes);
+    let mut terms = Vec::new();
+    while let Some(res) = parser.dist_next() {
+        terms.push(res?);
+    }
+    return Ok(terms);
+}
+
+fn atom(s: &str) -> BertTerm {
+    BertTerm::Atom(s.to_string())
+}
+
+#[test]
+fn atom_cache() {
+    let first: &[u8] = &[
+        131, 68, 2, 0x88, 0, // two new entries
+        5, 3, b'f', b'o', b'o', // entry 5
+        6, 3, b'b', b'a', b'r', // entry 6
+        104, 2, 97, 6, 82, 0, // control
+        82, 1, // payload
+    ];
+    // The second message refers to an entry of the first one.
+    let second: &[u8] = &[131, 68, 1, 0x00, 5, 104, 1, 82, 0];
+    let terms = p(&[first, &[], second]).unwrap();
+    assert_eq!(
+        vec![
+            BertTerm::Tuple(vec![
+                BertTerm::Tuple(vec![BertTerm::Int(6), atom("foo")]),
+                atom("bar"),
+            ]),
+            BertTerm::Tuple(vec![BertTerm::Tuple(vec![atom("foo")])]),
+        ],
+        terms
+    );
+}
+
+#[test]
+fn long_atoms() {
+    let message: &[u8] = &[131, 68, 1, 0x18, 7, 0, 2, b'o', b'k', 82, 0];
+    assert_eq!(
+        vec![BertTerm::Tuple(vec![atom("ok")])],
+        p(&[message]).unwrap()
+    );
+}
+
+#[test]
+fn pass_through() {
+    let message: &[u8] = &[112, 131, 97, 1, 131, 97, 2];
+    assert_eq!(
+        vec![BertTerm::Tuple(vec![BertTerm::Int(1), BertTerm::Int(2)])],
+        p(&[message]).unwrap()
+    );
+}
+
+#[test]
+fn fragments() {
+    let mut first = vec![131, 69];
+    first.extend_from_slice(&9u64.to_be_bytes());
+    first.extend_from_slice(&2u64.to_be_bytes());
+    first.extend_from_slice(&[1, 0x08, 0, 3, b'y', b'e', b's', 104, 2]);
+    let mut last = vec![131, 70];
+    last.extend_from_slice(&9u64.to_be_bytes());
+    last.extend_from_slice(&1u64.to_be_bytes());
+    last.extend_from_slice(&[82, 0, 97, 3, 106]);
+    assert_eq!(
+        vec![BertTerm::Tuple(vec![
+            BertTerm::Tuple(vec![atom("yes"), BertTerm::Int(3)]),
+            BertTerm::Nil,
+        ])],
+        p(&[&first, &last]).unwrap()
+    );
+
+    assert!(matches!(
+        p(&[&last]),
+        Err(BertError::MissingDistFragments { seq: 9, .. })
+    ));
+
+    // The reassembled message ends in the middle of {3, _}: it is
+    // invalid, not cut short.
+    last.truncate(18);
+    last.extend_from_slice(&[82, 0, 104, 2, 97, 3]);
+    assert!(matches!(
+        p(&[&first, &last]).unwrap_err().root(),
+        BertError::InvalidFrameLength {
+            offset: 36,
+            expected: 8,
+            actual: 9,
+        }
+    ));
+}
+
+#[test]
+fn errors() {
+    assert!(matches!(
+        p(&[&[131, 68, 0, 82, 0]]),
+        Err(BertError::InvalidAtomCacheRef {
+            offset: 8,
+            index: 0,
+        })
+    ));
+    assert!(matches!(
+        p(&[&[131, 68, 0, 97, 1, 97, 2, 97, 3]]),
+        Err(BertError::InvalidFrameLength {
+            offset: 4,
+            expected: 9,
+            actual: 7,
+        })
+    ));
+    assert!(matches!(
+        p(&[&[131, 71, 97, 1]]),
+        Err(BertError::InvalidTag(5, 71))
+    ));
+}
@@ -13,10 +13,10 @@ fn p(bytes: &[u8]) -> Result<BertTerm> {
 fn magic_number() {
     assert!(p(&[131, 97, 0]).is_ok());
     assert!(p(&[130, 97, 0]).is_err());
-    assert!(match p(&[130, 97, 0]) {
-        Err(BertError::InvalidMagicNumber { .. }) => true,
-        _ => false,
-    });
+    assert!(matches!(
+        p(&[130, 97, 0]),
+        Err(BertError::InvalidMagicNumber { .. })
+    ));
 }
 
 #[test]
@@ -71,22 +71,11 @@ fn old_float() {
 
 #[test]
 fn new_float() {
-    let pi: f64 = 3.141592;
-    let float_as_u64: u64 = pi.to_bits();
-    let bytes = &[
-        131,
-        70,
-        (float_as_u64 >> 56) as u8 & 0xff_u8,
-        (float_as_u64 >> 48) as u8 & 0xff_u8,
-        (float_as_u64 >> 40) as u8 & 0xff_u8,
-        (float_as_u64 >> 32) as u8 & 0xff_u8,
-        (float_as_u64 >> 24) as u8 & 0xff_u8,
-        (float_as_u64 >> 16) as u8 & 0xff_u8,
-        (float_as_u64 >> 8) as u8 & 0xff_u8,
-        (float_as_u64 >> 0) as u8 & 0xff_u8,
-    ];
-    assert!(match p(bytes) {
-        Ok(BertTerm::Float(x)) => x == 3.141592,
+    let pi = std::f64::consts::PI;
+    let mut bytes = vec![131, 70];
+    bytes.extend_from_slice(&pi.to_be_bytes());
+    assert!(match p(&bytes) {
+        Ok(BertTerm::Float(x)) => x == pi,
         _ => false,
     });
 }
@@ -105,10 +94,10 @@ fn atom() {
         _ => false,
     });
 
-    assert!(match p(b"\x83\x73\x04abc") {
-        Err(BertError::NotEnoughData { .. }) => true,
-        _ => false,
-    });
+    assert!(matches!(
+        p(b"\x83\x73\x04abc"),
+        Err(BertError::NotEnoughData { .. })
+    ));
 
     // latin1 (0xe9 = é)
     assert!(match p(b"\x83\x64\x00\x04caf\xe9") {
@@ -146,10 +135,7 @@ fn atom_utf8() {
     // Not enough data
     let mut bert: Vec<u8> = vec![131, 119, (atom_bytes.len() + 1) as u8];
     bert.extend(&atom_bytes);
-    assert!(match p(&bert) {
-        Err(BertError::NotEnoughData { .. }) => true,
-        _ => false,
-    });
+    assert!(matches!(p(&bert), Err(BertError::NotEnoughData { .. })));
 }
 
 #[test]
@@ -160,10 +146,10 @@ fn string() {
     });
 
     // not enough characters
-    assert!(match p(b"\x83\x6b\x00\x04foo") {
-        Err(BertError::NotEnoughData { .. }) => true,
-        _ => false,
-    });
+    assert!(matches!(
+        p(b"\x83\x6b\x00\x04foo"),
+        Err(BertError::NotEnoughData { .. })
+    ));
 }
 
 #[test]
@@ -174,18 +160,15 @@ fn binary() {
     });
 
     // not enough characters
-    assert!(match p(b"\x83\x6d\x00\x00\x00\x04foo") {
-        Err(BertError::NotEnoughData { .. }) => true,
-        _ => false,
-    });
+    assert!(matches!(
+        p(b"\x83\x6d\x00\x00\x00\x04foo"),
+        Err(BertError::NotEnoughData { .. })
+    ));
 }
 
 #[test]
 fn nil() {
-    assert!(match p(b"\x83\x6a") {
-        Ok(BertTerm::Nil) => true,
-        _ => false,
-    });
+    assert!(matches!(p(b"\x83\x6a"), Ok(BertTerm::Nil)));
 }
 
 #[test]
@@ -203,14 +186,8 @@ fn list() {
     match p(b) {
         Ok(List(ref terms)) => {
             assert_eq!(4, terms.len());
-            assert!(match terms[0] {
-                Int(16) => true,
-                _ => false,
-            });
-            assert!(match terms[1] {
-                Int(0xffff) => true,
-                _ => false,
-            });
+            assert!(matches!(terms[0], Int(16)));
+            assert!(matches!(terms[1], Int(0xffff)));
             assert!(match terms[2] {
                 Atom(ref s) => s == "abc",
                 _ => false,
@@ -220,7 +197,7 @@ fn list() {
                 _ => false,
             });
         }
-        _ => assert!(false),
+        _ => panic!(),
     };
 
     // improper list
@@ -233,16 +210,10 @@ fn list() {
         107, 0, 3, b'g', b'h', b'i', // tail
     ];
     match p(b) {
-        Ok(List(ref terms)) => {
-            assert_eq!(5, terms.len());
-            assert!(match terms[0] {
-                Int(16) => true,
-                _ => false,
-            });
-            assert!(match terms[1] {
-                Int(0xffff) => true,
-                _ => false,
-            });
+        Ok(ImproperList(ref terms, ref tail)) => {
+            assert_eq!(4, terms.len());
+            assert!(matches!(terms[0], Int(16)));
+            assert!(matches!(terms[1], Int(0xffff)));
             assert!(match terms[2] {
                 Atom(ref s) => s == "abc",
                 _ => false,
@@ -251,12 +222,12 @@ fn list() {
                 String(ref s) => s == b"def",
                 _ => false,
             });
-            assert!(match terms[4] {
+            assert!(match **tail {
                 String(ref s) => s == b"ghi",
                 _ => false,
             });
         }
-        _ => assert!(false),
+        _ => panic!(),
     };
 }
 
@@ -274,16 +245,13 @@ fn tuple() {
     match p(b) {
         Ok(Tuple(ref terms)) => {
             assert_eq!(2, terms.len());
-            assert!(match terms[0] {
-                Int(16) => true,
-                _ => false,
-            });
+            assert!(matches!(terms[0], Int(16)));
             assert!(match terms[1] {
                 Atom(ref s) => s == "abc",
                 _ => false,
             });
         }
-        _ => assert!(false),
+        _ => panic!(),
     };
 
     // large
@@ -296,10 +264,7 @@ fn tuple() {
     match p(b) {
         Ok(Tuple(ref terms)) => {
             assert_eq!(2, terms.len());
-            assert!(match terms[0] {
-                Int(16) => true,
-                _ => false,
-            });
+            assert!(matches!(terms[0], Int(16)));
             assert!(match terms[1] {
                 Atom(ref s) => s == "abc",
                 _ => false,
@@ -307,7 +272,7 @@ fn tuple() {
         }
         e => {
             println!("{:?}", e);
-            assert!(false)
+            panic!()
         }
     };
 }
@@ -351,3 +316,296 @@ fn map() {
         _ => false,
     });
 }
+
+#[test]
+fn pid() {
+    // NEW_PID_EXT
+    assert!(
+        match p(b"\x83\x58\x77\x03a@b\x00\x00\x00\x7b\x00\x00\x00\x00\x00\x00\x00\x02") {
+            Ok(BertTerm::Pid {
+                ref node,
+                id,
+                serial,
+                creation,
+            }) => node == "a@b" && id == 123 && serial == 0 && creation == 2,
+            _ => false,
+        }
+    );
+
+    // PID_EXT
+    assert!(
+        match p(b"\x83\x67\x77\x03a@b\x00\x00\x00\x7b\x00\x00\x00\x01\x03") {
+            Ok(BertTerm::Pid {
+                ref node,
+                id,
+                serial,
+                creation,
+            }) => node == "a@b" && id == 123 && serial == 1 && creation == 3,
+            _ => false,
+        }
+    );
+
+    // the node must be an atom
+    assert!(matches!(
+        p(b"\x83\x58\x61\x00\x00\x00\x00\x7b\x00\x00\x00\x00\x00\x00\x00\x02"),
+        Err(BertError::InvalidNode(2))
+    ));
+}
+
+#[test]
+fn port() {
+    // NEW_PORT_EXT
+    assert!(
+        match p(b"\x83\x59\x77\x03a@b\x00\x00\x00\x05\x00\x00\x00\x02") {
+            Ok(BertTerm::Port {
+                ref node,
+                id,
+                creation,
+            }) => node == "a@b" && id == 5 && creation == 2,
+            _ => false,
+        }
+    );
+
+    // PORT_EXT
+    assert!(match p(b"\x83\x66\x77\x03a@b\x00\x00\x00\x05\x01") {
+        Ok(BertTerm::Port { id, creation, .. }) => id == 5 && creation == 1,
+        _ => false,
+    });
+
+    // V4_PORT_EXT
+    assert!(
+        match p(b"\x83\x78\x77\x03a@b\x00\x00\x00\x01\x00\x00\x00\x05\x00\x00\x00\x02") {
+            Ok(BertTerm::Port { id, creation, .. }) => id == (1 << 32) + 5 && creation == 2,
+            _ => false,
+        }
+    );
+}
+
+#[test]
+fn reference() {
+    // NEWER_REFERENCE_EXT
+    assert!(match p(b"\x83\x5a\x00\x03\x77\x03a@b\x00\x00\x00\x02\x00\x00\x00\x03\x00\x00\x00\x02\x00\x00\x00\x01") {
+        Ok(BertTerm::Reference {
+            ref node,
+            creation,
+            ref ids,
+        }) => node == "a@b" && creation == 2 && ids == &[3, 2, 1],
+        _ => false,
+    });
+
+    // NEW_REFERENCE_EXT
+    assert!(
+        match p(b"\x83\x72\x00\x01\x77\x03a@b\x01\x00\x00\x00\x07") {
+            Ok(BertTerm::Reference {
+                creation, ref ids, ..
+            }) => creation == 1 && ids == &[7],
+            _ => false,
+        }
+    );
+
+    // REFERENCE_EXT
+    assert!(match p(b"\x83\x65\x77\x03a@b\x00\x00\x00\x07\x01") {
+        Ok(BertTerm::Reference {
+            creation, ref ids, ..
+        }) => creation == 1 && ids == &[7],
+        _ => false,
+    });
+}
+
+#[test]
+fn export() {
+    assert!(match p(b"\x83\x71\x77\x05lists\x77\x03map\x61\x02") {
+        Ok(BertTerm::Export {
+            ref module,
+            ref function,
+            arity,
+        }) => module == "lists" && function == "map" && arity == 2,
+        _ => false,
+    });
+
+    // the arity must be an integer
+    assert!(matches!(
+        p(b"\x83\x71\x77\x05lists\x77\x03map\x6a"),
+        Err(BertError::InvalidFun(14))
+    ));
+}
+
+#[test]
+fn new_fun() {
+    let mut b: Vec<u8> = vec![131, 112, 0, 0, 0, 0, 1];
+    b.extend(&[0xab; 16]); // uniq
+    b.extend(&[0, 0, 0, 3]); // index
+    b.extend(&[0, 0, 0, 1]); // one free variable
+    b.extend(b"\x77\x03mod"); // module
+    b.extend(&[97, 3]); // old index
+    b.extend(&[98, 0, 0, 0x30, 0x39]); // old uniq
+    b.extend(b"\x58\x77\x03a@b\x00\x00\x00\x7b\x00\x00\x00\x00\x00\x00\x00\x02"); // pid
+    b.extend(&[97, 42]); // free variable
+    let size = (b.len() - 2) as u8;
+    b[5] = size;
+
+    assert!(match p(&b) {
+        Ok(BertTerm::Fun(ref fun)) => {
+            fun.module == "mod"
+                && fun.arity == Some(1)
+                && fun.uniq == [0xab; 16]
+                && fun.index == 3
+                && fun.old_index == 3
+                && fun.old_uniq == 12345
+                && fun.free_vars == vec![BertTerm::Int(42)]
+        }
+        _ => false,
+    });
+}
+
+#[test]
+fn bit_binary() {
+    assert!(match p(b"\x83\x4d\x00\x00\x00\x03\x05\x01\x02\x18") {
+        Ok(BertTerm::BitBinary(ref bytes, bits)) => bytes == &[1, 2, 0x18] && bits == 5,
+        _ => false,
+    });
+
+    // not enough bytes
+    assert!(matches!(
+        p(b"\x83\x4d\x00\x00\x00\x03\x05\x01\x02"),
+        Err(BertError::NotEnoughData { .. })
+    ));
+
+    // empty
+    assert!(match p(b"\x83\x4d\x00\x00\x00\x00\x00") {
+        Ok(BertTerm::BitBinary(ref bytes, 0)) => bytes.is_empty(),
+        _ => false,
+    });
+
+    // invalid number of bits
+    for bytes in [
+        &b"\x83\x4d\x00\x00\x00\x01\x00\x01"[..],
+        &b"\x83\x4d\x00\x00\x00\x01\x09\x01"[..],
+        &b"\x83\x4d\x00\x00\x00\x00\x01"[..],
+    ] {
+        assert!(matches!(
+            p(bytes),
+            Err(BertError::InvalidBitBinary { offset: 6, .. })
+        ));
+    }
+}
+
+#[test]
+fn compressed() {
+    // term_to_binary(lists:duplicate(10, 7), [compressed])
+    let b = &[
+        131, 80, 0, 0, 0, 26, // uncompressed size
+        120, 156, 203, 97, 96, 96, 224, 74, 100, 199, 132, 89, 0, 60, 210, 4, 241,
+    ];
+    assert!(match p(b) {
+        Ok(BertTerm::List(ref terms)) =>
+            terms.len() == 10 && terms.iter().all(|t| *t == BertTerm::Int(7)),
+        _ => false,
+    });
+
+    // wrong uncompressed size
+    let b = &[
+        131, 80, 0, 0, 0, 25, // uncompressed size
+        120, 156, 203, 97, 96, 96, 224, 74, 100, 199, 132, 89, 0, 60, 210, 4, 241,
+    ];
+    assert!(matches!(
+        p(b),
+        Err(BertError::InvalidCompressedSize {
+            offset: 2,
+            expected: 25,
+            actual: 26,
+        })
+    ));
+
+    // truncated zlib stream
+    let b = &[131, 80, 0, 0, 0, 26, 120, 156, 203, 97, 96, 96];
+    assert!(matches!(p(b), Err(BertError::NotEnoughData { .. })));
+
+    // <<104, 2, 97, 1>>: the inflated tuple lacks an element
+    let b = &[
+        131, 80, 0, 0, 0, 4, // uncompressed size
+        120, 156, 203, 96, 74, 100, 4, 0, 2, 109, 0, 205,
+    ];
+    let err = p(b).unwrap_err();
+    assert_eq!(&[PathStep::Compressed, PathStep::Tuple(1)], err.path());
+    assert!(matches!(
+        *err.root(),
+        BertError::TruncatedCompressedTerm {
+            offset: 4,
+            needed: 1,
+            available: 0,
+        }
+    ));
+}
+
+#[test]
+fn borrowed() {
+    use std::borrow::Cow;
+
+    let bytes = vec![
+        131, 104, 3, 100, 0, 2, b'o', b'k', 109, 0, 0, 0, 2, b'h', b'i', 107, 0, 1, 42,
+    ];
+    let parser = BertParser::new(bytes.clone());
+    let term = parser.bert1_next_ref().unwrap().unwrap();
+    match term {
+        BertTermRef::Tuple(ref terms) => {
+            assert!(matches!(terms[0], BertTermRef::Atom(Cow::Borrowed("ok"))));
+            assert!(matches!(
+                terms[1],
+                BertTermRef::Binary(Cow::Borrowed(b"hi"))
+            ));
+            assert!(matches!(
+                terms[2],
+                BertTermRef::String(Cow::Borrowed(&[42]))
+            ));
+        }
+        _ => panic!("expected a tuple"),
+    }
+    assert_eq!(p(&bytes).unwrap(), term.to_owned());
+}
+
+#[test]
+fn local() {
+    assert!(match p(b"\x83\x79\xde\xad\xbe\xef\x64\x00\x02ok") {
+        Ok(BertTerm::Local(0xdeadbeef, ref term)) => **term == BertTerm::Atom("ok".to_string()),
+        _ => false,
+    });
+
+    // the hash is followed by a term
+    assert!(match p(b"\x83\x79\xde\xad\xbe\xef") {
+        Err(BertError::AtPath {
+            ref path,
+            ref error,
+        }) => {
+            path[..] == [PathStep::Local]
+                && matches!(**error, BertError::NotEnoughData { offset: 6, .. })
+        }
+        _ => false,
+    });
+}
+
+#[test]
+fn error_path() {
+    // {1, #{[1, <invalid>] => 2}}
+    let bytes = [
+        131, 104, 2, 97, 1, 116, 0, 0, 0, 1, 108, 0, 0, 0, 2, 97, 1, 255,
+    ];
+    let err = p(&bytes).unwrap_err();
+    assert_eq!(
+        &[PathStep::Tuple(1), PathStep::MapKey(0), PathStep::List(1)],
+        err.path()
+    );
+    assert!(matches!(err.root(), BertError::InvalidTag(17, 255)));
+    assert_eq!(
+        "invalid tag at offset 17: 0xff, in tuple[1] \u{2192} map key #0 \u{2192} list[1]",
+        err.to_string()
+    );
+
+    // The path goes on in a compressed term: [[], <invalid>]
+    let compressed = [
+        131, 80, 0, 0, 0, 8, 120, 156, 203, 97, 96, 96, 96, 202, 250, 159, 5, 0, 7, 22, 2, 66,
+    ];
+    let err = p(&compressed).unwrap_err();
+    assert_eq!(&[PathStep::Compressed, PathStep::List(1)], err.path());
+    assert!(matches!(err.root(), BertError::InvalidTag(6, 255)));
+}
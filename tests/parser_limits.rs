@@ -0,0 +1,224 @@
+use std::io::{Cursor, Write};
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use ppbert::decoder::*;
+use ppbert::framing::Bert1;
+use ppbert::parser::*;
+use ppbert::pp::*;
+use ppbert::prelude::*;
+use ppbert::stream::StreamParser;
+
+mod common;
+use common::nested_lists;
+
+fn p(bytes: &[u8], limits: ParserLimits) -> Result<BertTerm> {
+    let mut parser = BertParser::with_limits(bytes.to_vec(), limits);
+    return parser.bert1_next().unwrap();
+}
+
+#[test]
+fn depth() {
+    let limits = ParserLimits {
+        max_depth: 3,
+        ..ParserLimits::none()
+    };
+    assert!(p(&nested_lists(3), limits).is_ok());
+    let err = p(&nested_lists(4), limits).unwrap_err();
+    assert_eq!(
+        &[PathStep::List(0), PathStep::List(0), PathStep::List(0)],
+        err.path()
+    );
+    assert!(matches!(
+        err.root(),
+        BertError::DepthLimitExceeded {
+            offset: 16,
+            limit: 3
+        }
+    ));
+}
+
+#[test]
+fn default_depth() {
+    // A term at the default limit can be parsed and printed.
+    let term = p(&nested_lists(DEFAULT_MAX_DEPTH), ParserLimits::default()).unwrap();
+    let pp = ErlangPrettyPrinter::new(2, 6, "");
+    assert!(pp.write(&term, Box::new(Vec::new())).is_ok());
+    term.drop_deep();
+
+    // A deeper one is an error, not a stack overflow.
+    let bytes = nested_lists(1_000_000);
+    let err = p(&bytes, ParserLimits::default()).unwrap_err();
+    assert!(matches!(
+        err.root(),
+        BertError::DepthLimitExceeded {
+            limit: DEFAULT_MAX_DEPTH,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn inflated_depth() {
+    // [compressed [[]]]: the compressed term and the levels of the
+    // inflated term count.
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&[108, 0, 0, 0, 1, 106, 106]).unwrap();
+    let mut bytes = vec![131, 108, 0, 0, 0, 1, 80, 0, 0, 0, 7];
+    bytes.extend_from_slice(&encoder.finish().unwrap());
+    bytes.push(106);
+
+    let limits = |max_depth| ParserLimits {
+        max_depth,
+        ..ParserLimits::none()
+    };
+    assert!(p(&bytes, limits(4)).is_ok());
+    let err = p(&bytes, limits(3)).unwrap_err();
+    assert_eq!(
+        &[PathStep::List(0), PathStep::Compressed, PathStep::List(0)],
+        err.path()
+    );
+    assert!(matches!(
+        err.root(),
+        BertError::DepthLimitExceeded { limit: 3, .. }
+    ));
+}
+
+#[test]
+fn elements() {
+    let limits = ParserLimits {
+        max_elements: 4,
+        ..ParserLimits::none()
+    };
+    // {1, [2, 3]}: the tuple and the list have 4 elements.
+    let term = [131, 104, 2, 97, 1, 108, 0, 0, 0, 2, 97, 2, 97, 3, 106];
+    assert!(p(&term, limits).is_ok());
+
+    // #{1 => 2, 3 => 4, 5 => 6}
+    let map = [
+        131, 116, 0, 0, 0, 3, 97, 1, 97, 2, 97, 3, 97, 4, 97, 5, 97, 6,
+    ];
+    assert!(matches!(
+        p(&map, limits),
+        Err(BertError::ElementLimitExceeded {
+            offset: 6,
+            limit: 4
+        })
+    ));
+
+    // The count starts over with every term.
+    let mut parser = BertParser::with_limits([term, term].concat(), limits);
+    assert!(parser.bert1_next().unwrap().is_ok());
+    assert!(parser.bert1_next().unwrap().is_ok());
+}
+
+#[test]
+fn huge_length() {
+    // A list claiming 2^32-1 elements is not preallocated.
+    let bytes = [131, 108, 255, 255, 255, 255, 97, 1];
+    for limits in [ParserLimits::none(), ParserLimits::default()] {
+        let err = p(&bytes, limits).unwrap_err();
+        assert_eq!(&[PathStep::List(1)], err.path());
+        assert!(matches!(err.root(), BertError::NotEnoughData { .. }));
+    }
+}
+
+#[test]
+fn binary_size() {
+    let limits = ParserLimits {
+        max_binary_size: 2,
+        ..ParserLimits::none()
+    };
+    assert!(p(&[131, 109, 0, 0, 0, 2, 1, 2], limits).is_ok());
+    assert!(matches!(
+        p(&[131, 109, 0, 0, 0, 3, 1, 2, 3], limits),
+        Err(BertError::BinaryLimitExceeded {
+            offset: 6,
+            size: 3,
+            limit: 2
+        })
+    ));
+    assert!(matches!(
+        p(&[131, 107, 0, 3, 1, 2, 3], limits),
+        Err(BertError::BinaryLimitExceeded { size: 3, .. })
+    ));
+    // The declared size of a compressed term is checked before
+    // inflating it.
+    assert!(matches!(
+        p(&[131, 80, 255, 255, 255, 255], limits),
+        Err(BertError::BinaryLimitExceeded {
+            offset: 6,
+            size: 0xffff_ffff,
+            limit: 2
+        })
+    ));
+}
+
+#[test]
+fn default_limits() {
+    // A zip bomb: a binary of 65 MiB of zeros in about 65 KiB.
+    let size = 65 << 20;
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&[109]).unwrap();
+    encoder.write_all(&(size as u32 - 5).to_be_bytes()).unwrap();
+    encoder.write_all(&vec![0; size - 5]).unwrap();
+    let data = encoder.finish().unwrap();
+    let mut bytes = vec![131, 80];
+    bytes.extend_from_slice(&(size as u32).to_be_bytes());
+    bytes.extend_from_slice(&data);
+
+    assert!(matches!(
+        p(&bytes, ParserLimits::default()),
+        Err(BertError::BinaryLimitExceeded { offset: 6, .. })
+    ));
+    assert!(matches!(
+        BertParser::new(bytes.clone()).bert1_next(),
+        Some(Err(BertError::BinaryLimitExceeded { .. }))
+    ));
+    assert!(matches!(
+        p(&bytes, ParserLimits::none()),
+        Ok(BertTerm::Binary(ref b)) if b.len() == size - 5
+    ));
+}
+
+#[test]
+fn atom_length() {
+    let limits = ParserLimits {
+        max_atom_length: 2,
+        ..ParserLimits::none()
+    };
+    assert!(p(&[131, 119, 2, b'o', b'k'], limits).is_ok());
+    assert!(matches!(
+        p(&[131, 119, 5, b'e', b'r', b'r', b'o', b'r'], limits),
+        Err(BertError::AtomLimitExceeded {
+            offset: 3,
+            length: 5,
+            limit: 2
+        })
+    ));
+    assert!(matches!(
+        p(&[131, 100, 0, 3, b'f', b'o', b'o'], limits),
+        Err(BertError::AtomLimitExceeded { length: 3, .. })
+    ));
+}
+
+#[test]
+fn stream_and_decoder() {
+    let limits = ParserLimits {
+        max_binary_size: 2,
+        ..ParserLimits::none()
+    };
+    // The stream parser fails without waiting for the bytes.
+    let bytes = vec![131, 109, 0, 0, 0, 3];
+    let mut parser = StreamParser::with_limits(Cursor::new(bytes), Bert1, limits);
+    let err = parser.next().unwrap().unwrap_err();
+    assert_eq!(&[PathStep::Term(0)], err.path());
+    assert!(matches!(err.root(), BertError::BinaryLimitExceeded { .. }));
+
+    let mut decoder = Decoder::with_limits(Bert1, limits);
+    assert!(matches!(
+        decoder.feed(&[131, 109, 0, 0, 0, 3]),
+        Err(BertError::BinaryLimitExceeded { .. })
+    ));
+}
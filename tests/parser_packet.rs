@@ -0,0 +1,57 @@
+use ppbert::framing::{Framing, Packet};
+use ppbert::parser::*;
+use ppbert::prelude::*;
+
+fn p(bytes: &[u8], n: u8) -> Result<Vec<BertTerm>> {
+    let parser = BertParser::new(bytes.to_vec());
+    let framing = Packet::new(n).unwrap();
+    let mut terms = Vec::new();
+    while let Some(res) = framing.next_term(&parser) {
+        terms.push(res?.into_owned());
+    }
+    return Ok(terms);
+}
+
+#[test]
+fn zero_terms() {
+    assert!(p(&[], 1).unwrap().is_empty());
+}
+
+#[test]
+fn two_terms() {
+    let expected = vec![BertTerm::Int(1), BertTerm::Nil];
+    assert_eq!(expected, p(&[3, 131, 97, 1, 2, 131, 106], 1).unwrap());
+    assert_eq!(expected, p(&[0, 3, 131, 97, 1, 0, 2, 131, 106], 2).unwrap());
+    assert_eq!(
+        expected,
+        p(&[0, 0, 0, 3, 131, 97, 1, 0, 0, 0, 2, 131, 106], 4).unwrap()
+    );
+}
+
+#[test]
+fn length_mismatch() {
+    assert!(matches!(
+        p(&[4, 131, 97, 1, 2, 131, 106], 1),
+        Err(BertError::InvalidFrameLength {
+            offset: 1,
+            expected: 4,
+            actual: 3,
+        })
+    ));
+    assert!(matches!(
+        p(&[2, 131, 97, 1], 1),
+        Err(BertError::InvalidFrameLength {
+            offset: 1,
+            expected: 2,
+            actual: 3,
+        })
+    ));
+    assert!(matches!(
+        p(&[0, 5, 131, 97, 1], 2),
+        Err(BertError::NotEnoughData {
+            offset: 2,
+            needed: 5,
+            available: 3,
+        })
+    ));
+}
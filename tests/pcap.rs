@@ -0,0 +1,104 @@
+use std::fs;
+use std::time::Duration;
+
+use ppbert::pcap::*;
+use ppbert::prelude::*;
+
+fn atom(s: &str) -> BertTerm {
+    BertTerm::Atom(s.to_string())
+}
+
+// See tests/fixtures/dist_capture.py.
+fn check(file: &str, a: &str, b: &str) {
+    let capture = fs::read(format!("tests/fixtures/{}", file)).unwrap();
+    let messages = dist_messages(&capture, None).unwrap();
+    assert_eq!(2, messages.len());
+
+    let m = &messages[0];
+    assert_eq!(Duration::new(1_700_000_001, 500_001_000), m.time);
+    assert_eq!(a, m.src.to_string());
+    assert_eq!(b, m.dst.to_string());
+    assert_eq!(Some("a@host"), m.src_node.as_deref());
+    assert_eq!(Some("b@host"), m.dst_node.as_deref());
+    assert_eq!(
+        BertTerm::Tuple(vec![
+            BertTerm::Tuple(vec![
+                BertTerm::Int(6),
+                atom("hello"),
+                BertTerm::Nil,
+                atom("world"),
+            ]),
+            BertTerm::Tuple(vec![atom("hello"), BertTerm::Int(42)]),
+        ]),
+        *m.message.as_ref().unwrap()
+    );
+
+    let m = &messages[1];
+    assert_eq!(b, m.src.to_string());
+    assert_eq!(Some("b@host"), m.src_node.as_deref());
+    assert_eq!(
+        BertTerm::Tuple(vec![BertTerm::Tuple(vec![
+            BertTerm::Int(2),
+            BertTerm::Nil,
+            atom("pong"),
+        ])]),
+        *m.message.as_ref().unwrap()
+    );
+
+    // Another port filters out the connection.
+    assert!(dist_messages(&capture, Some(4371)).unwrap().is_empty());
+    assert_eq!(2, dist_messages(&capture, Some(4370)).unwrap().len());
+}
+
+#[test]
+fn pcap() {
+    check("dist.pcap", "10.0.0.1:51000", "10.0.0.2:4370");
+}
+
+#[test]
+fn pcapng() {
+    check("dist.pcapng", "[fd00::1]:51000", "[fd00::2]:4370");
+}
+
+#[test]
+fn invalid_capture() {
+    assert!(matches!(
+        dist_messages(b"not a capture", None),
+        Err(BertError::InvalidCapture(0))
+    ));
+}
+
+// A pcap capture of raw IPv4 packets, of one TCP segment from
+// 10.0.0.1:51000 to 10.0.0.2:4370 with `payload`.
+fn raw_capture(payload: &[u8]) -> Vec<u8> {
+    let mut capture = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+    capture.extend_from_slice(&[0; 8]);
+    capture.extend_from_slice(&65535u32.to_le_bytes());
+    capture.extend_from_slice(&101u32.to_le_bytes());
+
+    let mut packet = vec![0x45, 0];
+    packet.extend_from_slice(&(40 + payload.len() as u16).to_be_bytes());
+    packet.extend_from_slice(&[0, 0, 0, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2]);
+    packet.extend_from_slice(&[0xc7, 0x38, 0x11, 0x12, 0, 0, 0, 1, 0, 0, 0, 0]);
+    packet.extend_from_slice(&[0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0]);
+    packet.extend_from_slice(payload);
+
+    capture.extend_from_slice(&[0; 8]);
+    capture.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    capture.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    capture.extend_from_slice(&packet);
+    return capture;
+}
+
+#[test]
+fn pass_through() {
+    // A connection whose first message is a pass-through message,
+    // without a magic number before its tag.
+    let capture = raw_capture(&[0, 0, 0, 7, 112, 131, 97, 1, 131, 97, 2]);
+    let messages = dist_messages(&capture, None).unwrap();
+    assert_eq!(1, messages.len());
+    assert_eq!(
+        BertTerm::Tuple(vec![BertTerm::Int(1), BertTerm::Int(2)]),
+        *messages[0].message.as_ref().unwrap()
+    );
+}
@@ -0,0 +1,158 @@
+use std::borrow::Cow;
+use std::io::{self, Cursor, Read};
+
+use ppbert::framing::*;
+use ppbert::prelude::*;
+use ppbert::stream::StreamParser;
+
+// A reader that returns its bytes one at a time, so that every
+// term is split across reads.
+struct Trickle(Vec<u8>, usize);
+
+impl Read for Trickle {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.1 >= self.0.len() || buf.is_empty() {
+            return Ok(0);
+        }
+        buf[0] = self.0[self.1];
+        self.1 += 1;
+        return Ok(1);
+    }
+}
+
+// A reader that fails once its bytes are read.
+struct Failing;
+
+impl Read for Failing {
+    fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+        return Err(io::Error::other("read past the term"));
+    }
+}
+
+fn p<F: Framing>(bytes: &[u8], framing: F) -> Vec<Result<BertTerm>> {
+    StreamParser::new(Trickle(bytes.to_vec(), 0), framing).collect()
+}
+
+#[test]
+fn bert1() {
+    let terms = p(&[131, 97, 1, 131, 100, 0, 2, b'o', b'k'], Bert1);
+    assert_eq!(2, terms.len());
+    assert!(matches!(terms[0], Ok(BertTerm::Int(1))));
+    assert!(matches!(terms[1], Ok(BertTerm::Atom(ref s)) if s == "ok"));
+}
+
+#[test]
+fn bert2() {
+    let terms = p(&[3, 131, 97, 1, 3, 131, 97, 2], Bert2);
+    assert_eq!(2, terms.len());
+    assert!(matches!(terms[1], Ok(BertTerm::Int(2))));
+}
+
+#[test]
+fn truncated() {
+    // The second term is incomplete; offsets are counted from the
+    // start of the stream.
+    let terms = p(&[131, 97, 1, 131, 98, 0, 0], Bert1);
+    assert_eq!(2, terms.len());
+    let err = terms[1].as_ref().unwrap_err();
+    assert_eq!(&[PathStep::Term(1)], err.path());
+    assert!(matches!(
+        err.root(),
+        BertError::NotEnoughData {
+            offset: 5,
+            needed: 4,
+            available: 2,
+        }
+    ));
+}
+
+#[test]
+fn invalid_compressed_term() {
+    // <<104, 2, 97, 1>> compressed, then a reader that fails: the
+    // error is reported without reading more bytes.
+    let bytes = vec![
+        131, 80, 0, 0, 0, 4, 120, 156, 203, 96, 74, 100, 4, 0, 2, 109, 0, 205,
+    ];
+    let failing = Trickle(bytes, 0).chain(Failing);
+    let mut stream = StreamParser::new(failing, Bert1);
+    assert!(matches!(
+        stream.next().unwrap().unwrap_err().root(),
+        BertError::TruncatedCompressedTerm { .. }
+    ));
+}
+
+#[test]
+fn next_ref() {
+    let bytes = [131, 109, 0, 0, 0, 2, b'h', b'i', 131, 97, 7];
+    let mut parser = StreamParser::new(Trickle(bytes.to_vec(), 0), Bert1);
+    assert!(matches!(
+        parser.next_ref(),
+        Some(Ok(BertTermRef::Binary(ref b))) if b[..] == b"hi"[..]
+    ));
+    assert!(matches!(parser.next_ref(), Some(Ok(BertTermRef::Int(7)))));
+    assert!(parser.next_ref().is_none());
+
+    // A term read at once borrows from the buffer; a term split
+    // across reads is copied.
+    let mut parser = StreamParser::new(Cursor::new(bytes.to_vec()), Bert1);
+    assert!(matches!(
+        parser.next_ref(),
+        Some(Ok(BertTermRef::Binary(Cow::Borrowed(b"hi"))))
+    ));
+    let mut parser = StreamParser::new(Trickle(bytes.to_vec(), 0), Bert1);
+    assert!(matches!(
+        parser.next_ref(),
+        Some(Ok(BertTermRef::Binary(Cow::Owned(_))))
+    ));
+}
+
+#[test]
+fn dist_fragments() {
+    // The fragments are kept across the reads of the message.
+    let mut bytes = vec![0, 0, 0, 27, 131, 69];
+    bytes.extend_from_slice(&9u64.to_be_bytes());
+    bytes.extend_from_slice(&2u64.to_be_bytes());
+    bytes.extend_from_slice(&[1, 0x08, 0, 3, b'y', b'e', b's', 104, 2]);
+    bytes.extend_from_slice(&[0, 0, 0, 23, 131, 70]);
+    bytes.extend_from_slice(&9u64.to_be_bytes());
+    bytes.extend_from_slice(&1u64.to_be_bytes());
+    bytes.extend_from_slice(&[82, 0, 97, 3, 106]);
+    let terms = p(&bytes, Dist);
+    assert!(matches!(terms[..], [Ok(BertTerm::Tuple(ref t))] if t.len() == 2));
+}
+
+#[test]
+fn disk_log_resync() {
+    // The middle record is truncated; the warning about it is only
+    // reported once, although it is parsed again after every byte.
+    let bytes = [
+        1, 2, 3, 4, 6, 7, 8, 9, // header
+        0, 0, 0, 3, 0x62, 0x57, 0x4c, 0x41, 131, 97, 1, // 1
+        0, 0, 0, 3, 0x62, 0x57, 0x4c, 0x41, 131, // truncated
+        0, 0, 0, 3, 0x62, 0x57, 0x4c, 0x41, 131, 97, 2, // 2
+    ];
+    let mut parser = StreamParser::new(Trickle(bytes.to_vec(), 0), DiskLog);
+    let mut terms = Vec::new();
+    for term in parser.by_ref() {
+        terms.push(term.unwrap());
+    }
+    assert_eq!(vec![BertTerm::Int(1), BertTerm::Int(2)], terms);
+    assert!(matches!(
+        parser.take_warnings()[..],
+        [BertWarning::SkippedBytes {
+            start: 19,
+            end: 28,
+            ..
+        }]
+    ));
+}
+
+#[test]
+fn old_float() {
+    let mut bytes = vec![131, 99];
+    bytes.extend_from_slice(b"1.5e+00");
+    bytes.resize(33, 0);
+    bytes.extend_from_slice(&[131, 97, 1]);
+    let terms = p(&bytes, Bert1);
+    assert!(matches!(terms[..], [Ok(BertTerm::Float(f)), Ok(BertTerm::Int(1))] if f == 1.5));
+}
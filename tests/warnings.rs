@@ -0,0 +1,105 @@
+use ppbert::parser::*;
+use ppbert::prelude::*;
+
+fn warnings(bytes: &[u8]) -> Vec<BertWarning> {
+    let mut parser = BertParser::new(bytes.to_vec());
+    assert!(parser.bert1_next().unwrap().is_ok());
+    return parser.take_warnings();
+}
+
+fn strict(bytes: &[u8]) -> Result<BertTerm> {
+    let mut parser = BertParser::new(bytes.to_vec());
+    parser.set_strict(true);
+    return parser.bert1_next().unwrap();
+}
+
+#[test]
+fn suspicious_terms() {
+    // #{a => 1, a => 2}
+    let map = [
+        131, 116, 0, 0, 0, 2, 119, 1, b'a', 97, 1, 119, 1, b'a', 97, 2,
+    ];
+    assert!(matches!(
+        warnings(&map)[..],
+        [BertWarning::DuplicateMapKey { offset: 11 }]
+    ));
+
+    assert!(matches!(
+        warnings(&[131, 110, 1, 0, 42])[..],
+        [BertWarning::SmallBigInt { offset: 1 }]
+    ));
+    assert!(matches!(
+        warnings(&[131, 111, 0, 0, 0, 1, 1, 0])[..],
+        [BertWarning::NegativeZero { offset: 1 }]
+    ));
+
+    let mut float = vec![131, 99];
+    float.extend_from_slice(b"1.5");
+    float.resize(2 + 31, 0);
+    assert!(matches!(
+        warnings(&float)[..],
+        [BertWarning::OldFloat { offset: 1 }]
+    ));
+
+    assert!(matches!(
+        warnings(&[131, 115, 2, b'o', b'k'])[..],
+        [BertWarning::Latin1Atom { offset: 1 }]
+    ));
+    let mut atom = vec![131, 118, 1, 0];
+    atom.resize(4 + 256, b'a');
+    assert!(matches!(
+        warnings(&atom)[..],
+        [BertWarning::LongAtom {
+            offset: 1,
+            length: 256
+        }]
+    ));
+    assert!(warnings(&atom)[0].is_suspicious());
+
+    // Nothing to warn about.
+    let map = [
+        131, 116, 0, 0, 0, 2, 119, 1, b'a', 97, 1, 119, 1, b'b', 97, 2,
+    ];
+    assert!(warnings(&map).is_empty());
+    assert!(warnings(&[131, 110, 4, 0, 0, 0, 0, 128]).is_empty());
+    assert!(warnings(&[131, 119, 2, b'o', b'k']).is_empty());
+}
+
+#[test]
+fn nested() {
+    // {[#{[] => 1, [] => 2}]}: keys are compared by their encoding,
+    // whatever their type.
+    let bytes = [
+        131, 104, 1, 108, 0, 0, 0, 1, 116, 0, 0, 0, 2, 106, 97, 1, 106, 97, 2, 106,
+    ];
+    assert!(matches!(
+        warnings(&bytes)[..],
+        [BertWarning::DuplicateMapKey { offset: 16 }]
+    ));
+
+    // The offsets in a compressed term are in its inflated bytes.
+    let compressed = [
+        131, 80, 0, 0, 0, 4, 120, 156, 43, 102, 202, 207, 6, 0, 3, 31, 1, 80,
+    ];
+    assert!(matches!(
+        warnings(&compressed)[..],
+        [BertWarning::Latin1Atom { offset: 0 }]
+    ));
+    let err = strict(&compressed).unwrap_err();
+    assert_eq!(&[PathStep::Compressed], err.path());
+}
+
+#[test]
+fn strict_errors() {
+    let err = strict(&[131, 104, 1, 115, 2, b'o', b'k']).unwrap_err();
+    assert_eq!(&[PathStep::Tuple(0)], err.path());
+    match err.root() {
+        BertError::Strict(warning) => {
+            assert!(matches!(**warning, BertWarning::Latin1Atom { offset: 3 }))
+        }
+        e => panic!("unexpected error: {}", e),
+    }
+    assert_eq!(Some(3), err.offset());
+
+    assert!(strict(&[131, 119, 2, b'o', b'k']).is_ok());
+}
@@ -0,0 +1,132 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use ppbert::prelude::*;
+use ppbert::wrap_log::*;
+
+// The index of a log opened with `disk_log:open([{name, log},
+// {type, wrap}, {size, {1024, 4}}])`, after it wrapped to its second
+// file, which holds 3 terms, with 7 terms in the first.
+const INDEX: [u8; 26] = [
+    0, 0, 0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 0, 0, 0, 0, 3,
+];
+
+#[test]
+fn index_versions() {
+    assert_eq!(
+        WrapLogIndex {
+            current: 2,
+            items: vec![7, 3]
+        },
+        WrapLogIndex::parse(&INDEX).unwrap()
+    );
+
+    // A log that was just opened.
+    let fresh = [0, 0, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0];
+    assert_eq!(
+        WrapLogIndex {
+            current: 1,
+            items: vec![0]
+        },
+        WrapLogIndex::parse(&fresh).unwrap()
+    );
+
+    let v1 = [0, 0, 0, 0, 2, 0, 0, 0, 5, 0, 0, 0, 1];
+    assert_eq!(
+        WrapLogIndex {
+            current: 2,
+            items: vec![5, 1]
+        },
+        WrapLogIndex::parse(&v1).unwrap()
+    );
+
+    let v0 = [3, 0, 0, 0, 5, 0, 0, 0, 1, 0, 0, 0, 4];
+    assert_eq!(
+        WrapLogIndex {
+            current: 3,
+            items: vec![5, 1, 4]
+        },
+        WrapLogIndex::parse(&v0).unwrap()
+    );
+
+    // A partial entry.
+    assert!(matches!(
+        WrapLogIndex::parse(&[0, 0, 0, 0, 1, 0, 0]),
+        Err(BertError::InvalidWrapLogIndex(5))
+    ));
+}
+
+#[test]
+fn invalid_index() {
+    // An unknown version.
+    assert!(matches!(
+        WrapLogIndex::parse(&[0, 0, 0, 0, 0, 3, 0, 0, 0, 1]),
+        Err(BertError::InvalidWrapLogIndex(5))
+    ));
+
+    // No current file, or more files than disk_log allows.
+    assert!(matches!(
+        WrapLogIndex::parse(&[0, 0, 0, 0, 0, 2, 0, 0, 0, 0]),
+        Err(BertError::InvalidWrapLogIndex(6))
+    ));
+    assert!(matches!(
+        WrapLogIndex::parse(&[0, 0, 0, 0, 0, 2, 0, 0, 0xfd, 0xe8]),
+        Err(BertError::InvalidWrapLogIndex(6))
+    ));
+    assert!(matches!(
+        WrapLogIndex::parse(&[0, 0xff, 0xff, 0xff, 0xff]),
+        Err(BertError::InvalidWrapLogIndex(1))
+    ));
+    assert!(matches!(
+        WrapLogIndex::parse(&[0, 0, 0]),
+        Err(BertError::InvalidWrapLogIndex(1))
+    ));
+}
+
+#[test]
+fn chronological_order() {
+    let dir = env::temp_dir().join(format!("ppbert-wrap-log-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let base = dir.join("LOG");
+    let path = |suffix: &str| PathBuf::from(format!("{}.{}", base.display(), suffix));
+
+    // Four files, of which the second is being written to.
+    fs::write(path("idx"), INDEX).unwrap();
+    fs::write(path("siz"), [0, 0, 4, 0, 0, 0, 0, 4]).unwrap();
+    for n in ["1", "2", "3", "4"] {
+        fs::write(path(n), []).unwrap();
+    }
+
+    let base_name = base.to_str().unwrap();
+    assert!(is_wrap_log(base_name));
+    let expected = vec![path("3"), path("4"), path("1"), path("2")];
+    assert_eq!(expected, wrap_log_files(&base).unwrap());
+    assert_eq!(expected, wrap_log_files(&path("idx")).unwrap());
+
+    // The log has not wrapped yet.
+    fs::remove_file(path("siz")).unwrap();
+    fs::remove_file(path("3")).unwrap();
+    fs::remove_file(path("4")).unwrap();
+    assert_eq!(vec![path("1"), path("2")], wrap_log_files(&base).unwrap());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn invalid_size() {
+    let dir = env::temp_dir().join(format!("ppbert-wrap-size-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let base = dir.join("LOG");
+    let path = |suffix: &str| PathBuf::from(format!("{}.{}", base.display(), suffix));
+
+    // More files than disk_log allows.
+    fs::write(path("idx"), INDEX).unwrap();
+    fs::write(path("siz"), [0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 0xfd, 0xe8]).unwrap();
+    assert!(matches!(
+        wrap_log_files(&base),
+        Err(BertError::InvalidWrapLogSize)
+    ));
+
+    fs::remove_dir_all(&dir).unwrap();
+}